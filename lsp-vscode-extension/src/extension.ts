@@ -41,6 +41,10 @@ export function activate(context: ExtensionContext) {
 			{ scheme: 'file', language: 'plaintext', pattern: '**/*.bzl' },
 			{ scheme: 'file', pattern: '**/tools/build_rules/prelude_bazel' },
 		],
+		initializationOptions: {
+			// Found on the PATH if not set.
+			buildifier: workspace.getConfiguration('bazelLanguageServer').get('buildifier')
+		},
 		synchronize: {
 			// Notify the server about file changes to '.clientrc files contained in the workspace
 			// TODO Watch bazelrc
@@ -1,14 +1,22 @@
 use rustpython_parser::ast;
-use rustpython_parser::error;
 use rustpython_parser::parser;
+use rustpython_parser::token::Tok;
 use std::collections::HashMap;
-use std::fs::read_to_string;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+use tower_lsp::lsp_types as lsp;
 
 use crate::bazel::BazelResolver;
-use crate::index::function_call::FunctionCall;
+use crate::source::{Pos, Source};
+use crate::index::function_call::{Argument, FunctionCall};
 use crate::index::function_decl::FunctionDecl;
 use crate::index::indexed_document::IndexedDocument;
+use crate::index::label_string::LabelString;
+use crate::index::load_statement::LoadStatement;
+use crate::index::local_decl::LocalDecl;
+use crate::index::member_access::{MemberAccess, Receiver};
+use crate::index::range::Range;
+use crate::index::definition::{Definition, Field, RuleKind};
+use crate::index::signature::{Parameter, Signature};
 
 pub fn process_document(
 	contents: &str,
@@ -17,7 +25,8 @@ pub fn process_document(
 	let ast = parser::parse_program(contents)
 		.map_err(|err| format!("Failed to parse program: {:?}", err))?;
 	let mut indexed_document = IndexedDocument::new();
-	let docs_to_load = process_suite(&mut indexed_document, &ast.statements, bazel)?;
+	let source = Source::new(contents);
+	let docs_to_load = process_suite(&mut indexed_document, &ast.statements, bazel, source.as_ref(), None)?;
 	Ok((indexed_document, docs_to_load))
 }
 
@@ -25,10 +34,12 @@ fn process_suite(
 	index: &mut IndexedDocument,
 	suite: &ast::Suite,
 	bazel: &dyn BazelResolver,
+	source: Option<&Source>,
+	function: Option<&str>,
 ) -> Result<Vec<PathBuf>, String> {
 	let mut documents_left_to_parse = vec![];
 	for stmt in suite.iter() {
-		let docs_to_parse_in_stmt = process_statement(index, stmt, bazel)?;
+		let docs_to_parse_in_stmt = process_statement(index, stmt, bazel, source, function)?;
 		documents_left_to_parse.extend(docs_to_parse_in_stmt);
 	}
 	Ok(documents_left_to_parse)
@@ -38,49 +49,153 @@ fn process_statement(
 	index: &mut IndexedDocument,
 	statement: &ast::Statement,
 	bazel: &dyn BazelResolver,
+	source: Option<&Source>,
+	// The `def` whose body the statement is in, where assignments bind locals.
+	function: Option<&str>,
 ) -> Result<Vec<PathBuf>, String> {
 	let location = statement.location;
 	match &statement.node {
-		ast::StatementType::FunctionDef { name, body, .. } => {
+		ast::StatementType::FunctionDef { name, args, body, .. } => {
 			let location_with_def =
 				ast::Location::new(location.row(), location.column() + "def ".len());
 			index.declarations.insert(
 				name.clone(),
-				FunctionDecl::declared_in_file(name, location_with_def),
+				FunctionDecl::declared_in_file(name, location_with_def).with_definition(Definition::Function {
+					signature: process_parameters(args),
+					doc: docstring(body),
+					forwards_kwargs_to: kwargs_target(args, body),
+				}),
 			);
-			Ok(process_suite(index, body, bazel)?)
+			let named_parameters = args
+				.args
+				.iter()
+				.chain(&args.kwonlyargs)
+				.chain(match &args.vararg {
+					ast::Varargs::Named(arg) => Some(arg),
+					_ => None,
+				})
+				.chain(match &args.kwarg {
+					ast::Varargs::Named(arg) => Some(arg),
+					_ => None,
+				});
+			for parameter in named_parameters {
+				index.locals.push(LocalDecl::parameter(&parameter.arg, name, parameter.location));
+			}
+			// Defaults are evaluated where the `def` is, not in its body.
+			for default in args.defaults.iter().chain(args.kw_defaults.iter().flatten()) {
+				process_rhs_expression(default, index, bazel, source)?;
+			}
+			let (first_call_in_body, first_member_in_body) = (index.calls.len(), index.members.len());
+			let docs_to_load = process_suite(index, body, bazel, source, Some(name))?;
+			// Nested functions already claimed their own calls and members.
+			for call in &mut index.calls[first_call_in_body..] {
+				call.function.get_or_insert_with(|| name.clone());
+			}
+			for member in &mut index.members[first_member_in_body..] {
+				member.function.get_or_insert_with(|| name.clone());
+			}
+			Ok(docs_to_load)
 		}
 		ast::StatementType::Assign { targets, value } => {
+			let definition = process_definition(value);
 			for target in targets {
-				match &target.node {
-					ast::ExpressionType::Identifier { name, .. } => {
-						index.declarations.insert(
-							name.clone(),
-							FunctionDecl::declared_in_file(name, target.location),
-						);
-					}
-					_ => {}
-				};
+				bind_target(index, target, function, definition.as_ref());
+			}
+			process_rhs_expression(value, index, bazel, source)
+		}
+		ast::StatementType::AugAssign { target, value, .. } => {
+			process_rhs_expression(target, index, bazel, source)?;
+			process_rhs_expression(value, index, bazel, source)
+		}
+		ast::StatementType::Expression { expression } | ast::StatementType::Return { value: Some(expression) } => {
+			process_rhs_expression(expression, index, bazel, source)
+		}
+		ast::StatementType::If { test, body, orelse } => {
+			let mut docs_to_load = process_rhs_expression(test, index, bazel, source)?;
+			docs_to_load.extend(process_suite(index, body, bazel, source, function)?);
+			if let Some(orelse) = orelse {
+				docs_to_load.extend(process_suite(index, orelse, bazel, source, function)?);
 			}
-			process_rhs_expression(value, index, bazel)
+			Ok(docs_to_load)
 		}
-		ast::StatementType::Expression { expression } => {
-			process_rhs_expression(&expression, index, bazel)
+		ast::StatementType::For { target, iter, body, orelse, .. } => {
+			let mut docs_to_load = process_rhs_expression(iter, index, bazel, source)?;
+			bind_target(index, target, function, None);
+			docs_to_load.extend(process_suite(index, body, bazel, source, function)?);
+			if let Some(orelse) = orelse {
+				docs_to_load.extend(process_suite(index, orelse, bazel, source, function)?);
+			}
+			Ok(docs_to_load)
 		}
 		_ => Ok(vec![]),
 	}
 }
 
+/// Binds the names that `target` assigns to, as locals of `function` if it's in one, or else as declarations of the file.
+fn bind_target(index: &mut IndexedDocument, target: &ast::Expression, function: Option<&str>, definition: Option<&Definition>) {
+	match &target.node {
+		ast::ExpressionType::Identifier { name } => {
+			if let Some(function) = function {
+				index.locals.push(LocalDecl::variable(name, function, target.location));
+				return;
+			}
+			let decl = FunctionDecl::declared_in_file(name, target.location);
+			index.declarations.insert(
+				name.clone(),
+				match definition {
+					Some(definition) => decl.with_definition(definition.clone()),
+					None => decl,
+				},
+			);
+		}
+		ast::ExpressionType::Tuple { elements } | ast::ExpressionType::List { elements } => {
+			for element in elements {
+				bind_target(index, element, function, None);
+			}
+		}
+		_ => {}
+	}
+}
+
+/// The names that `target` assigns to, like `a` and `b` in `for a, b in pairs`.
+fn bound_names(target: &ast::Expression) -> Vec<&str> {
+	match &target.node {
+		ast::ExpressionType::Identifier { name } => vec![name],
+		ast::ExpressionType::Tuple { elements } | ast::ExpressionType::List { elements } => {
+			elements.iter().flat_map(bound_names).collect()
+		}
+		_ => vec![],
+	}
+}
+
+/// The positional arguments of a call, with where they start if we have the tokens to tell.
+fn call_arguments(args: &[ast::Expression], source: Option<&Source>) -> Vec<Argument> {
+	args.iter()
+		.map(|arg| Argument {
+			start: source.and_then(|source| {
+				let (row, column) = source.start_of(source.outer(arg)?.0);
+				Some(lsp::Position::new(row as u32 - 1, column as u32 - 1))
+			}),
+			name: match &arg.node {
+				ast::ExpressionType::Identifier { name } => Some(name.clone()),
+				_ => None,
+			},
+			is_starred: matches!(arg.node, ast::ExpressionType::Starred { .. }),
+		})
+		.collect()
+}
+
 fn process_rhs_expression(
 	expression: &ast::Expression,
 	index: &mut IndexedDocument,
 	bazel: &dyn BazelResolver,
+	source: Option<&Source>,
 ) -> Result<Vec<PathBuf>, String> {
 	match &expression.node {
 		ast::ExpressionType::Identifier { name, .. } => {
 			index
 				.calls
-				.push(FunctionCall::from_identifier(&name, expression.location));
+				.push(FunctionCall::from_identifier(name, expression.location));
 			Ok(vec![])
 		}
 		ast::ExpressionType::Call {
@@ -88,63 +203,444 @@ fn process_rhs_expression(
 			args,
 			keywords,
 		} => match &function.node {
-			ast::ExpressionType::Identifier { name, .. } => match name {
-				name if name == "load" => process_load(&args, &keywords, index, bazel),
-				_ => {
-					// None of these should have files to load, as they are not top-level loads.
-					process_rhs_expression(&function, index, bazel)?;
-					for arg in args {
-						process_rhs_expression(&arg, index, bazel)?;
+			ast::ExpressionType::Identifier { name, .. } if name == "load" => process_load(args, keywords, index, bazel),
+			_ => {
+				// None of these should have files to load, as they are not top-level loads.
+				match &function.node {
+					ast::ExpressionType::Identifier { name } => index
+						.calls
+						.push(FunctionCall::from_identifier(name, function.location).with_arguments(call_arguments(args, source))),
+					_ => {
+						process_rhs_expression(function, index, bazel, source)?;
 					}
-					for kwarg in keywords {
-						process_rhs_expression(&kwarg.value, index, bazel)?;
+				}
+				let first_label = index.labels.len();
+				for arg in args {
+					process_rhs_expression(arg, index, bazel, source)?;
+				}
+				if matches!(&function.node, ast::ExpressionType::Identifier { name } if name == "Label") {
+					for label in &mut index.labels[first_label..] {
+						label.in_label_call = true;
 					}
-					Ok(vec![])
 				}
-			},
-			_ => Ok(vec![]),
+				for kwarg in keywords {
+					// Names of targets aren't labels of anything yet.
+					if kwarg.name.as_deref() == Some("name") && string_constant(&kwarg.value).is_some() {
+						continue;
+					}
+					process_rhs_expression(&kwarg.value, index, bazel, source)?;
+				}
+				Ok(vec![])
+			}
 		},
+		ast::ExpressionType::String {
+			value: ast::StringGroup::Constant { value },
+		} => {
+			index.labels.extend(LabelString::from_string(value, expression.location));
+			Ok(vec![])
+		}
+		ast::ExpressionType::List { elements }
+		| ast::ExpressionType::Tuple { elements }
+		| ast::ExpressionType::Set { elements }
+		| ast::ExpressionType::Slice { elements }
+		| ast::ExpressionType::BoolOp { values: elements, .. }
+		| ast::ExpressionType::Compare { vals: elements, .. } => {
+			for element in elements {
+				process_rhs_expression(element, index, bazel, source)?;
+			}
+			Ok(vec![])
+		}
+		ast::ExpressionType::Dict { elements } => {
+			for (key, value) in elements {
+				if let Some(key) = key {
+					process_rhs_expression(key, index, bazel, source)?;
+				}
+				process_rhs_expression(value, index, bazel, source)?;
+			}
+			Ok(vec![])
+		}
+		ast::ExpressionType::Comprehension { kind, generators } => {
+			let bound = generators.iter().flat_map(|generator| bound_names(&generator.target)).collect::<Vec<_>>();
+			// The names the comprehension binds are only its own, except in the first iterable, which is evaluated outside.
+			let forget_bound = |index: &mut IndexedDocument, first_call: usize| {
+				let inner = index.calls.split_off(first_call);
+				index.calls.extend(inner.into_iter().filter(|call| !bound.contains(&call.function_name.as_str())));
+			};
+			let first_call = index.calls.len();
+			match kind.as_ref() {
+				ast::ComprehensionKind::GeneratorExpression { element }
+				| ast::ComprehensionKind::List { element }
+				| ast::ComprehensionKind::Set { element } => {
+					process_rhs_expression(element, index, bazel, source)?;
+				}
+				ast::ComprehensionKind::Dict { key, value } => {
+					process_rhs_expression(key, index, bazel, source)?;
+					process_rhs_expression(value, index, bazel, source)?;
+				}
+			}
+			forget_bound(index, first_call);
+			for (i, generator) in generators.iter().enumerate() {
+				let before_iter = index.calls.len();
+				process_rhs_expression(&generator.iter, index, bazel, source)?;
+				let first_call = if i == 0 { index.calls.len() } else { before_iter };
+				for condition in &generator.ifs {
+					process_rhs_expression(condition, index, bazel, source)?;
+				}
+				forget_bound(index, first_call);
+			}
+			Ok(vec![])
+		}
+		ast::ExpressionType::Attribute { value, name } => {
+			if let Some(receiver) = receiver_of(value) {
+				index
+					.members
+					.push(MemberAccess::after_dot(receiver, name, expression.location));
+			}
+			process_rhs_expression(value, index, bazel, source)
+		}
+		ast::ExpressionType::Subscript { a, b } | ast::ExpressionType::Binop { a, b, .. } => {
+			process_rhs_expression(a, index, bazel, source)?;
+			process_rhs_expression(b, index, bazel, source)
+		}
+		ast::ExpressionType::IfExpression { test, body, orelse } => {
+			process_rhs_expression(body, index, bazel, source)?;
+			process_rhs_expression(test, index, bazel, source)?;
+			process_rhs_expression(orelse, index, bazel, source)
+		}
+		ast::ExpressionType::Unop { a: operand, .. } | ast::ExpressionType::Starred { value: operand } => {
+			process_rhs_expression(operand, index, bazel, source)
+		}
 		_ => Ok(vec![]),
 	}
 }
 
-fn process_string_literal(expr: &ast::Expression) -> String {
+/// What `value.member` accesses the member of, if we can tell statically.
+fn receiver_of(value: &ast::Expression) -> Option<Receiver> {
+	match &value.node {
+		ast::ExpressionType::Identifier { name } => Some(Receiver::Name(name.clone())),
+		ast::ExpressionType::Attribute { value, name } => match receiver_of(value)? {
+			Receiver::Name(receiver) => Some(Receiver::Name(format!("{}.{}", receiver, name))),
+			Receiver::Provider(_) => None,
+		},
+		ast::ExpressionType::Subscript { b, .. } => match &b.node {
+			ast::ExpressionType::Identifier { name } => Some(Receiver::Provider(name.clone())),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+fn process_parameters(args: &ast::Parameters) -> Signature {
+	let mut parameters = vec![];
+	// Defaults belong to the last positional parameters.
+	let first_with_default = args.args.len() - args.defaults.len();
+	for (i, arg) in args.args.iter().enumerate() {
+		let default = i
+			.checked_sub(first_with_default)
+			.and_then(|i| args.defaults.get(i));
+		parameters.push(parameter_with_default(&arg.arg, default));
+	}
+	match &args.vararg {
+		ast::Varargs::Named(arg) => parameters.push(Parameter::new(&format!("*{}", arg.arg))),
+		ast::Varargs::Unnamed => parameters.push(Parameter::new("*")),
+		ast::Varargs::None => {}
+	}
+	for (arg, default) in args.kwonlyargs.iter().zip(&args.kw_defaults) {
+		parameters.push(parameter_with_default(&arg.arg, default.as_ref()));
+	}
+	if let ast::Varargs::Named(arg) = &args.kwarg {
+		parameters.push(Parameter::new(&format!("**{}", arg.arg)));
+	}
+	Signature { parameters }
+}
+
+fn parameter_with_default(name: &str, default: Option<&ast::Expression>) -> Parameter {
+	Parameter {
+		mandatory: default.is_none(),
+		default: default.map(expression_to_string),
+		..Parameter::new(name)
+	}
+}
+
+fn docstring(body: &ast::Suite) -> Option<String> {
+	match &body.first()?.node {
+		ast::StatementType::Expression { expression } => string_constant(expression),
+		_ => None,
+	}
+}
+
+/// The function that a `def` passes its `**kwargs` on to, if any.
+fn kwargs_target(args: &ast::Parameters, body: &ast::Suite) -> Option<String> {
+	let kwargs = match &args.kwarg {
+		ast::Varargs::Named(arg) => &arg.arg,
+		_ => return None,
+	};
+	body.iter().find_map(|statement| {
+		let expression = match &statement.node {
+			ast::StatementType::Expression { expression } => expression,
+			ast::StatementType::Assign { value, .. } => value,
+			ast::StatementType::Return { value: Some(value) } => value,
+			_ => return None,
+		};
+		match &expression.node {
+			ast::ExpressionType::Call { function, keywords, .. } => keywords
+				.iter()
+				.any(|keyword| {
+					keyword.name.is_none()
+						&& matches!(&keyword.value.node, ast::ExpressionType::Identifier { name } if name == kwargs)
+				})
+				.then(|| expression_to_string(function)),
+			_ => None,
+		}
+	})
+}
+
+/// Recognizes the builtins that define rules, providers and the like,
+/// e.g. `my_rule = rule(implementation = _impl, attrs = {...})`.
+fn process_definition(value: &ast::Expression) -> Option<Definition> {
+	let (function, args, keywords) = match &value.node {
+		ast::ExpressionType::Call { function, args, keywords } => match &function.node {
+			ast::ExpressionType::Identifier { name } => (name, args, keywords),
+			_ => return None,
+		},
+		_ => return None,
+	};
+	let keyword = |name: &str| {
+		keywords
+			.iter()
+			.find(|keyword| keyword.name.as_deref() == Some(name))
+			.map(|keyword| &keyword.value)
+	};
+	let doc = keyword("doc").and_then(string_constant);
+	if let Some(kind) = RuleKind::from_function(function) {
+		let mut parameters = vec![];
+		if kind.is_instantiated() {
+			// They all have a name, without having to declare it.
+			parameters.push(Parameter {
+				attr_type: Some("string".to_string()),
+				mandatory: true,
+				..Parameter::new("name")
+			});
+		}
+		if let Some(ast::ExpressionType::Dict { elements }) = keyword("attrs").map(|attrs| &attrs.node) {
+			for (key, value) in elements {
+				if let Some(name) = key.as_ref().and_then(string_constant) {
+					parameters.push(process_attr(&name, value));
+				}
+			}
+		}
+		let implementation = match keyword("implementation").map(|implementation| &implementation.node) {
+			Some(ast::ExpressionType::Identifier { name }) => Some(name.clone()),
+			_ => None,
+		};
+		return Some(Definition::Rule {
+			kind,
+			attrs: Signature { parameters },
+			doc,
+			implementation,
+		});
+	}
+	match function.as_str() {
+		"provider" => Some(Definition::Provider {
+			fields: keyword("fields").map(process_fields).unwrap_or_default(),
+			// The doc can also be the first positional argument.
+			doc: doc.or_else(|| args.first().and_then(string_constant)),
+		}),
+		"module_extension" => {
+			let tag_classes = match keyword("tag_classes").map(|tag_classes| &tag_classes.node) {
+				Some(ast::ExpressionType::Dict { elements }) => elements
+					.iter()
+					.filter_map(|(key, _)| key.as_ref().and_then(string_constant))
+					.collect(),
+				_ => vec![],
+			};
+			Some(Definition::ModuleExtension { tag_classes, doc })
+		}
+		"struct" => Some(Definition::Struct {
+			fields: keywords
+				.iter()
+				.filter_map(|keyword| {
+					let name = keyword.name.as_ref()?;
+					Some(Field {
+						name: name.clone(),
+						doc: None,
+						range: keyword_range(name, &keyword.value),
+					})
+				})
+				.collect(),
+		}),
+		_ => None,
+	}
+}
+
+/// Reads `fields = ["a", "b"]` or `fields = {"a": "doc of a"}`.
+fn process_fields(fields: &ast::Expression) -> Vec<Field> {
+	match &fields.node {
+		ast::ExpressionType::List { elements } => elements
+			.iter()
+			.filter_map(|element| {
+				string_constant(element).map(|name| Field {
+					range: string_range(&name, element),
+					name,
+					doc: None,
+				})
+			})
+			.collect(),
+		ast::ExpressionType::Dict { elements } => elements
+			.iter()
+			.filter_map(|(key, value)| {
+				let key = key.as_ref()?;
+				string_constant(key).map(|name| Field {
+					range: string_range(&name, key),
+					name,
+					doc: string_constant(value),
+				})
+			})
+			.collect(),
+		_ => vec![],
+	}
+}
+
+/// Reads `attr.<type>(mandatory = ..., default = ..., doc = ...)`.
+fn process_attr(name: &str, value: &ast::Expression) -> Parameter {
+	let mut parameter = Parameter::new(name);
+	if let ast::ExpressionType::Call { function, keywords, .. } = &value.node {
+		if let ast::ExpressionType::Attribute { value, name } = &function.node {
+			if let ast::ExpressionType::Identifier { name: module } = &value.node {
+				if module == "attr" {
+					parameter.attr_type = Some(name.clone());
+				}
+			}
+		}
+		for keyword in keywords {
+			match keyword.name.as_deref() {
+				Some("mandatory") => parameter.mandatory = keyword.value.node == ast::ExpressionType::True,
+				Some("default") => parameter.default = Some(expression_to_string(&keyword.value)),
+				Some("doc") => parameter.doc = string_constant(&keyword.value),
+				_ => {}
+			}
+		}
+	}
+	parameter
+}
+
+/// The range of the contents of a string literal, which the parser locates right after its opening quote.
+fn string_range(contents: &str, literal: &ast::Expression) -> Range {
+	Range::from_identifier(contents, literal.location)
+}
+
+/// Keywords have no location of their own, so we assume buildifier's `name = value` formatting.
+fn keyword_range(name: &str, value: &ast::Expression) -> Range {
+	let location = value.location;
+	// Strings are located after their opening quote.
+	let quote = if string_constant(value).is_some() { 1 } else { 0 };
+	match location.column().checked_sub(name.len() + " = ".len() + quote) {
+		Some(column) if column > 0 => Range::from_identifier(name, ast::Location::new(location.row(), column)),
+		_ => Range::from_identifier(name, location),
+	}
+}
+
+fn string_constant(expr: &ast::Expression) -> Option<String> {
+	match &expr.node {
+		ast::ExpressionType::String {
+			value: ast::StringGroup::Constant { value },
+		} => Some(value.clone()),
+		_ => None,
+	}
+}
+
+/// Renders simple expressions, such as default values, back to source.
+fn expression_to_string(expr: &ast::Expression) -> String {
+	let join = |exprs: &[ast::Expression]| exprs.iter().map(expression_to_string).collect::<Vec<_>>().join(", ");
+	match &expr.node {
+		ast::ExpressionType::String { .. } => match string_constant(expr) {
+			Some(value) => format!("{:?}", value),
+			None => "\"...\"".to_string(),
+		},
+		ast::ExpressionType::Number { value } => match value {
+			ast::Number::Integer { value } => value.to_string(),
+			ast::Number::Float { value } => value.to_string(),
+			ast::Number::Complex { .. } => "...".to_string(),
+		},
+		ast::ExpressionType::True => "True".to_string(),
+		ast::ExpressionType::False => "False".to_string(),
+		ast::ExpressionType::None => "None".to_string(),
+		ast::ExpressionType::Identifier { name } => name.clone(),
+		ast::ExpressionType::Attribute { value, name } => format!("{}.{}", expression_to_string(value), name),
+		ast::ExpressionType::List { elements } => format!("[{}]", join(elements)),
+		ast::ExpressionType::Tuple { elements } => format!("({})", join(elements)),
+		ast::ExpressionType::Dict { elements } => {
+			let elements = elements
+				.iter()
+				.map(|(key, value)| match key {
+					Some(key) => format!("{}: {}", expression_to_string(key), expression_to_string(value)),
+					None => format!("**{}", expression_to_string(value)),
+				})
+				.collect::<Vec<_>>();
+			format!("{{{}}}", elements.join(", "))
+		}
+		ast::ExpressionType::Call { function, args, keywords } => {
+			let mut arguments = args.iter().map(expression_to_string).collect::<Vec<_>>();
+			arguments.extend(keywords.iter().map(|keyword| match &keyword.name {
+				Some(name) => format!("{} = {}", name, expression_to_string(&keyword.value)),
+				None => format!("**{}", expression_to_string(&keyword.value)),
+			}));
+			format!("{}({})", expression_to_string(function), arguments.join(", "))
+		}
+		_ => "...".to_string(),
+	}
+}
+
+fn process_string_literal(expr: &ast::Expression) -> Result<String, String> {
 	if let ast::ExpressionType::String { value } = &expr.node {
 		if let ast::StringGroup::Constant { value } = value {
-			value.clone()
+			Ok(value.clone())
 		} else {
-			panic!("Loaded symbol with non-constant {:?}", expr)
+			Err(format!("Loaded symbol with non-constant {:?}", expr))
 		}
 	} else {
-		panic!("Couldn't understand loaded symbol {:?}", expr)
+		Err(format!("Couldn't understand loaded symbol {:?}", expr))
 	}
 }
 
 fn process_load(
-	args: &Vec<ast::Expression>,
-	kwargs: &Vec<ast::Keyword>,
+	args: &[ast::Expression],
+	kwargs: &[ast::Keyword],
 	index: &mut IndexedDocument,
 	bazel: &dyn BazelResolver,
 ) -> Result<Vec<PathBuf>, String> {
-	let source = process_string_literal(&args[0]);
-	let maybe_source_as_path = bazel.resolve_bazel_path(&source);
+	let source_arg = args.first().ok_or("Load without a file to load")?;
+	let source = process_string_literal(source_arg)?;
+	let mut symbols = vec![];
+	let mut symbol_ranges = vec![];
+	for arg in &args[1..] {
+		let name = process_string_literal(arg)?;
+		symbol_ranges.push((name.clone(), Range::from_identifier(&name, arg.location)));
+		symbols.push((name.clone(), name));
+	}
+	for kwarg in kwargs {
+		let imported_name = kwarg
+			.name
+			.as_ref()
+			.cloned()
+			.ok_or("Kwarg without a name")?;
+		let real_name = process_string_literal(&kwarg.value)?;
+		symbol_ranges.push((imported_name.clone(), keyword_range(&imported_name, &kwarg.value)));
+		symbol_ranges.push((imported_name.clone(), Range::from_identifier(&real_name, kwarg.value.location)));
+		symbols.push((imported_name, real_name));
+	}
+	let imported_names = symbols.iter().map(|(imported_name, _)| imported_name.clone()).collect();
+	// We still record loads we can't resolve, so that we can point them out,
+	// but they don't bring any declarations along.
+	let maybe_source_as_path = bazel.resolve_bazel_path(&source).ok();
+	index.loads.push(
+		LoadStatement::new(&source, source_arg.location, maybe_source_as_path.clone(), imported_names)
+			.with_symbol_ranges(symbol_ranges),
+	);
 	match maybe_source_as_path {
-		Ok(source_as_path) => {
+		Some(source_as_path) => {
 			let mut declarations = HashMap::new();
-			for arg in &args[1..args.len()] {
-				let name = process_string_literal(&arg);
-				declarations.insert(
-					name.clone(),
-					FunctionDecl::loaded(&name, &name, &source_as_path),
-				);
-			}
-			for kwarg in kwargs {
-				let imported_name = kwarg
-					.name
-					.as_ref()
-					.cloned()
-					.ok_or_else(|| "Kwarg without a name")?;
-				let real_name = process_string_literal(&kwarg.value);
+			for (imported_name, real_name) in symbols {
 				declarations.insert(
 					imported_name.clone(),
 					FunctionDecl::loaded(&real_name, &imported_name, &source_as_path),
@@ -153,10 +649,291 @@ fn process_load(
 			index.declarations.extend(declarations);
 			Ok(vec![source_as_path])
 		}
-		Err(s) => Err(s)
+		None => Ok(vec![]),
+	}
+}
+
+/// The last token of `statement`, which ends the last statement of its body if it has one.
+fn statement_end(source: &Source, statement: &ast::Statement) -> Option<usize> {
+	let end_of = |body: &ast::Suite| statement_end(source, body.last()?);
+	match &statement.node {
+		ast::StatementType::FunctionDef { body, .. } => end_of(body),
+		ast::StatementType::If { body, orelse, .. } | ast::StatementType::For { body, orelse, .. } => match orelse {
+			Some(orelse) if !orelse.is_empty() => end_of(orelse),
+			_ => end_of(body),
+		},
+		_ => source.end_of_line(source.statement_start(statement)?).checked_sub(1),
+	}
+}
+
+/// Folds the rows from `first` to `last`, counted from 1 like the lexer does, if that hides anything.
+fn fold(ranges: &mut Vec<lsp::FoldingRange>, first: usize, last: usize, kind: Option<lsp::FoldingRangeKind>) {
+	if last > first {
+		ranges.push(lsp::FoldingRange {
+			start_line: first as u32 - 1,
+			start_character: None,
+			end_line: last as u32 - 1,
+			end_character: None,
+			kind,
+			collapsed_text: None,
+		});
+	}
+}
+
+/// Folds the brackets of `expr` if it's a call, list or dict, leaving the closing bracket visible on its own line.
+fn fold_brackets(source: &Source, expr: &ast::Expression, ranges: &mut Vec<lsp::FoldingRange>) -> Option<()> {
+	let open = source.token_at(&expr.location)?;
+	let close = *source.closing.get(&open)?;
+	let last = match source.tokens[close - 1].end.0 < source.tokens[close].start.0 {
+		true => source.tokens[close].start.0 - 1,
+		false => source.tokens[close].start.0,
+	};
+	fold(ranges, source.tokens[open].start.0, last, None);
+	Some(())
+}
+
+fn fold_expression(source: &Source, expr: &ast::Expression, ranges: &mut Vec<lsp::FoldingRange>) {
+	let mut fold_all = |exprs: &mut dyn Iterator<Item = &ast::Expression>| {
+		for expr in exprs {
+			fold_expression(source, expr, ranges);
+		}
+	};
+	match &expr.node {
+		ast::ExpressionType::Call { function, args, keywords } => {
+			fold_all(&mut std::iter::once(function.as_ref()).chain(args).chain(keywords.iter().map(|kwarg| &kwarg.value)));
+			fold_brackets(source, expr, ranges);
+		}
+		ast::ExpressionType::List { elements } | ast::ExpressionType::Tuple { elements } => {
+			fold_all(&mut elements.iter());
+			fold_brackets(source, expr, ranges);
+		}
+		ast::ExpressionType::Dict { elements } => {
+			fold_all(&mut elements.iter().flat_map(|(key, value)| key.iter().chain(Some(value))));
+			fold_brackets(source, expr, ranges);
+		}
+		ast::ExpressionType::Comprehension { .. } => {
+			fold_brackets(source, expr, ranges);
+		}
+		ast::ExpressionType::String { .. } => {
+			if let Some((first, last, _)) = source.outer(expr) {
+				fold(ranges, source.tokens[first].start.0, source.tokens[last].end.0, None);
+			}
+		}
+		ast::ExpressionType::Binop { a, b, .. } | ast::ExpressionType::Subscript { a, b } => fold_all(&mut vec![a.as_ref(), b.as_ref()].into_iter()),
+		ast::ExpressionType::BoolOp { values: operands, .. } | ast::ExpressionType::Compare { vals: operands, .. } => fold_all(&mut operands.iter()),
+		ast::ExpressionType::IfExpression { test, body, orelse } => fold_all(&mut vec![test.as_ref(), body.as_ref(), orelse.as_ref()].into_iter()),
+		ast::ExpressionType::Attribute { value: operand, .. }
+		| ast::ExpressionType::Unop { a: operand, .. }
+		| ast::ExpressionType::Lambda { body: operand, .. }
+		| ast::ExpressionType::Starred { value: operand } => fold_expression(source, operand, ranges),
+		_ => {}
 	}
 }
 
+fn fold_suite(source: &Source, suite: &[ast::Statement], ranges: &mut Vec<lsp::FoldingRange>) -> Option<()> {
+	for statement in suite {
+		let first = source.statement_start(statement)?;
+		let row = source.tokens[first].start.0;
+		let last_row = |statement: &ast::Statement| Some(source.tokens[statement_end(source, statement)?].end.0);
+		match &statement.node {
+			ast::StatementType::FunctionDef { args, body, .. } => {
+				fold(ranges, row, last_row(statement)?, None);
+				for default in args.defaults.iter().chain(args.kw_defaults.iter().flatten()) {
+					fold_expression(source, default, ranges);
+				}
+				fold_suite(source, body, ranges)?;
+			}
+			ast::StatementType::If { test, body, orelse } => {
+				fold_expression(source, test, ranges);
+				fold(ranges, row, last_row(body.last()?)?, None);
+				fold_suite(source, body, ranges)?;
+				if let Some(orelse) = orelse.as_ref().filter(|orelse| !orelse.is_empty()) {
+					// An `elif` folds itself, as the `if` it stands for.
+					let orelse_first = source.statement_start(&orelse[0])?;
+					if !source.is(orelse_first, &Tok::Elif) {
+						let else_token = (first..orelse_first).rev().find(|&index| source.is(index, &Tok::Else))?;
+						fold(ranges, source.tokens[else_token].start.0, last_row(orelse.last()?)?, None);
+					}
+					fold_suite(source, orelse, ranges)?;
+				}
+			}
+			ast::StatementType::For { iter, body, .. } => {
+				fold_expression(source, iter, ranges);
+				fold(ranges, row, last_row(statement)?, None);
+				fold_suite(source, body, ranges)?;
+			}
+			ast::StatementType::Expression { expression } => match &expression.node {
+				ast::ExpressionType::String { .. } => fold(ranges, row, last_row(statement)?, Some(lsp::FoldingRangeKind::Comment)),
+				_ => fold_expression(source, expression, ranges),
+			},
+			ast::StatementType::Assign { value, .. } => fold_expression(source, value, ranges),
+			ast::StatementType::AugAssign { value, .. } => fold_expression(source, value, ranges),
+			ast::StatementType::Return { value: Some(value) } => fold_expression(source, value, ranges),
+			_ => {}
+		}
+	}
+	Some(())
+}
+
+/// What can be folded in `contents`: blocks, docstrings, comments, and calls, lists and dicts over several lines.
+pub fn folding_ranges(contents: &str) -> Option<Vec<lsp::FoldingRange>> {
+	let program = parser::parse_program(contents).ok()?;
+	let source = Source::new(contents)?;
+	let mut ranges = vec![];
+	fold_suite(&source, &program.statements, &mut ranges)?;
+	let mut comments = source.comments.iter().filter(|comment| comment.own_line).peekable();
+	while let Some(comment) = comments.next() {
+		let mut last = comment.pos.0;
+		while let Some(next) = comments.next_if(|next| next.pos.0 == last + 1) {
+			last = next.pos.0;
+		}
+		fold(&mut ranges, comment.pos.0, last, Some(lsp::FoldingRangeKind::Comment));
+	}
+	ranges.sort_by_key(|range| (range.start_line, range.end_line));
+	Some(ranges)
+}
+
+type Span = (Pos, Pos);
+
+fn contains(span: Span, pos: Pos) -> bool {
+	span.0 <= pos && pos <= span.1
+}
+
+fn span(source: &Source, first: usize, last: usize) -> Span {
+	(source.start_of(first), source.tokens[last].end)
+}
+
+/// Adds the spans of `expr` and of what's in it that contain `pos`, from the outside in.
+fn select_in_expression(source: &Source, expr: &ast::Expression, pos: Pos, spans: &mut Vec<Span>) -> Option<()> {
+	let (first, last, _) = source.outer(expr)?;
+	if !contains(span(source, first, last), pos) {
+		return Some(());
+	}
+	spans.push(span(source, first, last));
+	let mut select_all = |exprs: &mut dyn Iterator<Item = &ast::Expression>| {
+		for expr in exprs {
+			select_in_expression(source, expr, pos, spans)?;
+		}
+		Some(())
+	};
+	match &expr.node {
+		ast::ExpressionType::Call { function, args, keywords } => {
+			select_all(&mut std::iter::once(function.as_ref()).chain(args))?;
+			for keyword in keywords {
+				// A keyword argument starts at its name and its `=`, or at `**`.
+				let (value_first, value_last, _) = source.outer(&keyword.value)?;
+				let keyword_first = value_first.checked_sub(if keyword.name.is_some() { 2 } else { 1 })?;
+				if contains(span(source, keyword_first, value_last), pos) {
+					spans.push(span(source, keyword_first, value_last));
+				}
+				select_in_expression(source, &keyword.value, pos, spans)?;
+			}
+			Some(())
+		}
+		ast::ExpressionType::Dict { elements } => {
+			for (key, value) in elements {
+				let (value_first, value_last, _) = source.outer(value)?;
+				let entry_first = match key {
+					Some(key) => source.outer(key)?.0,
+					None => value_first.checked_sub(1)?,
+				};
+				if contains(span(source, entry_first, value_last), pos) {
+					spans.push(span(source, entry_first, value_last));
+				}
+				if let Some(key) = key {
+					select_in_expression(source, key, pos, spans)?;
+				}
+				select_in_expression(source, value, pos, spans)?;
+			}
+			Some(())
+		}
+		ast::ExpressionType::List { elements } | ast::ExpressionType::Tuple { elements } => select_all(&mut elements.iter()),
+		ast::ExpressionType::Binop { a, b, .. } | ast::ExpressionType::Subscript { a, b } => select_all(&mut vec![a.as_ref(), b.as_ref()].into_iter()),
+		ast::ExpressionType::BoolOp { values: operands, .. } | ast::ExpressionType::Compare { vals: operands, .. } => select_all(&mut operands.iter()),
+		ast::ExpressionType::IfExpression { test, body, orelse } => select_all(&mut vec![body.as_ref(), test.as_ref(), orelse.as_ref()].into_iter()),
+		ast::ExpressionType::Attribute { value: operand, .. }
+		| ast::ExpressionType::Unop { a: operand, .. }
+		| ast::ExpressionType::Lambda { body: operand, .. }
+		| ast::ExpressionType::Starred { value: operand } => select_in_expression(source, operand, pos, spans),
+		_ => Some(()),
+	}
+}
+
+/// Adds the spans of the statement of `suite` that contains `pos`, and of what's in it, from the outside in.
+fn select_in_suite(source: &Source, suite: &[ast::Statement], pos: Pos, spans: &mut Vec<Span>) -> Option<()> {
+	for statement in suite {
+		let statement_span = span(source, source.statement_start(statement)?, statement_end(source, statement)?);
+		if !contains(statement_span, pos) {
+			continue;
+		}
+		spans.push(statement_span);
+		return match &statement.node {
+			ast::StatementType::FunctionDef { args, body, .. } => {
+				for default in args.defaults.iter().chain(args.kw_defaults.iter().flatten()) {
+					select_in_expression(source, default, pos, spans)?;
+				}
+				select_in_suite(source, body, pos, spans)
+			}
+			ast::StatementType::If { test, body, orelse } => {
+				select_in_expression(source, test, pos, spans)?;
+				select_in_suite(source, body, pos, spans)?;
+				select_in_suite(source, orelse.as_deref().unwrap_or_default(), pos, spans)
+			}
+			ast::StatementType::For { target, iter, body, .. } => {
+				select_in_expression(source, target, pos, spans)?;
+				select_in_expression(source, iter, pos, spans)?;
+				select_in_suite(source, body, pos, spans)
+			}
+			ast::StatementType::Expression { expression: value } | ast::StatementType::Return { value: Some(value) } => {
+				select_in_expression(source, value, pos, spans)
+			}
+			ast::StatementType::Assign { targets, value } => {
+				for target in targets {
+					select_in_expression(source, target, pos, spans)?;
+				}
+				select_in_expression(source, value, pos, spans)
+			}
+			ast::StatementType::AugAssign { target, value, .. } => {
+				select_in_expression(source, target, pos, spans)?;
+				select_in_expression(source, value, pos, spans)
+			}
+			_ => Some(()),
+		};
+	}
+	Some(())
+}
+
+/// What to select at each of `positions`, growing from the token there to the argument, the call,
+/// the statement and the `def`s around it.
+pub fn selection_ranges(contents: &str, positions: &[lsp::Position]) -> Option<Vec<lsp::SelectionRange>> {
+	let program = parser::parse_program(contents).ok()?;
+	let source = Source::new(contents)?;
+	let as_lsp_position = |(row, column): Pos| lsp::Position::new(row as u32 - 1, column as u32 - 1);
+	let selections = positions.iter().map(|position| {
+		let pos = (position.line as usize + 1, position.character as usize + 1);
+		let mut spans = vec![];
+		select_in_suite(&source, &program.statements, pos, &mut spans);
+		// The cursor can also be right after the token, as long as it's not at the start of the next.
+		let tokens = source.tokens.iter().enumerate().filter(|(_, token)| !matches!(token.tok, Tok::Newline | Tok::Indent | Tok::Dedent));
+		let token = tokens.clone().find(|(index, token)| source.start_of(*index) <= pos && pos < token.end).or_else(|| tokens.clone().find(|(_, token)| token.end == pos));
+		spans.extend(token.map(|(index, _)| span(&source, index, index)));
+		spans.dedup();
+		spans
+			.into_iter()
+			.fold(None, |parent, (start, end)| {
+				Some(lsp::SelectionRange {
+					range: lsp::Range::new(as_lsp_position(start), as_lsp_position(end)),
+					parent: parent.map(Box::new),
+				})
+			})
+			.unwrap_or(lsp::SelectionRange {
+				range: lsp::Range::new(*position, *position),
+				parent: None,
+			})
+	});
+	Some(selections.collect())
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -164,9 +941,6 @@ mod test {
 
 	use trim_margin::MarginTrimmable;
 	use rustpython_parser::ast;
-    use tempfile;
-
-	use std::io::{self, Write};
 
     struct MockBazelResolver {
 		files_in_workspace: HashMap<String, String>
@@ -179,7 +953,7 @@ mod test {
 		}
 	}
 	impl BazelResolver for MockBazelResolver {
-	    fn resolve_bazel_path(&self, path: &String) -> Result<PathBuf, String> {
+	    fn resolve_bazel_path(&self, path: &str) -> Result<PathBuf, String> {
 			let sanitized_path = &self.sanitize_starlark_label(path);
 			if self.files_in_workspace.contains_key(sanitized_path) {
 		        Ok(PathBuf::from(sanitized_path))
@@ -207,21 +981,39 @@ mod test {
 	}
 
 	fn declaration_in_file(name: &str, location: ast::Location) -> FunctionDecl	{
-		FunctionDecl::declared_in_file(&name.to_string(), location)
+		FunctionDecl::declared_in_file(name, location)
 	}
 
 	fn declaration_loaded(name: &str, imported_name: Option<&str>, path: &str) -> FunctionDecl	{
-		FunctionDecl::loaded(&name.to_string(), &imported_name.unwrap_or(name).to_string(), &path.into())
+		FunctionDecl::loaded(name, imported_name.unwrap_or(name), &PathBuf::from(path))
+	}
+
+	fn symbol_range(symbol: &str, text: &str, location: ast::Location) -> (String, Range) {
+		(symbol.to_string(), Range::from_identifier(text, location))
 	}
 
 	fn call(name: &str, location: ast::Location) -> FunctionCall {
-		FunctionCall::from_identifier(&name.to_string(), location)
+		FunctionCall::from_identifier(name, location)
+	}
+
+	fn call_in_function(name: &str, function: &str, location: ast::Location) -> FunctionCall {
+		let mut call = call(name, location);
+		call.function = Some(function.to_string());
+		call
+	}
+
+	fn argument(line: u32, character: u32, name: Option<&str>) -> Argument {
+		Argument {
+			start: Some(lsp::Position::new(line, character)),
+			name: name.map(|name| name.to_string()),
+			is_starred: false,
+		}
 	}
 
 	#[test]
 	fn test_single_assignment() {
 		let file = "a = 3";
-		let (indexed_document, paths_to_load) = run_parse(&file, hashmap!{});
+		let (indexed_document, paths_to_load) = run_parse(file, hashmap!{});
 
 		let expected_indexed_document = IndexedDocument::finished(
 			hashmap! {
@@ -244,10 +1036,10 @@ mod test {
 
 		let expected_indexed_document = IndexedDocument::finished(
 			hashmap! {
-			    "hello".to_string() => declaration_in_file("hello", location(0, 4))
+			    "hello".to_string() => declaration_in_file("hello", location(0, 4)).with_definition(function(vec![], None))
 			},
 			vec![
-				call("call_to_other_function", location(1, 2)),
+				call_in_function("call_to_other_function", "hello", location(1, 2)),
 				call("hello", location(2, 0)),
 			],
 		);
@@ -275,7 +1067,7 @@ mod test {
 			},
 			vec![
 				call("loaded_func", location(4, 0)),
-				call("loaded_and_renamed_func", location(5, 0)),
+				call("loaded_and_renamed_func", location(5, 0)).with_arguments(vec![argument(5, 24, None), argument(5, 27, None)]),
 			],
 		);
 		assert_eq!(indexed_document.declarations, expected_indexed_document.declarations);
@@ -298,10 +1090,10 @@ mod test {
 		let expected_indexed_document = IndexedDocument::finished(
 			hashmap! {
 			  "loaded_func".to_string() => declaration_loaded("loaded_func", None, "some_file.bzl"),
-			  "defined_func".to_string() => declaration_in_file("defined_func", location(1, 4)),
+			  "defined_func".to_string() => declaration_in_file("defined_func", location(1, 4)).with_definition(function(vec![], None)),
 			},
 			vec![
-				call("loaded_func", location(2, 2)),
+				call_in_function("loaded_func", "defined_func", location(2, 2)),
 				call("defined_func", location(3, 0)),
 			],
 		);
@@ -311,8 +1103,226 @@ mod test {
 		assert_eq!(paths_to_load, expected_paths_to_load);
 	}
 	
-	// This test fails for now because we don't correctly parse symbols inside functions, such as `a` and `b`.
-	#[test] #[ignore]
+	#[test]
+	fn test_unresolved_load_is_recorded_without_declarations() {
+		let file = trimmed("
+		|load('//:some_file.bzl', 'loaded_func')
+		|load('@missing//:defs.bzl', 'missing_func', renamed = 'other_func')
+		");
+		let (indexed_document, paths_to_load) = run_parse(&file, hashmap!{"some_file.bzl" => ""});
+
+		assert_eq!(
+			indexed_document.loads,
+			vec![
+				LoadStatement::new("//:some_file.bzl", location(0, 6), Some(PathBuf::from("some_file.bzl")), vec!["loaded_func".to_string()])
+					.with_symbol_ranges(vec![symbol_range("loaded_func", "loaded_func", location(0, 26))]),
+				LoadStatement::new("@missing//:defs.bzl", location(1, 6), None, vec!["missing_func".to_string(), "renamed".to_string()])
+					.with_symbol_ranges(vec![
+						symbol_range("missing_func", "missing_func", location(1, 29)),
+						symbol_range("renamed", "renamed", location(1, 44)),
+						symbol_range("renamed", "other_func", location(1, 55)),
+					]),
+			]
+		);
+		assert_eq!(indexed_document.declarations.keys().collect::<Vec<_>>(), vec!["loaded_func"]);
+		assert_eq!(paths_to_load, vec![PathBuf::from("some_file.bzl")]);
+
+		// Loads the user is still typing are errors, not crashes.
+		let bazel_resolver = MockBazelResolver::new(hashmap!{});
+		assert!(super::process_document("load()", &bazel_resolver).is_err());
+		assert!(super::process_document("load('//:some_file.bzl', loaded_func)", &bazel_resolver).is_err());
+	}
+
+	fn function(parameters: Vec<Parameter>, doc: Option<&str>) -> Definition {
+		Definition::Function {
+			signature: Signature { parameters },
+			doc: doc.map(|doc| doc.to_string()),
+			forwards_kwargs_to: None,
+		}
+	}
+
+	fn attr(name: &str, attr_type: &str, mandatory: bool, default: Option<&str>) -> Parameter {
+		Parameter {
+			attr_type: Some(attr_type.to_string()),
+			mandatory,
+			default: default.map(|default| default.to_string()),
+			..Parameter::new(name)
+		}
+	}
+
+	fn definition(indexed_document: &IndexedDocument, name: &str) -> Option<Definition> {
+		indexed_document.declarations[name].definition.clone()
+	}
+
+	#[test]
+	fn test_def_signature() {
+		let file = trimmed("
+		|def my_macro(name, srcs = [], *args, visibility = None, **kwargs):
+		|  '''Wraps my_rule.'''
+		|  pass
+		");
+		let (indexed_document, _) = run_parse(&file, hashmap!{});
+
+		let mut name = Parameter::new("name");
+		name.mandatory = true;
+		let mut srcs = Parameter::new("srcs");
+		srcs.default = Some("[]".to_string());
+		let mut visibility = Parameter::new("visibility");
+		visibility.default = Some("None".to_string());
+		assert_eq!(
+			definition(&indexed_document, "my_macro"),
+			Some(function(
+				vec![name, srcs, Parameter::new("*args"), visibility, Parameter::new("**kwargs")],
+				Some("Wraps my_rule."),
+			))
+		);
+	}
+
+	#[test]
+	fn test_kwargs_forwarding() {
+		let file = trimmed("
+		|def my_macro(name, **kwargs):
+		|  _private(name)
+		|  native.cc_library(name = name + '_lib', **kwargs)
+		|def other_macro(name, **kwargs):
+		|  my_macro(name, kwargs = kwargs)
+		");
+		let (indexed_document, _) = run_parse(&file, hashmap!{});
+
+		let forwards_kwargs_to = |name: &str| match definition(&indexed_document, name) {
+			Some(Definition::Function { forwards_kwargs_to, .. }) => forwards_kwargs_to,
+			other => panic!("Not a function: {:?}", other),
+		};
+		assert_eq!(forwards_kwargs_to("my_macro"), Some("native.cc_library".to_string()));
+		assert_eq!(forwards_kwargs_to("other_macro"), None);
+	}
+
+	#[test]
+	fn test_rule_definition() {
+		let file = trimmed("
+		|my_rule = rule(
+		|  implementation = _impl,
+		|  attrs = {
+		|    'srcs': attr.label_list(mandatory = True, allow_files = ['.rs'], doc = 'Sources.'),
+		|    'edition': attr.string(default = '2018'),
+		|    'crate_features': attr.string_list(default = []),
+		|  },
+		|  doc = 'Builds a crate.',
+		|)
+		|my_aspect = aspect(implementation = _impl, attrs = {'_tool': attr.label()})
+		|not_a_rule = struct(attrs = {'srcs': attr.label_list()})
+		");
+		let (indexed_document, _) = run_parse(&file, hashmap!{});
+
+		let mut srcs = attr("srcs", "label_list", true, None);
+		srcs.doc = Some("Sources.".to_string());
+		assert_eq!(
+			definition(&indexed_document, "my_rule"),
+			Some(Definition::Rule {
+				kind: RuleKind::Rule,
+				attrs: Signature {
+					parameters: vec![
+						attr("name", "string", true, None),
+						srcs,
+						attr("edition", "string", false, Some("\"2018\"")),
+						attr("crate_features", "string_list", false, Some("[]")),
+					],
+				},
+				doc: Some("Builds a crate.".to_string()),
+				implementation: Some("_impl".to_string()),
+			})
+		);
+		// Aspects aren't instantiated by name.
+		assert_eq!(
+			definition(&indexed_document, "my_aspect"),
+			Some(Definition::Rule {
+				kind: RuleKind::Aspect,
+				attrs: Signature {
+					parameters: vec![attr("_tool", "label", false, None)],
+				},
+				doc: None,
+				implementation: Some("_impl".to_string()),
+			})
+		);
+		assert!(matches!(definition(&indexed_document, "not_a_rule"), Some(Definition::Struct { .. })));
+	}
+
+	#[test]
+	fn test_provider_and_module_extension_definitions() {
+		let file = trimmed("
+		|MyInfo = provider('Info about my rule.', fields = {'files': 'The files.', 'count': 'How many.'})
+		|OtherInfo = provider(fields = ['a'])
+		|my_ext = module_extension(implementation = _impl, tag_classes = {'install': _install}, doc = 'Installs.')
+		");
+		let (indexed_document, _) = run_parse(&file, hashmap!{});
+
+		let field = |name: &str, doc: Option<&str>, location| Field {
+			name: name.to_string(),
+			doc: doc.map(|doc| doc.to_string()),
+			range: Range::from_identifier(name, location),
+		};
+		assert_eq!(
+			definition(&indexed_document, "MyInfo"),
+			Some(Definition::Provider {
+				fields: vec![
+					field("files", Some("The files."), location(0, 52)),
+					field("count", Some("How many."), location(0, 75)),
+				],
+				doc: Some("Info about my rule.".to_string()),
+			})
+		);
+		assert_eq!(
+			definition(&indexed_document, "OtherInfo"),
+			Some(Definition::Provider {
+				fields: vec![field("a", None, location(1, 32))],
+				doc: None,
+			})
+		);
+		assert_eq!(
+			definition(&indexed_document, "my_ext"),
+			Some(Definition::ModuleExtension {
+				tag_classes: vec!["install".to_string()],
+				doc: Some("Installs.".to_string()),
+			})
+		);
+	}
+
+	#[test]
+	fn test_structs_and_member_accesses() {
+		let file = trimmed("
+		|foo = struct(bar = _bar, baz = 1)
+		|f(foo.bar, dep[MyInfo].files, ctx.attr.deps)
+		");
+		let (indexed_document, _) = run_parse(&file, hashmap!{});
+
+		let field = |name: &str, location| Field {
+			name: name.to_string(),
+			doc: None,
+			range: Range::from_identifier(name, location),
+		};
+		assert_eq!(
+			definition(&indexed_document, "foo"),
+			Some(Definition::Struct {
+				fields: vec![field("bar", location(0, 13)), field("baz", location(0, 25))],
+			})
+		);
+		let name = |name: &str| Receiver::Name(name.to_string());
+		assert_eq!(
+			indexed_document.members,
+			vec![
+				MemberAccess::after_dot(name("foo"), "bar", location(1, 5)),
+				MemberAccess::after_dot(Receiver::Provider("MyInfo".to_string()), "files", location(1, 22)),
+				MemberAccess::after_dot(name("ctx.attr"), "deps", location(1, 38)),
+				MemberAccess::after_dot(name("ctx"), "attr", location(1, 33)),
+			]
+		);
+		assert_eq!(
+			indexed_document.calls.iter().map(|call| call.function_name.as_str()).collect::<Vec<_>>(),
+			vec!["struct", "_bar", "f", "foo", "dep", "MyInfo", "ctx"]
+		);
+	}
+
+	#[test]
 	fn test_function_declaration() {
 		let file = trimmed("
         |def func(a, b):
@@ -323,18 +1333,114 @@ mod test {
 
 		let expected_indexed_document = IndexedDocument::finished(
 			hashmap! {
-			  "func".to_string() => declaration_in_file("func", location(0, 4))
+			  "func".to_string() => declaration_in_file("func", location(0, 4)).with_definition(function(
+				  vec![super::parameter_with_default("a", None), super::parameter_with_default("b", None)],
+				  None,
+			  ))
 			},
 			vec![
-				call("func", location(2, 0)),
+				call_in_function("a", "func", location(1, 2)),
+				call_in_function("b", "func", location(1, 6)),
+				call("func", location(2, 0)).with_arguments(vec![argument(2, 5, None), argument(2, 10, None)]),
 				call("c", location(2, 5)),
 				call("d", location(2, 10)),
-				call("a", location(1, 2)),
-				call("b", location(1, 6)),
 			],
 		);
 		assert_eq!(indexed_document.declarations, expected_indexed_document.declarations);
 		assert_eq!(indexed_document.calls, expected_indexed_document.calls);
 		assert!(paths_to_load.is_empty());
 	}
+
+	#[test]
+	fn test_names_in_control_flow_and_operators() {
+		let file = trimmed("
+		|def f(x, y = default()):
+		|  for a, b in pairs(x):
+		|    if a or not b:
+		|      return one(a) + two(b)
+		|  total = 0
+		|  total += three(y) if x == y else 4
+		|  return [five(c) for c in x if six(c)]
+		|squares = {n: n * n for n in seven()}
+		");
+		let (indexed_document, _) = run_parse(&file, hashmap!{});
+
+		let names = |function: Option<&str>| {
+			indexed_document
+				.calls
+				.iter()
+				.filter(|call| call.function.as_deref() == function)
+				.map(|call| call.function_name.as_str())
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(
+			names(Some("f")),
+			vec!["pairs", "x", "a", "b", "one", "a", "two", "b", "total", "three", "y", "x", "y", "five", "x", "six"]
+		);
+		assert_eq!(names(None), vec!["default", "seven"]);
+		assert!(["a", "b", "total"].iter().all(|name| indexed_document.is_local(name, Some("f"))));
+		assert!(!indexed_document.is_local("c", Some("f")));
+	}
+
+	const BLOCKS: &str = r#"# Helpers for
+# libraries.
+load("//:defs.bzl", "x")
+
+def library(name, srcs = []):
+    """Makes a library.
+
+    Really.
+    """
+    if srcs:
+        x(
+            name = name,
+            srcs = srcs,
+        )
+    else:
+        x(name = name)
+
+my_rule = rule(
+    attrs = {
+        "a": attr.string(),
+    },
+)
+"#;
+
+	#[test]
+	fn test_folding_ranges() {
+		let comment = Some(lsp::FoldingRangeKind::Comment);
+		let folded = folding_ranges(BLOCKS)
+			.unwrap()
+			.into_iter()
+			.map(|range| (range.start_line, range.end_line, range.kind))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			folded,
+			vec![
+				(0, 1, comment.clone()),
+				(4, 15, None),
+				(5, 8, comment),
+				(9, 13, None),
+				(10, 12, None),
+				(14, 15, None),
+				(17, 20, None),
+				(18, 19, None),
+			]
+		);
+	}
+
+	#[test]
+	fn test_selection_ranges() {
+		let selections = selection_ranges(BLOCKS, &[lsp::Position::new(11, 20), lsp::Position::new(3, 0)]).unwrap();
+		let mut ranges = vec![];
+		let mut selection = Some(&selections[0]);
+		while let Some(current) = selection {
+			let range = current.range;
+			ranges.push((range.start.line, range.start.character, range.end.line, range.end.character));
+			selection = current.parent.as_deref();
+		}
+		assert_eq!(ranges, vec![(11, 19, 11, 23), (11, 12, 11, 23), (10, 8, 13, 9), (9, 4, 15, 22), (4, 0, 15, 22)]);
+		assert_eq!(selections[1].range, lsp::Range::new(lsp::Position::new(3, 0), lsp::Position::new(3, 0)));
+		assert_eq!(selections[1].parent, None);
+	}
 }
@@ -1,9 +1,18 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::build_language::NativeRules;
+
+/// Files whose presence marks the root of a Bazel workspace.
+const WORKSPACE_MARKERS: [&str; 3] = ["WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"];
+
+/// Files, besides `*.bzl`, that Bazel reads Starlark from.
+const STARLARK_FILE_NAMES: [&str; 5] = ["BUILD", "BUILD.bazel", "WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"];
 
 #[derive(Debug, Default, Clone)]
-struct BazelExecutable {
+pub struct BazelExecutable {
   executable: PathBuf,
 }
 impl BazelExecutable {
@@ -11,48 +20,273 @@ impl BazelExecutable {
         BazelExecutable{executable: PathBuf::from(executable)}
 	}
 
-	fn call_bazel(&self, command: Vec<String>, cwd: &PathBuf) -> Result<String, String> {
+	/// Finds the executable called `name` in the directories of the PATH.
+	pub fn from_path(name: &str) -> Option<Self> {
+		let path = std::env::var_os("PATH")?;
+		std::env::split_paths(&path)
+			.map(|dir| dir.join(name))
+			.find(|executable| executable.is_file())
+			.map(|executable| BazelExecutable { executable })
+	}
+
+	/// Runs the executable with `input` as its stdin, and returns what it output whatever its exit status,
+	/// for tools that report findings through it.
+	pub fn call_with_input(&self, command: Vec<String>, cwd: &Path, input: &[u8]) -> Result<std::process::Output, String> {
+		let mut child = std::process::Command::new(&self.executable)
+			.args(&command)
+			.current_dir(cwd)
+			.stdin(std::process::Stdio::piped())
+			.stdout(std::process::Stdio::piped())
+			.stderr(std::process::Stdio::piped())
+			.spawn()
+			.map_err(|err| format!("Error running command {:?}: {:?}", command, err))?;
+		// We write from another thread, so that a child that outputs before it read all of its input can't block us.
+		let mut stdin = child.stdin.take();
+		let input = input.to_vec();
+		let writer = std::thread::spawn(move || {
+			use std::io::Write;
+			stdin.as_mut().map(|stdin| stdin.write_all(&input))
+		});
+		let output = child
+			.wait_with_output()
+			.map_err(|err| format!("Error running command {:?}: {:?}", command, err))?;
+		let _ = writer.join();
+		Ok(output)
+	}
+
+	/// Starts the executable with its output piped, for commands whose output we pass on as it comes.
+	pub fn spawn(&self, command: Vec<String>, cwd: &Path) -> Result<std::process::Child, String> {
 		std::process::Command::new(&self.executable)
 			.args(&command)
 			.current_dir(cwd)
-			.output()
+			.stdin(std::process::Stdio::null())
+			.stdout(std::process::Stdio::piped())
+			.stderr(std::process::Stdio::piped())
+			.spawn()
 			.map_err(|err| format!("Error running Bazel command {:?}: {:?}", command, err))
+	}
+
+	fn call_bazel(&self, command: Vec<String>, cwd: &Path) -> Result<String, String> {
+		self.call_bazel_binary(command, cwd)
 			.and_then(|out| {
-				String::from_utf8(out.stdout)
+				String::from_utf8(out)
 					.map_err(|err| format!("Error parsing output: {:?}", err))
 			})
 			.map(|out| out.trim().to_string())
 	}
 
-    fn get_exec_root(&self, source_root: &PathBuf) -> Result<PathBuf, String> {
-		let execroot: String = self.call_bazel(
-			vec!["info".to_string(), "execution_root".to_string()],
+	fn call_bazel_binary(&self, command: Vec<String>, cwd: &Path) -> Result<Vec<u8>, String> {
+		std::process::Command::new(&self.executable)
+			.args(&command)
+			.current_dir(cwd)
+			.output()
+			.map_err(|err| format!("Error running Bazel command {:?}: {:?}", command, err))
+			.and_then(|out| {
+				if out.status.success() {
+					Ok(out)
+				} else {
+					Err(format!(
+						"Bazel command {:?} failed: {}",
+						command,
+						String::from_utf8_lossy(&out.stderr).trim()
+					))
+				}
+			})
+			.map(|out| out.stdout)
+	}
+
+    fn get_output_base(&self, source_root: &Path) -> Result<PathBuf, String> {
+		let output_base: String = self.call_bazel(
+			vec!["info".to_string(), "output_base".to_string()],
 			source_root,
 		)?;
-		Ok(PathBuf::from(
-			execroot.replace("execroot/__main__", "external"),
-		))
+		Ok(PathBuf::from(output_base.trim()))
+	}
+
+	fn get_native_rules(&self, source_root: &Path) -> Result<NativeRules, String> {
+		let proto = self.call_bazel_binary(
+			vec!["info".to_string(), "build-language".to_string()],
+			source_root,
+		)?;
+		NativeRules::from_build_language(&proto)
+	}
+}
+
+/// Where the external repositories of the workspace with this output base are,
+/// which is what we resolve "@repo//" labels against.
+fn external_dir(output_base: &Path) -> PathBuf {
+	output_base.join("external")
+}
+
+fn has_external_dir(output_base: &Path) -> bool {
+	external_dir(output_base).is_dir()
+}
+
+/// Finds the output base of a workspace without running Bazel.
+///
+/// We first follow the `bazel-<workspace>` and `bazel-out` convenience symlinks,
+/// and then fall back to Bazel's default output user root.
+fn locate_output_base(workspace: &Path) -> Option<PathBuf> {
+	output_base_from_symlinks(workspace)
+		.or_else(|| output_base_from_user_root(workspace))
+		.filter(|output_base| has_external_dir(output_base))
+}
+
+fn output_base_from_symlinks(workspace: &Path) -> Option<PathBuf> {
+	let workspace_name = workspace.file_name()?.to_string_lossy();
+	// bazel-<workspace> points to <output_base>/execroot/<name>,
+	// bazel-out points to <output_base>/execroot/<name>/bazel-out.
+	let candidates = vec![
+		(workspace.join(format!("bazel-{}", workspace_name)), 2),
+		(workspace.join("bazel-out"), 3),
+	];
+	candidates.into_iter().find_map(|(link, depth)| {
+		let target = std::fs::read_link(&link).ok()?;
+		// Links that point somewhere else than we expect don't lead to an output base.
+		target.ancestors().nth(depth).map(PathBuf::from).filter(|output_base| has_external_dir(output_base))
+	})
+}
+
+fn output_base_from_user_root(workspace: &Path) -> Option<PathBuf> {
+	let user = std::env::var("USER").ok()?;
+	let user_root = if cfg!(target_os = "macos") {
+		PathBuf::from("/private/var/tmp")
+	} else {
+		dirs::home_dir()?.join(".cache").join("bazel")
+	};
+	let workspace = workspace.canonicalize().ok()?;
+	let digest = md5::compute(workspace.to_string_lossy().as_bytes());
+	Some(user_root.join(format!("_bazel_{}", user)).join(format!("{:x}", digest)))
+}
+
+fn is_workspace_root(dir: &Path) -> bool {
+	WORKSPACE_MARKERS.iter().any(|marker| dir.join(marker).is_file())
+}
+
+pub fn is_build_file(path: &Path) -> bool {
+	matches!(path.file_name().and_then(|name| name.to_str()), Some("BUILD") | Some("BUILD.bazel"))
+}
+
+pub fn is_starlark_file(path: &Path) -> bool {
+	match path.file_name().and_then(|name| name.to_str()) {
+		Some(name) => name.ends_with(".bzl") || STARLARK_FILE_NAMES.contains(&name),
+		None => false,
+	}
+}
+
+/// Lists every directory under `folder`, including `folder` itself,
+/// skipping the ones in the `.bazelignore` of the workspaces we go through.
+///
+/// We don't descend into hidden directories or into the `bazel-*` convenience symlinks,
+/// which would otherwise make us pick up copies of the workspace from the output base.
+fn walk_dirs(folder: &Path) -> Vec<PathBuf> {
+	let mut dirs = vec![];
+	let mut ignored = vec![];
+	let mut pending = vec![folder.to_path_buf()];
+	while let Some(dir) = pending.pop() {
+		// Parents come out of `pending` before their children, so their ignores apply to those.
+		if is_workspace_root(&dir) {
+			ignored.extend(read_bazelignore(&dir));
+		}
+		let entries = match std::fs::read_dir(&dir) {
+			Ok(entries) => entries,
+			Err(_) => continue,
+		};
+		for entry in entries.filter_map(|entry| entry.ok()) {
+			let name = entry.file_name().to_string_lossy().to_string();
+			let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+			let path = entry.path();
+			if is_dir && !name.starts_with('.') && !name.starts_with("bazel-") && !ignored.contains(&path) {
+				pending.push(path);
+			}
+		}
+		dirs.push(dir);
+	}
+	dirs.sort();
+	dirs
+}
+
+/// Finds every Bazel workspace under `folder`, including `folder` itself.
+fn discover_workspaces(folder: &Path) -> Vec<PathBuf> {
+	walk_dirs(folder)
+		.into_iter()
+		.filter(|dir| is_workspace_root(dir))
+		.collect()
+}
+
+/// Reads the directories that Bazel should ignore, relative to the workspace root.
+fn read_bazelignore(root: &Path) -> Vec<PathBuf> {
+	std::fs::read_to_string(root.join(".bazelignore"))
+		.map(|contents| {
+			contents
+				.lines()
+				.map(|line| line.trim())
+				.filter(|line| !line.is_empty() && !line.starts_with('#'))
+				.map(|line| root.join(line.trim_end_matches('/')))
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+/// Finds every BUILD, WORKSPACE and `.bzl` file in the workspace at `root`, honoring `.bazelignore`.
+pub fn starlark_files(root: &Path) -> Vec<PathBuf> {
+	let mut files = walk_dirs(root)
+		.into_iter()
+		.filter_map(|dir| std::fs::read_dir(dir).ok())
+		.flat_map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()))
+		.filter(|path| path.is_file() && is_starlark_file(path))
+		.collect::<Vec<_>>();
+	files.sort();
+	files
+}
+
+/// How we found the external repositories of the workspace.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExternalRepos {
+	/// Bazel ran, and told us where the output base is.
+	FromBazel,
+	/// Bazel couldn't run, but we found an output base on disk.
+	FromOutputBase(PathBuf),
+	/// Neither worked, so we can only navigate the main repository.
+	Unavailable(String),
+}
+
+impl ExternalRepos {
+	pub fn unavailable_features(&self) -> Vec<&'static str> {
+		match self {
+			ExternalRepos::FromBazel => vec![],
+			ExternalRepos::FromOutputBase(_) => vec![
+				"Refreshing external repositories",
+				"Attribute completion for native rules",
+				"Undefined name warnings in BUILD files",
+			],
+			ExternalRepos::Unavailable(_) => vec![
+				"Refreshing external repositories",
+				"Goto definition into external repositories",
+				"Attribute completion for native rules",
+				"Undefined name warnings in BUILD files",
+			],
+		}
 	}
 }
 
 pub trait BazelResolver {
-	fn resolve_bazel_path(&self, path: &String) -> Result<PathBuf, String>;
-	fn sanitize_starlark_label(&self, label: &String) -> String {
+	fn resolve_bazel_path(&self, path: &str) -> Result<PathBuf, String>;
+	fn sanitize_starlark_label(&self, label: &str) -> String {
 		let label = label
 			.trim()
-			.replace("@", "")
+			.replace('@', "")
 			.replace("//:", "/")
 			.replace("//", "/")
-			.replace(":", "/");
-		if label.starts_with("/") {
-			label.strip_prefix("/").unwrap().to_string()
-		} else {
-			label
+			.replace(':', "/");
+		match label.strip_prefix('/') {
+			Some(stripped) => stripped.to_string(),
+			None => label,
 		}
 	}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BazelWorkspace {
 	inner: Arc<Mutex<InnerBazel>>,
 }
@@ -64,95 +298,231 @@ impl BazelWorkspace {
 		}
 	}
 
-	pub fn maybe_change_source_root(&self, new_root: &PathBuf) -> Result<(), String> {
+	/// Sets up the workspace at `workspace` without running Bazel,
+	/// with the external repositories of the output base we find on disk, if any.
+	pub fn locate_workspace(&self, workspace: &Path) -> Result<(), String> {
 		let inner = &mut *self
 			.inner
 			.lock()
 			.map_err(|err| format!("Error locking Bazel {:?}", err))?;
-		inner.maybe_change_source_root(new_root)
+		inner.locate_workspace(workspace);
+		Ok(())
 	}
-	pub fn update_workspace(&self, workspace: &PathBuf) -> Result<(), String> {
+
+	/// Asks Bazel where the external repositories are, unless we already did.
+	///
+	/// Returns None if we did. This can take a while, so we don't hold the lock while Bazel runs.
+	pub fn query_bazel(&self) -> Result<Option<ExternalRepos>, String> {
+		let (bazel_exe, workspace_root) = {
+			let inner = &mut *self
+				.inner
+				.lock()
+				.map_err(|err| format!("Error locking Bazel {:?}", err))?;
+			if inner.queried_bazel {
+				return Ok(None);
+			}
+			inner.queried_bazel = true;
+			let workspace_root = inner
+				.workspace_root
+				.clone()
+				.ok_or("Trying to query Bazel, but it is not initialized!")?;
+			(inner.bazel_exe.clone(), workspace_root)
+		};
+		let output_base = bazel_exe.get_output_base(&workspace_root);
 		let inner = &mut *self
 			.inner
 			.lock()
 			.map_err(|err| format!("Error locking Bazel {:?}", err))?;
-		inner.update_workspace(workspace)
+		Ok(Some(inner.set_output_base(output_base)))
 	}
-}
 
-impl BazelResolver for BazelWorkspace { 
-	fn resolve_bazel_path(&self, path: &String) -> Result<PathBuf, String> {
-		let inner = &*self
-			.inner
+	/// Asks Bazel for the rules it provides natively, and keeps them.
+	///
+	/// This can take a while, so we don't hold the lock while Bazel runs.
+	pub fn load_native_rules(&self) -> Result<(), String> {
+		let (bazel_exe, workspace_root) = {
+			let inner = self
+				.inner
+				.lock()
+				.map_err(|err| format!("Error locking Bazel {:?}", err))?;
+			let workspace_root = inner
+				.workspace_root
+				.clone()
+				.ok_or("Trying to load native rules, but Bazel is not initialized!")?;
+			(inner.bazel_exe.clone(), workspace_root)
+		};
+		let native_rules = bazel_exe.get_native_rules(&workspace_root)?;
+		self.inner
 			.lock()
-			.map_err(|err| format!("Error locking Bazel {:?}", err))?;
-		inner.resolve_bazel_path(path)
+			.map_err(|err| format!("Error locking Bazel {:?}", err))?
+			.native_rules = Some(Arc::new(native_rules));
+		Ok(())
+	}
+
+	/// Starts Bazel with `command` in the workspace, without waiting for it to finish.
+	pub fn spawn_bazel(&self, command: Vec<String>) -> Result<std::process::Child, String> {
+		let (bazel_exe, workspace_root) = {
+			let inner = self
+				.inner
+				.lock()
+				.map_err(|err| format!("Error locking Bazel {:?}", err))?;
+			let workspace_root = inner
+				.workspace_root
+				.clone()
+				.ok_or("Trying to run Bazel, but it is not initialized!")?;
+			(inner.bazel_exe.clone(), workspace_root)
+		};
+		bazel_exe.spawn(command, &workspace_root)
+	}
+
+	pub fn workspace_root(&self) -> Option<PathBuf> {
+		self.inner.lock().ok()?.workspace_root.clone()
+	}
+
+	pub fn output_base(&self) -> Option<PathBuf> {
+		self.inner.lock().ok()?.output_base.clone()
+	}
+
+	pub fn native_rules(&self) -> Option<Arc<NativeRules>> {
+		self.inner.lock().ok()?.native_rules.clone()
+	}
+
+	/// Resolves labels as `file` sees them, with `//` meaning the repository that contains it.
+	pub fn resolver_for(&self, file: &Path) -> FileResolver {
+		match self.inner.lock() {
+			Ok(inner) => inner.resolver_for(Some(file)),
+			Err(_) => FileResolver::default(),
+		}
+	}
+
+	/// The label of `file`, like `//pkg:defs.bzl`, or `@repo//pkg:defs.bzl` in an external repository.
+	pub fn label_for(&self, file: &Path) -> Option<String> {
+		self.inner.lock().ok()?.label_for(file)
+	}
+
+	/// Whether `file` belongs to this workspace, either as a source file,
+	/// or as a file in one of its external repositories.
+	fn contains(&self, file: &Path) -> Containment {
+		match self.inner.lock() {
+			Ok(inner) => inner.contains(file),
+			Err(_) => Containment::No,
+		}
 	}
 }
 
-#[derive(Debug)]
-struct InnerBazel {
-	exec_root: Option<PathBuf>,
-	workspace_root: Option<PathBuf>,
-	source_root: Option<PathBuf>, // Where to resolve "//:" references against
-	bazel_exe: BazelExecutable,
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Containment {
+	No,
+	External,
+	// We keep the depth of the workspace root, so that nested workspaces win over their parents.
+	Source(usize),
 }
 
-impl InnerBazel {
-	pub fn new() -> Self {
-		InnerBazel {
-			exec_root: None,
-			workspace_root: None,
-			source_root: None,
-			bazel_exe: BazelExecutable::new("bazelisk"),
+/// All the Bazel workspaces the server knows about, keyed by their root.
+///
+/// A client may open several workspace folders, and each folder may contain
+/// more than one Bazel workspace, so we keep one `BazelWorkspace` per root.
+#[derive(Debug, Default)]
+pub struct BazelWorkspaces {
+	workspaces: RwLock<HashMap<PathBuf, BazelWorkspace>>,
+}
+
+impl BazelWorkspaces {
+	/// Registers every Bazel workspace under `folder`, and returns their roots.
+	///
+	/// If the folder contains no workspace markers at all,
+	/// we register the folder itself, so that we can still navigate it.
+	/// We don't run Bazel here: see `BazelWorkspace::query_bazel`.
+	pub fn add_folder(&self, folder: &Path) -> Result<Vec<PathBuf>, String> {
+		let mut roots = discover_workspaces(folder);
+		if roots.is_empty() {
+			roots.push(folder.to_path_buf());
 		}
+		for root in &roots {
+			let workspace = BazelWorkspace::new();
+			workspace.locate_workspace(root)?;
+			self.workspaces
+				.write()
+				.map_err(|err| format!("Error locking workspaces {:?}", err))?
+				.insert(root.clone(), workspace);
+		}
+		Ok(roots)
 	}
 
-	pub	fn maybe_change_source_root(&mut self, file_path: &PathBuf) -> Result<(), String> {
-		if self.source_root.is_none() || self.workspace_root.is_none() {
-			Err(format!("Trying to change root to {:?}, but Bazel is not initialized!", file_path))
-		} else {
-			let exec_root = self.exec_root.as_ref().unwrap();
-			let workspace_root = self.workspace_root.as_ref().unwrap();
-			if file_path.starts_with(&exec_root) {
-				let ancestors = file_path.ancestors();
-				let mut new_root = None;
-				for ancestor in ancestors.take_while(|anc| anc != exec_root) {
-					new_root = Some(ancestor);
-				}
-				self.source_root = new_root.map(|nr| PathBuf::from(nr));
-			// }
-			// if file_path.ancestors().find(|anc| anc == &exec_root).is_some() {
-			// 	self.source_root = self.exec_root.clone();
-			} else if file_path.ancestors().find(|anc| anc == &workspace_root).is_some() {
-				self.source_root = self.workspace_root.clone();
-			}
-			Ok(())
+	pub fn roots(&self) -> Vec<PathBuf> {
+		let mut roots = self
+			.workspaces
+			.read()
+			.map(|workspaces| workspaces.keys().cloned().collect::<Vec<_>>())
+			.unwrap_or_default();
+		roots.sort();
+		roots
+	}
+
+	/// Forgets every Bazel workspace under `folder`, and returns their roots.
+	pub fn remove_folder(&self, folder: &Path) -> Result<Vec<PathBuf>, String> {
+		let workspaces = &mut *self
+			.workspaces
+			.write()
+			.map_err(|err| format!("Error locking workspaces {:?}", err))?;
+		let removed = workspaces
+			.keys()
+			.filter(|root| root.starts_with(folder))
+			.cloned()
+			.collect::<Vec<_>>();
+		for root in &removed {
+			workspaces.remove(root);
 		}
+		Ok(removed)
 	}
 
-	pub fn update_workspace(&mut self, workspace: &PathBuf) -> Result<(), String> {
-		self.bazel_exe.get_exec_root(workspace).map(|root| {
-			self.source_root = Some(workspace.clone());
-			self.workspace_root = self.source_root.clone();
-			self.exec_root = Some(root);
-			()
-		})
+	/// Finds the workspace that `file` should be resolved against.
+	///
+	/// Source files go to the innermost workspace that contains them.
+	/// Files in external repositories go to a workspace that fetched them.
+	pub fn workspace_for(&self, file: &Path) -> Option<BazelWorkspace> {
+		let workspaces = self.workspaces.read().ok()?;
+		let mut candidates = workspaces
+			.iter()
+			.map(|(root, workspace)| (workspace.contains(file), root, workspace))
+			.filter(|(containment, _, _)| *containment != Containment::No)
+			.collect::<Vec<_>>();
+		// Sort by containment, then by root, so that the choice is deterministic.
+		candidates.sort_by(|(c1, r1, _), (c2, r2, _)| c2.cmp(c1).then(r1.cmp(r2)));
+		candidates.first().map(|(_, _, workspace)| (*workspace).clone())
+	}
+}
+
+impl BazelResolver for BazelWorkspace {
+	fn resolve_bazel_path(&self, path: &str) -> Result<PathBuf, String> {
+		let inner = &*self
+			.inner
+			.lock()
+			.map_err(|err| format!("Error locking Bazel {:?}", err))?;
+		inner.resolver_for(None).resolve_bazel_path(path)
 	}
 }
 
-impl BazelResolver for InnerBazel {
-	fn resolve_bazel_path(&self, path: &String) -> Result<PathBuf, String> {
+/// Resolves labels from one file of a workspace.
+#[derive(Debug, Default)]
+pub struct FileResolver {
+	// Where to resolve "//" references against: the repository that contains the file.
+	repository_root: Option<PathBuf>,
+	external_dir: Option<PathBuf>,
+}
+
+impl BazelResolver for FileResolver {
+	fn resolve_bazel_path(&self, path: &str) -> Result<PathBuf, String> {
 		let resolved_path = self.sanitize_starlark_label(path);
 		let maybe_root = if path.starts_with("//") {
-			self.source_root.as_ref()
+			self.repository_root.clone()
 		} else {
-			self.exec_root.as_ref()
+			self.external_dir.clone()
 		};
 		maybe_root
-			.ok_or_else(|| format!("Empty source_root!"))
+			.ok_or_else(|| format!("No root to resolve {} against!", path))
 			.and_then(|root| {
-				let mut res = root.clone();
+				let mut res = root;
 				res.push(PathBuf::from(resolved_path));
 				if res.is_file() {
 					Ok(res)
@@ -163,3 +533,263 @@ impl BazelResolver for InnerBazel {
 			})
 	}
 }
+
+#[derive(Debug)]
+struct InnerBazel {
+	output_base: Option<PathBuf>,
+	workspace_root: Option<PathBuf>,
+	bazel_exe: BazelExecutable,
+	// None until Bazel tells us about them, if it ever does.
+	native_rules: Option<Arc<NativeRules>>,
+	// Whether we asked Bazel about the output base yet, which we only do once.
+	queried_bazel: bool,
+}
+
+impl InnerBazel {
+	pub fn new() -> Self {
+		InnerBazel {
+			output_base: None,
+			workspace_root: None,
+			bazel_exe: BazelExecutable::new("bazelisk"),
+			native_rules: None,
+			queried_bazel: false,
+		}
+	}
+
+	/// Files in an external repository resolve `//` against that repository,
+	/// and everything else against the main one.
+	fn resolver_for(&self, file: Option<&Path>) -> FileResolver {
+		let external_dir = self.external_dir();
+		let repository_root = match (file, &external_dir) {
+			(Some(file), Some(external)) if file.starts_with(external) => file
+				.strip_prefix(external)
+				.ok()
+				.and_then(|repo| repo.components().next())
+				.map(|repo| external.join(repo)),
+			_ => self.workspace_root.clone(),
+		};
+		FileResolver { repository_root, external_dir }
+	}
+
+	fn external_dir(&self) -> Option<PathBuf> {
+		self.output_base.as_deref().map(external_dir)
+	}
+
+	fn contains(&self, file: &Path) -> Containment {
+		match (&self.workspace_root, &self.external_dir()) {
+			(Some(root), _) if file.starts_with(root) => Containment::Source(root.components().count()),
+			(_, Some(external)) if file.starts_with(external) => Containment::External,
+			_ => Containment::No,
+		}
+	}
+
+	fn label_for(&self, file: &Path) -> Option<String> {
+		let (repo, root) = match (&self.workspace_root, &self.external_dir()) {
+			(Some(root), _) if file.starts_with(root) => (String::new(), root.clone()),
+			(_, Some(external)) if file.starts_with(external) => {
+				let repo = file.strip_prefix(external).ok()?.components().next()?.as_os_str();
+				(format!("@{}", repo.to_string_lossy()), external.join(repo))
+			}
+			_ => return None,
+		};
+		// Files belong to the package of the closest directory with a BUILD file.
+		let package = file
+			.ancestors()
+			.skip(1)
+			.take_while(|dir| dir.starts_with(&root))
+			.find(|dir| ["BUILD", "BUILD.bazel"].iter().any(|build| dir.join(build).is_file()))
+			.unwrap_or(&root);
+		let to_label = |path: &Path| path.to_string_lossy().replace('\\', "/");
+		Some(format!(
+			"{}//{}:{}",
+			repo,
+			to_label(package.strip_prefix(&root).ok()?),
+			to_label(file.strip_prefix(package).ok()?)
+		))
+	}
+
+	fn locate_workspace(&mut self, workspace: &Path) {
+		self.workspace_root = Some(workspace.to_path_buf());
+		self.set_output_base(Err("Bazel hasn't run yet".to_string()));
+	}
+
+	/// Keeps the output base that Bazel told us about, or else the one we find on disk.
+	fn set_output_base(&mut self, from_bazel: Result<PathBuf, String>) -> ExternalRepos {
+		let bazel_err = match from_bazel {
+			Ok(output_base) => {
+				self.output_base = Some(output_base);
+				return ExternalRepos::FromBazel;
+			}
+			Err(bazel_err) => bazel_err,
+		};
+		self.output_base = self.workspace_root.as_deref().and_then(locate_output_base);
+		match &self.output_base {
+			Some(output_base) => ExternalRepos::FromOutputBase(output_base.clone()),
+			None => ExternalRepos::Unavailable(bazel_err),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use std::fs;
+	use std::os::unix::fs::symlink;
+
+	fn workspace_without_executable(root: &Path) -> BazelWorkspace {
+		let workspace = BazelWorkspace {
+			inner: Arc::new(Mutex::new(InnerBazel {
+				bazel_exe: BazelExecutable::new("/nonexistent/bazel"),
+				..InnerBazel::new()
+			})),
+		};
+		workspace.locate_workspace(root).unwrap();
+		workspace
+	}
+
+	#[test]
+	fn test_locate_output_base_from_convenience_symlink() {
+		let tmp = tempfile::tempdir().unwrap();
+		let workspace = tmp.path().join("my_ws");
+		let output_base = tmp.path().join("output_base");
+		fs::create_dir_all(&workspace).unwrap();
+		fs::create_dir_all(output_base.join("execroot").join("my_ws")).unwrap();
+		fs::create_dir_all(output_base.join("external")).unwrap();
+		symlink(output_base.join("execroot").join("my_ws"), workspace.join("bazel-my_ws")).unwrap();
+
+		assert_eq!(locate_output_base(&workspace), Some(output_base.clone()));
+
+		// A link into something that isn't an output base doesn't count.
+		fs::remove_dir(output_base.join("external")).unwrap();
+		assert_eq!(output_base_from_symlinks(&workspace), None);
+	}
+
+	#[test]
+	fn test_query_bazel_without_bazel_falls_back_to_symlinks() {
+		let tmp = tempfile::tempdir().unwrap();
+		let workspace = tmp.path().join("my_ws");
+		let output_base = tmp.path().join("output_base");
+		let exec_root = output_base.join("execroot").join("my_ws");
+		fs::create_dir_all(&workspace).unwrap();
+		fs::create_dir_all(exec_root.join("bazel-out")).unwrap();
+		fs::create_dir_all(output_base.join("external").join("rules_foo")).unwrap();
+		fs::write(output_base.join("external").join("rules_foo").join("defs.bzl"), "").unwrap();
+		symlink(exec_root.join("bazel-out"), workspace.join("bazel-out")).unwrap();
+
+		let bazel = workspace_without_executable(&workspace);
+		// We find the external repositories before we ask Bazel, and only ask it once.
+		assert_eq!(bazel.output_base(), Some(output_base.clone()));
+		let external_repos = bazel.query_bazel().unwrap().unwrap();
+		assert_eq!(bazel.query_bazel(), Ok(None));
+
+		assert_eq!(external_repos, ExternalRepos::FromOutputBase(output_base.clone()));
+		assert_eq!(
+			bazel.resolve_bazel_path("@rules_foo//:defs.bzl"),
+			Ok(output_base.join("external").join("rules_foo").join("defs.bzl"))
+		);
+	}
+
+	#[test]
+	fn test_query_bazel_without_output_base_only_resolves_main_repo() {
+		let tmp = tempfile::tempdir().unwrap();
+		let workspace = tmp.path().join("my_ws");
+		fs::create_dir_all(&workspace).unwrap();
+		fs::write(workspace.join("defs.bzl"), "").unwrap();
+
+		let bazel = workspace_without_executable(&workspace);
+		let external_repos = bazel.query_bazel().unwrap().unwrap();
+
+		assert!(matches!(external_repos, ExternalRepos::Unavailable(_)));
+		assert!(!external_repos.unavailable_features().is_empty());
+		assert_eq!(bazel.resolve_bazel_path("//:defs.bzl"), Ok(workspace.join("defs.bzl")));
+		assert!(bazel.resolve_bazel_path("@rules_foo//:defs.bzl").is_err());
+	}
+
+	#[test]
+	fn test_resolve_main_labels_against_the_repository_of_the_file() {
+		let tmp = tempfile::tempdir().unwrap();
+		let workspace = tmp.path().join("my_ws");
+		let output_base = tmp.path().join("output_base");
+		let rules_foo = output_base.join("external").join("rules_foo");
+		fs::create_dir_all(&workspace).unwrap();
+		fs::create_dir_all(output_base.join("execroot").join("my_ws")).unwrap();
+		fs::create_dir_all(&rules_foo).unwrap();
+		fs::write(workspace.join("defs.bzl"), "").unwrap();
+		fs::write(rules_foo.join("defs.bzl"), "").unwrap();
+		symlink(output_base.join("execroot").join("my_ws"), workspace.join("bazel-my_ws")).unwrap();
+
+		let bazel = workspace_without_executable(&workspace);
+		let external = bazel.resolver_for(&rules_foo.join("BUILD"));
+		let main = bazel.resolver_for(&workspace.join("BUILD"));
+		assert_eq!(external.resolve_bazel_path("//:defs.bzl"), Ok(rules_foo.join("defs.bzl")));
+		assert_eq!(main.resolve_bazel_path("//:defs.bzl"), Ok(workspace.join("defs.bzl")));
+		assert_eq!(main.resolve_bazel_path("@rules_foo//:defs.bzl"), Ok(rules_foo.join("defs.bzl")));
+	}
+
+	#[test]
+	fn test_discover_nested_workspaces() {
+		let tmp = tempfile::tempdir().unwrap();
+		let outer = tmp.path().join("outer");
+		let inner = outer.join("third_party").join("inner");
+		fs::create_dir_all(&inner).unwrap();
+		fs::create_dir_all(outer.join("bazel-outer").join("copy")).unwrap();
+		fs::write(outer.join("WORKSPACE"), "").unwrap();
+		fs::write(inner.join("MODULE.bazel"), "").unwrap();
+		fs::write(outer.join("bazel-outer").join("copy").join("WORKSPACE"), "").unwrap();
+		// Ignored directories don't count, even when they look like workspaces.
+		fs::create_dir_all(outer.join("vendor").join("ws")).unwrap();
+		fs::write(outer.join("vendor").join("ws").join("WORKSPACE"), "").unwrap();
+		fs::write(outer.join(".bazelignore"), "vendor\n").unwrap();
+
+		assert_eq!(discover_workspaces(tmp.path()), vec![outer, inner]);
+	}
+
+	#[test]
+	fn test_documents_are_routed_to_the_innermost_workspace() {
+		let tmp = tempfile::tempdir().unwrap();
+		let outer = tmp.path().join("outer");
+		let inner = outer.join("inner");
+		fs::create_dir_all(&inner).unwrap();
+		fs::write(outer.join("WORKSPACE"), "").unwrap();
+		fs::write(inner.join("WORKSPACE.bazel"), "").unwrap();
+
+		let workspaces = BazelWorkspaces::default();
+		let added = workspaces.add_folder(&outer).unwrap();
+		assert_eq!(added.len(), 2);
+
+		let root_of = |file: &Path| {
+			workspaces
+				.workspace_for(file)
+				.and_then(|workspace| workspace.inner.lock().unwrap().workspace_root.clone())
+		};
+		assert_eq!(root_of(&outer.join("BUILD")), Some(outer.clone()));
+		assert_eq!(root_of(&inner.join("BUILD")), Some(inner.clone()));
+		assert_eq!(root_of(&tmp.path().join("BUILD")), None);
+
+		assert_eq!(workspaces.remove_folder(&inner).unwrap(), vec![inner.clone()]);
+		assert_eq!(root_of(&inner.join("BUILD")), Some(outer));
+	}
+
+	#[test]
+	fn test_starlark_files_honor_bazelignore() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		fs::create_dir_all(root.join("pkg")).unwrap();
+		fs::create_dir_all(root.join("ignored").join("pkg")).unwrap();
+		fs::write(root.join(".bazelignore"), "# Vendored sources\nignored/\n").unwrap();
+		for file in &["WORKSPACE", "BUILD.bazel", "pkg/BUILD", "pkg/defs.bzl", "pkg/main.rs", "ignored/pkg/BUILD"] {
+			fs::write(root.join(file), "").unwrap();
+		}
+
+		assert_eq!(
+			starlark_files(root),
+			vec![
+				root.join("BUILD.bazel"),
+				root.join("WORKSPACE"),
+				root.join("pkg").join("BUILD"),
+				root.join("pkg").join("defs.bzl"),
+			]
+		);
+	}
+}
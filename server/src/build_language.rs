@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use prost::Message;
+
+use crate::index::definition::{Definition, RuleKind};
+use crate::index::signature::{Parameter, Signature};
+
+// The parts of Bazel's `build.proto` that `bazel info build-language` outputs, and that we use.
+
+#[derive(Clone, PartialEq, Message)]
+struct BuildLanguage {
+	#[prost(message, repeated, tag = "1")]
+	rule: Vec<RuleDefinition>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct RuleDefinition {
+	#[prost(string, required, tag = "1")]
+	name: String,
+	#[prost(message, repeated, tag = "2")]
+	attribute: Vec<AttributeDefinition>,
+	#[prost(string, optional, tag = "3")]
+	documentation: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AttributeDefinition {
+	#[prost(string, required, tag = "1")]
+	name: String,
+	// An `Attribute.Discriminator`.
+	#[prost(int32, required, tag = "2")]
+	r#type: i32,
+	#[prost(bool, optional, tag = "3")]
+	mandatory: Option<bool>,
+	#[prost(string, optional, tag = "5")]
+	documentation: Option<String>,
+}
+
+/// The name `attr` gives to each `Attribute.Discriminator`.
+fn attr_type(discriminator: i32) -> Option<&'static str> {
+	match discriminator {
+		1 => Some("int"),
+		2 => Some("string"),
+		3 => Some("label"),
+		4 => Some("output"),
+		5 => Some("string_list"),
+		6 => Some("label_list"),
+		7 => Some("output_list"),
+		10 => Some("string_dict"),
+		12 => Some("label_list_dict"),
+		13 => Some("string_list_dict"),
+		14 => Some("bool"),
+		15 => Some("int"),
+		16 => Some("int_list"),
+		19 => Some("label_dict"),
+		21 => Some("label_keyed_string_dict"),
+		_ => None,
+	}
+}
+
+fn non_empty(doc: Option<String>) -> Option<String> {
+	doc.filter(|doc| !doc.trim().is_empty())
+}
+
+/// The rules Bazel itself provides, e.g. `java_library` or `filegroup`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NativeRules {
+	rules: HashMap<String, Definition>,
+}
+
+impl NativeRules {
+	/// Reads the output of `bazel info build-language`.
+	pub fn from_build_language(proto: &[u8]) -> Result<Self, String> {
+		let build_language = BuildLanguage::decode(proto)
+			.map_err(|err| format!("Error decoding the build language: {:?}", err))?;
+		let rules = build_language
+			.rule
+			.into_iter()
+			// Rules whose names start with `$` or `_` are internal to Bazel.
+			.filter(|rule| !rule.name.starts_with('$') && !rule.name.starts_with('_'))
+			.map(|rule| {
+				let parameters = rule
+					.attribute
+					.into_iter()
+					.filter(|attr| !attr.name.starts_with('$') && !attr.name.starts_with(':'))
+					.map(|attr| Parameter {
+						attr_type: attr_type(attr.r#type).map(|attr_type| attr_type.to_string()),
+						mandatory: attr.mandatory.unwrap_or(false),
+						doc: non_empty(attr.documentation),
+						..Parameter::new(&attr.name)
+					})
+					.collect();
+				let definition = Definition::Rule {
+					kind: RuleKind::Rule,
+					attrs: Signature { parameters },
+					doc: non_empty(rule.documentation),
+					implementation: None,
+				};
+				(rule.name, definition)
+			})
+			.collect();
+		Ok(NativeRules { rules })
+	}
+
+	/// Accepts both `cc_library` and `native.cc_library`.
+	pub fn get(&self, name: &str) -> Option<&Definition> {
+		self.rules.get(name.trim_start_matches("native."))
+	}
+
+	pub fn names(&self) -> impl Iterator<Item = &String> {
+		self.rules.keys()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn attribute(name: &str, r#type: i32, mandatory: bool) -> AttributeDefinition {
+		AttributeDefinition {
+			name: name.to_string(),
+			r#type,
+			mandatory: Some(mandatory),
+			documentation: None,
+		}
+	}
+
+	#[test]
+	fn test_decode_build_language() {
+		let build_language = BuildLanguage {
+			rule: vec![
+				RuleDefinition {
+					name: "java_library".to_string(),
+					attribute: vec![
+						attribute("name", 2, true),
+						attribute("srcs", 6, false),
+						attribute("$implicit_dep", 3, false),
+					],
+					documentation: Some("".to_string()),
+				},
+				RuleDefinition {
+					name: "$base_rule".to_string(),
+					attribute: vec![],
+					documentation: None,
+				},
+			],
+		};
+		let mut proto = vec![];
+		build_language.encode(&mut proto).unwrap();
+
+		let native_rules = NativeRules::from_build_language(&proto).unwrap();
+
+		let mut name = Parameter::new("name");
+		name.attr_type = Some("string".to_string());
+		name.mandatory = true;
+		let mut srcs = Parameter::new("srcs");
+		srcs.attr_type = Some("label_list".to_string());
+		assert_eq!(
+			native_rules.get("native.java_library"),
+			Some(&Definition::Rule {
+				kind: RuleKind::Rule,
+				attrs: Signature { parameters: vec![name, srcs] },
+				doc: None,
+				implementation: None,
+			})
+		);
+		assert_eq!(native_rules.get("$base_rule"), None);
+	}
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+use tower_lsp::lsp_types as lsp;
+
+use crate::bazel::BazelExecutable;
+use crate::format::document_range;
+
+/// The buildifier that lints and fixes Starlark files.
+#[derive(Debug, Clone)]
+pub struct Buildifier {
+	executable: BazelExecutable,
+}
+
+#[derive(Debug, Deserialize)]
+struct Report {
+	#[serde(default)]
+	files: Vec<FileReport>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileReport {
+	#[serde(default)]
+	warnings: Vec<Warning>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Warning {
+	start: Position,
+	end: Position,
+	category: String,
+	message: String,
+	#[serde(default)]
+	url: Option<String>,
+	#[serde(default)]
+	auto_fixable: bool,
+}
+
+/// Lines and columns both start at 1.
+#[derive(Debug, Deserialize)]
+struct Position {
+	line: u32,
+	column: u32,
+}
+
+impl Position {
+	fn as_lsp_position(&self) -> lsp::Position {
+		lsp::Position::new(self.line.saturating_sub(1), self.column.saturating_sub(1))
+	}
+}
+
+/// A warning of buildifier, as a diagnostic that links to the docs of the warning.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+	pub diagnostic: lsp::Diagnostic,
+	/// Whether `buildifier --lint=fix` fixes it.
+	pub fixable: bool,
+}
+
+impl Finding {
+	fn from_warning(warning: Warning) -> Self {
+		let range = lsp::Range::new(warning.start.as_lsp_position(), warning.end.as_lsp_position());
+		Finding {
+			diagnostic: lsp::Diagnostic {
+				range,
+				severity: Some(lsp::DiagnosticSeverity::WARNING),
+				code: Some(lsp::NumberOrString::String(warning.category)),
+				source: Some("buildifier".to_string()),
+				message: warning.message,
+				related_information: None,
+				tags: None,
+				code_description: warning
+					.url
+					.and_then(|url| lsp::Url::parse(&url).ok())
+					.map(|href| lsp::CodeDescription { href }),
+				data: None,
+			},
+			fixable: warning.auto_fixable,
+		}
+	}
+
+	pub fn category(&self) -> Option<&str> {
+		match &self.diagnostic.code {
+			Some(lsp::NumberOrString::String(category)) => Some(category),
+			_ => None,
+		}
+	}
+}
+
+impl Buildifier {
+	pub fn new(executable: &str) -> Self {
+		Buildifier {
+			executable: BazelExecutable::new(executable),
+		}
+	}
+
+	/// The `buildifier` on the PATH, if there is one.
+	pub fn from_path() -> Option<Self> {
+		BazelExecutable::from_path("buildifier").map(|executable| Buildifier { executable })
+	}
+
+	/// Buildifier reads `contents` from stdin, and tells what kind of file it is from the name we pass along.
+	fn run(&self, doc: &Path, contents: &str, mut args: Vec<String>) -> Result<std::process::Output, String> {
+		let dir = doc.parent().ok_or_else(|| format!("{:?} is not in a directory", doc))?;
+		let name = doc.file_name().ok_or_else(|| format!("{:?} is not a file", doc))?;
+		args.push(format!("--path={}", name.to_string_lossy()));
+		self.executable.call_with_input(args, dir, contents.as_bytes())
+	}
+
+	/// The lint warnings of `contents`, which is the text of `doc`.
+	pub fn lint(&self, doc: &Path, contents: &str) -> Result<Vec<Finding>, String> {
+		let args = vec![
+			"--mode=check".to_string(),
+			"--lint=warn".to_string(),
+			"--format=json".to_string(),
+		];
+		// Buildifier exits with an error when it finds warnings, so we go by whether it reported any.
+		let output = self.run(doc, contents, args)?;
+		let report = serde_json::from_slice::<Report>(&output.stdout).map_err(|err| {
+			format!(
+				"Couldn't read the findings of buildifier ({:?}): {}",
+				err,
+				String::from_utf8_lossy(&output.stderr).trim()
+			)
+		})?;
+		Ok(report
+			.files
+			.into_iter()
+			.flat_map(|file| file.warnings)
+			.map(Finding::from_warning)
+			.collect())
+	}
+
+	/// `contents`, with the warnings of `category` fixed, and formatted like buildifier always does.
+	pub fn fix(&self, doc: &Path, contents: &str, category: &str) -> Result<String, String> {
+		let args = vec!["--lint=fix".to_string(), format!("--warnings={}", category)];
+		let output = self.run(doc, contents, args)?;
+		if output.stdout.is_empty() {
+			return Err(format!(
+				"buildifier couldn't fix {}: {}",
+				category,
+				String::from_utf8_lossy(&output.stderr).trim()
+			));
+		}
+		String::from_utf8(output.stdout).map_err(|err| format!("Error parsing output: {:?}", err))
+	}
+}
+
+/// The quick fixes of the fixable `findings` among `diagnostics`, one per category of warning,
+/// since buildifier fixes all the warnings of a category at once.
+pub fn code_actions(
+	buildifier: &Buildifier,
+	doc: &Path,
+	contents: &str,
+	findings: &[Finding],
+	diagnostics: &[lsp::Diagnostic],
+) -> Vec<lsp::CodeAction> {
+	let uri = match lsp::Url::from_file_path(doc) {
+		Ok(uri) => uri,
+		Err(_) => return vec![],
+	};
+	let mut fixable = Vec::<(&str, Vec<lsp::Diagnostic>)>::new();
+	for finding in findings.iter().filter(|finding| finding.fixable && diagnostics.contains(&finding.diagnostic)) {
+		let category = match finding.category() {
+			Some(category) => category,
+			None => continue,
+		};
+		match fixable.iter_mut().find(|(fixed, _)| *fixed == category) {
+			Some((_, fixed)) => fixed.push(finding.diagnostic.clone()),
+			None => fixable.push((category, vec![finding.diagnostic.clone()])),
+		}
+	}
+	fixable
+		.into_iter()
+		.filter_map(|(category, diagnostics)| {
+			let fixed = buildifier.fix(doc, contents, category).ok().filter(|fixed| fixed != contents)?;
+			let edit = lsp::TextEdit::new(document_range(contents), fixed);
+			Some(lsp::CodeAction {
+				title: format!("Fix `{}` warnings with buildifier", category),
+				kind: Some(lsp::CodeActionKind::QUICKFIX),
+				diagnostics: Some(diagnostics),
+				edit: Some(lsp::WorkspaceEdit {
+					changes: Some(std::iter::once((uri.clone(), vec![edit])).collect::<HashMap<_, _>>()),
+					document_changes: None,
+					change_annotations: None,
+				}),
+				command: None,
+				is_preferred: Some(true),
+				disabled: None,
+				data: None,
+			})
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::fs;
+	use std::os::unix::fs::PermissionsExt;
+
+	// Answers lint checks with one warning, and prints the arguments it was run with when fixing.
+	const STUB: &str = r#"#!/bin/sh
+cat > /dev/null
+case "$*" in
+  *--lint=fix*) echo "fixed with $*" ;;
+  *) echo '{"success": false, "files": [{"filename": "<stdin>", "formatted": true, "valid": true, "warnings": [
+       {"start": {"line": 1, "column": 22}, "end": {"line": 1, "column": 25}, "category": "load",
+        "actionable": true, "autoFixable": true, "message": "Loaded symbol \"foo\" is unused.",
+        "url": "https://github.com/bazelbuild/buildtools/blob/master/WARNINGS.md#load"}]}]}'
+     exit 4 ;;
+esac
+"#;
+
+	#[test]
+	fn test_lints_and_fixes_with_buildifier() {
+		let tmp = tempfile::tempdir().unwrap();
+		let stub = tmp.path().join("buildifier");
+		fs::write(&stub, STUB).unwrap();
+		fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+		let buildifier = Buildifier::new(stub.to_str().unwrap());
+		let doc = tmp.path().join("BUILD");
+		let contents = "load(\"//:defs.bzl\", \"foo\")\n";
+
+		let findings = buildifier.lint(&doc, contents).unwrap();
+		assert_eq!(findings.len(), 1);
+		let finding = &findings[0];
+		assert_eq!(
+			finding.diagnostic.range,
+			lsp::Range::new(lsp::Position::new(0, 21), lsp::Position::new(0, 24))
+		);
+		assert_eq!(finding.category(), Some("load"));
+		let href = &finding.diagnostic.code_description.as_ref().unwrap().href;
+		assert_eq!(href.fragment(), Some("load"));
+		let published = serde_json::to_value(&finding.diagnostic).unwrap();
+		assert_eq!(published["codeDescription"]["href"], href.as_str());
+		assert_eq!(published["code"], "load");
+
+		let actions = code_actions(&buildifier, &doc, contents, &findings, std::slice::from_ref(&finding.diagnostic));
+		assert_eq!(actions.len(), 1);
+		let edits = &actions[0].edit.as_ref().unwrap().changes.as_ref().unwrap()[&lsp::Url::from_file_path(&doc).unwrap()];
+		assert_eq!(edits[0].new_text, "fixed with --lint=fix --warnings=load --path=BUILD\n");
+		assert_eq!(code_actions(&buildifier, &doc, contents, &findings, &[]), vec![]);
+	}
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tower_lsp::lsp_types as lsp;
+
+use crate::build_language::NativeRules;
+use crate::index::definition::Definition;
+use crate::index::indexed_document::IndexedDocument;
+use crate::index::signature::{Parameter, Signature};
+
+// A snapshot of the Starlark and Bazel API docs, for what `bazel info build-language` doesn't tell us.
+const BUNDLED_BUILTINS: &str = include_str!("builtins.json");
+
+#[derive(Debug, Deserialize)]
+struct BuiltinEntry {
+	name: String,
+	doc: String,
+	// None for values, which can't be called.
+	params: Option<Vec<BuiltinParam>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BuiltinParam {
+	name: String,
+	#[serde(default)]
+	mandatory: bool,
+	default: Option<String>,
+}
+
+/// The symbols every file can use without loading them.
+#[derive(Debug, Clone, Default)]
+pub struct Builtins {
+	// Dotted for members, e.g. `attr.string`.
+	bundled: Arc<HashMap<String, Definition>>,
+	native_rules: Option<Arc<NativeRules>>,
+}
+
+impl Builtins {
+	pub fn bundled() -> Self {
+		Builtins::from_json(BUNDLED_BUILTINS).expect("The bundled builtins are valid")
+	}
+
+	fn from_json(json: &str) -> Result<Self, String> {
+		let entries: Vec<BuiltinEntry> =
+			serde_json::from_str(json).map_err(|err| format!("Error reading the builtins: {:?}", err))?;
+		let bundled = entries
+			.into_iter()
+			.map(|entry| {
+				let doc = Some(entry.doc);
+				let definition = match entry.params {
+					Some(params) => Definition::Function {
+						signature: Signature {
+							parameters: params
+								.into_iter()
+								.map(|param| Parameter {
+									mandatory: param.mandatory,
+									default: param.default,
+									..Parameter::new(&param.name)
+								})
+								.collect(),
+						},
+						doc,
+						forwards_kwargs_to: None,
+					},
+					None => Definition::Value { doc },
+				};
+				(entry.name, definition)
+			})
+			.collect();
+		Ok(Builtins {
+			bundled: Arc::new(bundled),
+			native_rules: None,
+		})
+	}
+
+	/// The same builtins, plus the native rules of a workspace once Bazel told us about them.
+	pub fn with_native_rules(&self, native_rules: Option<Arc<NativeRules>>) -> Self {
+		Builtins {
+			bundled: self.bundled.clone(),
+			native_rules,
+		}
+	}
+
+	/// Accepts both global names and dotted members, e.g. `glob` or `native.cc_library`.
+	pub fn get(&self, name: &str) -> Option<&Definition> {
+		self.bundled
+			.get(name)
+			.or_else(|| self.native_rules.as_ref().and_then(|native_rules| native_rules.get(name)))
+	}
+
+	/// The names of the members of `module`, e.g. `string` for `attr`, with their definitions.
+	pub fn members(&self, module: &str) -> Vec<(String, Option<&Definition>)> {
+		let prefix = format!("{}.", module);
+		let mut members = self
+			.bundled
+			.iter()
+			.filter_map(|(name, definition)| Some((name.strip_prefix(&prefix)?, definition)))
+			.filter(|(member, _)| !member.contains('.'))
+			.map(|(member, definition)| (member.to_string(), Some(definition)))
+			.collect::<Vec<_>>();
+		if let (true, Some(native_rules)) = (module == "native", &self.native_rules) {
+			members.extend(native_rules.names().map(|name| (name.clone(), native_rules.get(name))));
+		}
+		members.sort_by(|a, b| a.0.cmp(&b.0));
+		members.dedup_by(|a, b| a.0 == b.0);
+		members
+	}
+
+	/// The global names, which BUILD files can also call native rules by.
+	pub fn globals(&self) -> Vec<(String, Option<&Definition>)> {
+		let mut globals = self
+			.bundled
+			.iter()
+			.filter(|(name, _)| !name.contains('.'))
+			.map(|(name, definition)| (name.clone(), Some(definition)))
+			.collect::<Vec<_>>();
+		if let Some(native_rules) = &self.native_rules {
+			globals.extend(native_rules.names().map(|name| (name.clone(), native_rules.get(name))));
+		}
+		globals.sort_by(|a, b| a.0.cmp(&b.0));
+		globals.dedup_by(|a, b| a.0 == b.0);
+		globals
+	}
+}
+
+/// Warns about the top-level names `document` uses without defining, loading or having them built in.
+///
+/// Names in function bodies can be bound by code we don't index, like lambdas, so we leave them alone.
+pub fn undefined_names(path: &Path, document: &IndexedDocument, builtins: &Builtins) -> Vec<lsp::Diagnostic> {
+	let is_bzl = path.extension().map(|extension| extension == "bzl").unwrap_or(false);
+	// BUILD files call native rules directly, so we can't tell until Bazel told us what those are.
+	if !is_bzl && builtins.native_rules.is_none() {
+		return vec![];
+	}
+	document
+		.calls
+		.iter()
+		.filter(|call| call.function.is_none())
+		.filter(|call| !document.declarations.contains_key(&call.function_name))
+		.filter(|call| !document.loads.iter().any(|load| load.symbols.contains(&call.function_name)))
+		.filter(|call| builtins.get(&call.function_name).is_none())
+		.map(|call| lsp::Diagnostic {
+			range: call.range().as_lsp_range(),
+			severity: Some(lsp::DiagnosticSeverity::WARNING),
+			source: Some("bazel-lsp".to_string()),
+			message: format!("'{}' is not defined", call.function_name),
+			..lsp::Diagnostic::default()
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::ast::process_document;
+	use crate::bazel::BazelWorkspace;
+
+	#[test]
+	fn test_bundled_builtins() {
+		let builtins = Builtins::bundled();
+		let run = builtins.get("ctx.actions.run").and_then(Definition::signature).unwrap();
+		assert!(run.parameters.iter().any(|param| param.name == "executable" && param.mandatory));
+		assert_eq!(builtins.get("native").and_then(Definition::signature), None);
+		assert!(builtins.members("attr").iter().any(|(name, _)| name == "string"));
+		assert_eq!(
+			builtins.members("ctx").into_iter().map(|(name, _)| name).collect::<Vec<_>>(),
+			vec!["runfiles"]
+		);
+	}
+
+	#[test]
+	fn test_undefined_names_skip_builtins_and_locals() {
+		let (document, _) = process_document(
+			"load(':missing.bzl', 'loaded')\nx = len(glob(['*']))\ndef f(a):\n  a()\nloaded(y, select({}), native)\n",
+			&BazelWorkspace::new(),
+		)
+		.unwrap();
+		let messages = undefined_names(Path::new("defs.bzl"), &document, &Builtins::bundled())
+			.into_iter()
+			.map(|diagnostic| diagnostic.message)
+			.collect::<Vec<_>>();
+		assert_eq!(messages, vec!["'y' is not defined"]);
+		// Without the native rules, we can't tell what BUILD files can call.
+		assert!(undefined_names(Path::new("BUILD"), &document, &Builtins::bundled()).is_empty());
+	}
+}
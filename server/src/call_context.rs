@@ -0,0 +1,318 @@
+use tower_lsp::lsp_types as lsp;
+
+/// The call the cursor is in, as far as we can tell from the text alone.
+///
+/// We can't rely on the parser here: while arguments are being typed, the file rarely parses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallContext {
+	// Dotted, e.g. `native.cc_library`.
+	pub function_name: String,
+	/// The position of the argument the cursor is in.
+	pub argument: usize,
+	/// The keyword of the argument the cursor is in, if it has one yet.
+	pub keyword: Option<String>,
+	/// The keywords of the other arguments.
+	pub used_keywords: Vec<String>,
+	/// Whether the cursor is where a keyword goes, e.g. right after `(` or `,`,
+	/// or in the middle of typing one.
+	pub at_keyword: bool,
+}
+
+#[derive(Debug)]
+struct Frame {
+	bracket: char,
+	function_name: Option<String>,
+	argument: usize,
+	keywords: Vec<Option<String>>,
+}
+
+impl Frame {
+	fn new(bracket: char, function_name: Option<String>) -> Self {
+		Frame {
+			bracket,
+			function_name,
+			argument: 0,
+			keywords: vec![None],
+		}
+	}
+}
+
+/// The byte offset of `position`, whose character counts UTF-16 code units, like LSP positions do.
+fn offset_of(text: &str, position: lsp::Position) -> usize {
+	let mut offset = 0;
+	for (line, contents) in text.split('\n').enumerate() {
+		if line as u32 == position.line {
+			let mut units = 0;
+			let column = contents
+				.char_indices()
+				.find(|(_, c)| {
+					let reached = units >= position.character as usize;
+					units += c.len_utf16();
+					reached
+				})
+				.map(|(column, _)| column)
+				.unwrap_or_else(|| contents.len());
+			return offset + column;
+		}
+		offset += contents.len() + 1;
+	}
+	text.len()
+}
+
+fn is_name_char(c: char) -> bool {
+	c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+/// The dotted name under the cursor, e.g. `ctx.actions.run`, and where it starts and ends on its line.
+pub fn name_at(text: &str, position: lsp::Position) -> Option<(String, lsp::Range)> {
+	let line = text.split('\n').nth(position.line as usize)?.chars().collect::<Vec<_>>();
+	// LSP characters count UTF-16 code units, like in `offset_of`, and we count chars.
+	let mut units = 0;
+	let cursor = line
+		.iter()
+		.take_while(|c| {
+			let before = units < position.character as usize;
+			units += c.len_utf16();
+			before
+		})
+		.count();
+	let character = |i: usize| line[..i].iter().map(|c| c.len_utf16()).sum::<usize>() as u32;
+	let start = (0..cursor).rev().take_while(|i| is_name_char(line[*i])).last().unwrap_or(cursor);
+	let end = (cursor..line.len()).take_while(|i| is_name_char(line[*i])).last().map(|i| i + 1).unwrap_or(cursor);
+	if start == end || line[start].is_ascii_digit() {
+		return None;
+	}
+	let name = line[start..end].iter().collect::<String>();
+	let range = lsp::Range::new(
+		lsp::Position::new(position.line, character(start)),
+		lsp::Position::new(position.line, character(end)),
+	);
+	Some((name, range))
+}
+
+/// The name of the top-level `def` whose body `position` is in.
+pub fn enclosing_function(text: &str, position: lsp::Position) -> Option<String> {
+	let lines = text.split('\n').take(position.line as usize + 1).collect::<Vec<_>>();
+	for (i, line) in lines.iter().enumerate().rev() {
+		if let Some(def) = line.strip_prefix("def ") {
+			let name = def.split(|c: char| !c.is_alphanumeric() && c != '_').next()?;
+			return Some(name.to_string()).filter(|name| !name.is_empty());
+		}
+		let cursor_line = i == position.line as usize;
+		// Any other unindented statement ends the function, unless it's what's being typed.
+		if !cursor_line && !line.is_empty() && !line.starts_with(char::is_whitespace) && !line.starts_with('#') {
+			return None;
+		}
+		if cursor_line && line.chars().take(position.character as usize).next().map(|c| !c.is_whitespace()).unwrap_or(false) {
+			return None;
+		}
+	}
+	None
+}
+
+/// Finds the innermost call that `position` is an argument of.
+pub fn call_at(text: &str, position: lsp::Position) -> Option<CallContext> {
+	let text = &text[..offset_of(text, position)];
+	let mut chars = text.chars().peekable();
+	let mut frames: Vec<Frame> = vec![];
+	// The identifier we just read, if nothing else came after it.
+	let mut last_identifier: Option<String> = None;
+	// Whether we are at the start of an argument, where a keyword could go.
+	let mut at_argument_start = false;
+	let mut pending_keyword: Option<String> = None;
+	while let Some(c) = chars.next() {
+		let identifier = last_identifier.take();
+		let keyword = pending_keyword.take();
+		let argument_start = std::mem::replace(&mut at_argument_start, false);
+		match c {
+			c if c.is_whitespace() => {
+				last_identifier = identifier;
+				pending_keyword = keyword;
+				at_argument_start = argument_start;
+			}
+			'#' => {
+				while chars.peek().map(|c| *c != '\n').unwrap_or(false) {
+					chars.next();
+				}
+				last_identifier = identifier;
+				pending_keyword = keyword;
+				at_argument_start = argument_start;
+			}
+			'"' | '\'' => {
+				let triple = chars.peek() == Some(&c) && {
+					let mut lookahead = chars.clone();
+					lookahead.next();
+					lookahead.peek() == Some(&c)
+				};
+				if triple {
+					chars.next();
+					chars.next();
+				}
+				let mut quotes = 0;
+				while let Some(next) = chars.next() {
+					match next {
+						'\\' => {
+							chars.next();
+							quotes = 0;
+						}
+						next if next == c => {
+							quotes += 1;
+							if !triple || quotes == 3 {
+								break;
+							}
+						}
+						_ => quotes = 0,
+					}
+				}
+			}
+			c if c.is_ascii_digit() => {
+				while chars.peek().map(|next| next.is_alphanumeric() || *next == '.').unwrap_or(false) {
+					chars.next();
+				}
+			}
+			c if c.is_alphanumeric() || c == '_' => {
+				let mut name = c.to_string();
+				while let Some(next) = chars.peek().filter(|next| next.is_alphanumeric() || **next == '_' || **next == '.') {
+					name.push(*next);
+					chars.next();
+				}
+				if argument_start {
+					pending_keyword = Some(name.clone());
+				}
+				last_identifier = Some(name);
+			}
+			'=' if chars.peek() == Some(&'=') => {
+				chars.next();
+			}
+			'=' => {
+				if let (Some(keyword), Some(frame)) = (keyword, frames.last_mut()) {
+					frame.keywords[frame.argument] = Some(keyword);
+				}
+			}
+			'(' | '[' | '{' => {
+				frames.push(Frame::new(c, identifier.filter(|_| c == '(')));
+				at_argument_start = true;
+			}
+			')' | ']' | '}' => {
+				frames.pop();
+			}
+			',' => {
+				if let Some(frame) = frames.last_mut() {
+					frame.argument += 1;
+					frame.keywords.push(None);
+				}
+				at_argument_start = true;
+			}
+			_ => {}
+		}
+	}
+	let typing_identifier = last_identifier.is_some() && text.ends_with(|c: char| c.is_alphanumeric() || c == '_');
+	let at_keyword = frames.last().map(|frame| frame.bracket == '(').unwrap_or(false)
+		&& (at_argument_start || (pending_keyword.is_some() && typing_identifier));
+	let frame = frames.into_iter().rev().find(|frame| frame.bracket == '(')?;
+	let mut keywords = frame.keywords;
+	let keyword = keywords.pop().flatten();
+	Some(CallContext {
+		function_name: frame.function_name?,
+		argument: frame.argument,
+		keyword,
+		used_keywords: keywords.into_iter().flatten().collect(),
+		at_keyword,
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	// `|` marks the cursor.
+	fn call_at_cursor(text: &str) -> Option<CallContext> {
+		let offset = text.find('|').unwrap();
+		let before = &text[..offset];
+		let line = before.matches('\n').count() as u32;
+		let character = before.rsplit('\n').next().unwrap().encode_utf16().count() as u32;
+		call_at(&text.replace('|', ""), lsp::Position::new(line, character))
+	}
+
+	fn context(function_name: &str, argument: usize, keyword: Option<&str>, used_keywords: &[&str]) -> Option<CallContext> {
+		Some(CallContext {
+			function_name: function_name.to_string(),
+			argument,
+			keyword: keyword.map(|keyword| keyword.to_string()),
+			used_keywords: used_keywords.iter().map(|keyword| keyword.to_string()).collect(),
+			at_keyword: false,
+		})
+	}
+
+	fn at_keyword(text: &str) -> Option<bool> {
+		call_at_cursor(text).map(|call| call.at_keyword)
+	}
+
+	#[test]
+	fn test_positional_and_keyword_arguments() {
+		assert_eq!(call_at_cursor("my_macro(1, f(2)|"), context("my_macro", 1, None, &[]));
+		assert_eq!(call_at_cursor("my_macro(1, f(2), 3|"), context("my_macro", 2, None, &[]));
+		assert_eq!(
+			call_at_cursor("rust_library(\n  name = \"lib\",\n  srcs = [\"a.rs\", |"),
+			context("rust_library", 1, Some("srcs"), &["name"])
+		);
+		assert_eq!(call_at_cursor("native.cc_library(deps = |)"), context("native.cc_library", 0, Some("deps"), &[]));
+	}
+
+	#[test]
+	fn test_keyword_positions() {
+		assert_eq!(at_keyword("java_library(|"), Some(true));
+		assert_eq!(at_keyword("java_library(name = 'a', sr|"), Some(true));
+		assert_eq!(at_keyword("java_library(name = 'a',\n  |"), Some(true));
+		assert_eq!(at_keyword("java_library(name = |"), Some(false));
+		assert_eq!(at_keyword("java_library(name = 'a', srcs = [sr|"), Some(false));
+		assert_eq!(at_keyword("java_library(name = 'a', srcs |"), Some(false));
+	}
+
+	#[test]
+	fn test_strings_and_comments_are_skipped() {
+		assert_eq!(
+			call_at_cursor("my_macro(a = \"(,\", # b = (\n  c == '''),''', 1|"),
+			context("my_macro", 2, None, &["a"])
+		);
+	}
+
+	#[test]
+	fn test_name_at() {
+		let text = "x = ctx.actions.run(1)";
+		let name = |character| name_at(text, lsp::Position::new(0, character)).map(|(name, _)| name);
+		assert_eq!(name(8), Some("ctx.actions.run".to_string()));
+		assert_eq!(name(19), Some("ctx.actions.run".to_string()));
+		assert_eq!(name(20), None);
+		assert_eq!(name(3), None);
+	}
+
+	#[test]
+	fn test_enclosing_function() {
+		let text = "def _impl(ctx):\n  x = 1\n\n  # comment\n  ctx.attr.\nmy_rule = rule()\nctx";
+		let function = |line, character| enclosing_function(text, lsp::Position::new(line, character));
+		assert_eq!(function(4, 11), Some("_impl".to_string()));
+		assert_eq!(function(0, 3), Some("_impl".to_string()));
+		assert_eq!(function(5, 3), None);
+		assert_eq!(function(6, 3), None);
+	}
+
+	#[test]
+	fn test_positions_count_utf16_code_units() {
+		// The emoji takes two code units, but only one char.
+		let argument = |text| call_at_cursor(text).map(|call| (call.argument, call.keyword));
+		assert_eq!(argument("my_macro('\u{1F600}', |)"), Some((1, None)));
+		assert_eq!(argument("my_macro('é', name = |)"), Some((1, Some("name".to_string()))));
+
+		let text = "'\u{1F600}' + ctx.attr";
+		let range = |start, end| lsp::Range::new(lsp::Position::new(0, start), lsp::Position::new(0, end));
+		assert_eq!(name_at(text, lsp::Position::new(0, 7)), Some(("ctx.attr".to_string(), range(7, 15))));
+		assert_eq!(name_at(text, lsp::Position::new(0, 15)), Some(("ctx.attr".to_string(), range(7, 15))));
+	}
+
+	#[test]
+	fn test_outside_of_calls() {
+		assert_eq!(call_at_cursor("my_macro()\n|"), None);
+		assert_eq!(call_at_cursor("x = [|"), None);
+	}
+}
@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types as lsp;
+
+use crate::builtins::Builtins;
+use crate::index::definition::Definition;
+use crate::index::indexed_document::IndexedDocument;
+use crate::index::member_access::Receiver;
+use crate::index::range::Range;
+use crate::index::snapshot::Snapshot;
+
+/// Something that makes or receives calls.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Node {
+	/// A file, which makes the calls at its top level, e.g. a BUILD file calling macros.
+	File(PathBuf),
+	/// A `def`, rule or provider, by the file that declares it.
+	Declared(PathBuf, String),
+	/// Something no file declares, e.g. `glob` or `native.cc_library`.
+	Builtin(String),
+}
+
+/// A call, or a reference to something callable, like the `implementation` of a rule.
+#[derive(Debug)]
+struct Call {
+	caller: Node,
+	callee: Node,
+	range: Range,
+}
+
+/// The calls `path` makes, with their callees resolved through its loads.
+fn calls_in(snapshot: &Snapshot, path: &Path, document: &IndexedDocument) -> Vec<Call> {
+	let caller = |function: &Option<String>| match function {
+		Some(function) => Node::Declared(path.to_path_buf(), function.clone()),
+		None => Node::File(path.to_path_buf()),
+	};
+	let calls = document.calls.iter().filter_map(|call| {
+		if document.is_local(&call.function_name, call.function.as_deref()) {
+			return None;
+		}
+		let callee = if document.declarations.contains_key(&call.function_name) {
+			let (file, decl) = snapshot.resolve_declaration(path, &call.function_name)?;
+			decl.definition?.signature()?;
+			Node::Declared(file, decl.imported_name)
+		} else {
+			Node::Builtin(call.function_name.clone())
+		};
+		Some(Call {
+			caller: caller(&call.function),
+			callee,
+			range: call.range().clone(),
+		})
+	});
+	// Only members of modules are calls we can follow, e.g. `native.cc_library` but not `ctx.actions.run`.
+	let members = document.members.iter().filter_map(|access| {
+		let receiver = match &access.receiver {
+			Receiver::Name(receiver) => receiver,
+			Receiver::Provider(_) => return None,
+		};
+		let module = receiver.split('.').next()?;
+		if document.declarations.contains_key(module) || document.is_local(module, access.function.as_deref()) {
+			return None;
+		}
+		Some(Call {
+			caller: caller(&access.function),
+			callee: Node::Builtin(format!("{}.{}", receiver, access.member)),
+			range: access.range().clone(),
+		})
+	});
+	calls.chain(members).collect()
+}
+
+/// The item for `node`. Builtins aren't in any file, so they are shown where they are called, at `site`.
+fn item_for(snapshot: &Snapshot, node: &Node, site: (&Path, &Range)) -> Option<lsp::CallHierarchyItem> {
+	let (name, kind, detail, file, range) = match node {
+		Node::File(file) => (
+			file.file_name()?.to_string_lossy().to_string(),
+			lsp::SymbolKind::FILE,
+			file.parent().map(|dir| dir.display().to_string()),
+			file.as_path(),
+			lsp::Range::default(),
+		),
+		Node::Declared(file, name) => {
+			let document = snapshot.get_doc(file)?;
+			let decl = document.declarations.get(name)?;
+			let kind = match decl.definition {
+				Some(Definition::Function { .. }) => lsp::SymbolKind::FUNCTION,
+				_ => lsp::SymbolKind::CLASS,
+			};
+			let file_name = file.file_name().map(|file_name| file_name.to_string_lossy().to_string());
+			(name.clone(), kind, file_name, file.as_path(), decl.declared_range()?.as_lsp_range())
+		}
+		Node::Builtin(name) => (
+			name.clone(),
+			lsp::SymbolKind::FUNCTION,
+			Some("builtin".to_string()),
+			site.0,
+			site.1.as_lsp_range(),
+		),
+	};
+	Some(lsp::CallHierarchyItem {
+		name,
+		kind,
+		tags: None,
+		detail,
+		uri: lsp::Url::from_file_path(file).ok()?,
+		range,
+		selection_range: range,
+		data: None,
+	})
+}
+
+/// Which node a client sent `item` back for.
+fn node_of(snapshot: &Snapshot, item: &lsp::CallHierarchyItem) -> Option<Node> {
+	let path = item.uri.to_file_path().ok()?;
+	if item.kind == lsp::SymbolKind::FILE {
+		return Some(Node::File(path));
+	}
+	let declared = snapshot
+		.get_doc(&path)?
+		.declarations
+		.get(&item.name)
+		.and_then(|decl| decl.declared_range())
+		.is_some();
+	Some(match declared {
+		true => Node::Declared(path, item.name.clone()),
+		false => Node::Builtin(item.name.clone()),
+	})
+}
+
+/// The callable declared or called at `position`.
+pub fn prepare(snapshot: &Snapshot, path: &Path, position: lsp::Position) -> Option<lsp::CallHierarchyItem> {
+	let document = snapshot.get_doc(path)?;
+	if let Some(name) = document.declaration_at(position) {
+		let decl = document.declarations.get(name)?;
+		decl.definition.as_ref()?.signature()?;
+		let node = Node::Declared(path.to_path_buf(), name.to_string());
+		return item_for(snapshot, &node, (path, decl.declared_range()?));
+	}
+	let call = calls_in(snapshot, path, &document)
+		.into_iter()
+		.find(|call| call.range.contains_position(position))?;
+	item_for(snapshot, &call.callee, (path, &call.range))
+}
+
+/// Who calls `item`, with where from. Only the file that declares it, and the files that load it,
+/// directly or through re-exports, can call a declaration.
+pub fn incoming_calls(snapshot: &Snapshot, item: &lsp::CallHierarchyItem) -> Vec<lsp::CallHierarchyIncomingCall> {
+	let node = match node_of(snapshot, item) {
+		Some(node) => node,
+		None => return vec![],
+	};
+	let files = match &node {
+		Node::Declared(file, _) => std::iter::once(file.clone()).chain(snapshot.transitive_loaders(file)).collect(),
+		Node::Builtin(_) => snapshot.indexed_files(),
+		Node::File(_) => return vec![],
+	};
+	let mut callers = BTreeMap::<Node, Vec<(PathBuf, Range)>>::new();
+	for file in files {
+		let document = match snapshot.get_doc(&file) {
+			Some(document) => document,
+			None => continue,
+		};
+		for call in calls_in(snapshot, &file, &document) {
+			if call.callee == node {
+				callers.entry(call.caller).or_default().push((file.clone(), call.range));
+			}
+		}
+	}
+	callers
+		.into_iter()
+		.filter_map(|(caller, calls)| {
+			let (file, range) = calls.first()?;
+			Some(lsp::CallHierarchyIncomingCall {
+				from: item_for(snapshot, &caller, (file, range))?,
+				from_ranges: calls.iter().map(|(_, range)| range.as_lsp_range()).collect(),
+			})
+		})
+		.collect()
+}
+
+/// What `item` calls. Builtins that can't be called, like the `native` module itself, are left out.
+pub fn outgoing_calls(
+	snapshot: &Snapshot,
+	item: &lsp::CallHierarchyItem,
+	builtins: &Builtins,
+) -> Vec<lsp::CallHierarchyOutgoingCall> {
+	let node = node_of(snapshot, item);
+	let file = match &node {
+		Some(Node::File(file)) | Some(Node::Declared(file, _)) => file,
+		_ => return vec![],
+	};
+	let document = match snapshot.get_doc(file) {
+		Some(document) => document,
+		None => return vec![],
+	};
+	let mut callees = BTreeMap::<Node, Vec<Range>>::new();
+	for call in calls_in(snapshot, file, &document) {
+		let callable = match &call.callee {
+			Node::Builtin(name) => builtins
+				.get(name)
+				.map(|definition| definition.signature().is_some())
+				.unwrap_or(true),
+			_ => true,
+		};
+		if Some(&call.caller) == node.as_ref() && callable {
+			callees.entry(call.callee).or_default().push(call.range);
+		}
+	}
+	callees
+		.into_iter()
+		.filter_map(|(callee, ranges)| {
+			Some(lsp::CallHierarchyOutgoingCall {
+				to: item_for(snapshot, &callee, (file, ranges.first()?))?,
+				from_ranges: ranges.iter().map(Range::as_lsp_range).collect(),
+			})
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::bazel::BazelWorkspace;
+	use crate::index::Documents;
+	use std::fs;
+
+	fn names<'a>(items: impl Iterator<Item = &'a lsp::CallHierarchyItem>) -> Vec<&'a str> {
+		items.map(|item| item.name.as_str()).collect()
+	}
+
+	#[test]
+	fn test_walks_calls_across_loads() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(
+			root.join("defs.bzl"),
+			"def my_library(name, srcs):\n  native.cc_library(name = name, srcs = srcs)\n  native.alias(name = 'a', actual = name)\n",
+		)
+		.unwrap();
+		fs::write(
+			root.join("macros.bzl"),
+			"load('//:defs.bzl', 'my_library')\ndef library(name, srcs):\n  my_library(name = name, srcs = srcs)\n",
+		)
+		.unwrap();
+		fs::create_dir(root.join("lib")).unwrap();
+		fs::write(
+			root.join("lib").join("BUILD"),
+			"load('//:macros.bzl', 'library')\nlibrary(name = 'lib', srcs = glob(['*.cc']))\n",
+		)
+		.unwrap();
+		let bazel = BazelWorkspace::new();
+		bazel.locate_workspace(root).unwrap();
+		let documents = Documents::default();
+		for file in &["defs.bzl", "macros.bzl", "lib/BUILD"] {
+			documents.index_single(&root.join(file), &bazel).unwrap();
+		}
+		let snapshot = documents.snapshot();
+		let defs = root.join("defs.bzl");
+
+		let cc_library = prepare(&snapshot, &defs, lsp::Position::new(1, 12)).unwrap();
+		assert_eq!((cc_library.name.as_str(), cc_library.detail.as_deref()), ("native.cc_library", Some("builtin")));
+		let my_library = incoming_calls(&snapshot, &cc_library);
+		assert_eq!(names(my_library.iter().map(|call| &call.from)), vec!["my_library"]);
+		assert_eq!(my_library[0].from_ranges, vec![lsp::Range::new(lsp::Position::new(1, 9), lsp::Position::new(1, 19))]);
+
+		let library = incoming_calls(&snapshot, &my_library[0].from);
+		assert_eq!(names(library.iter().map(|call| &call.from)), vec!["library"]);
+		assert_eq!(library[0].from.uri, lsp::Url::from_file_path(root.join("macros.bzl")).unwrap());
+		let build_files = incoming_calls(&snapshot, &library[0].from);
+		assert_eq!(names(build_files.iter().map(|call| &call.from)), vec!["BUILD"]);
+		assert_eq!(incoming_calls(&snapshot, &build_files[0].from), vec![]);
+		assert_eq!(prepare(&snapshot, &root.join("macros.bzl"), lsp::Position::new(1, 5)), Some(library[0].from.clone()));
+
+		let callees = outgoing_calls(&snapshot, &my_library[0].from, &Builtins::bundled());
+		assert_eq!(names(callees.iter().map(|call| &call.to)), vec!["native.alias", "native.cc_library"]);
+		let build_callees = outgoing_calls(&snapshot, &build_files[0].from, &Builtins::bundled());
+		assert_eq!(names(build_callees.iter().map(|call| &call.to)), vec!["library", "glob"]);
+	}
+}
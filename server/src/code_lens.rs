@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rustpython_parser::ast;
+use rustpython_parser::parser;
+use serde_json::json;
+use tower_lsp::lsp_types as lsp;
+
+use crate::bazel::{is_build_file, BazelWorkspace};
+use crate::source::Source;
+use crate::index::snapshot::Snapshot;
+use crate::links::{absolute_label, package_of};
+
+pub const BUILD: &str = "bazel.build";
+pub const TEST: &str = "bazel.test";
+pub const RUN: &str = "bazel.run";
+pub const REVERSE_DEPS: &str = "bazel.reverseDeps";
+
+/// The commands the lenses run, which take the label of a target and the uri of its BUILD file.
+pub const COMMANDS: &[&str] = &[BUILD, TEST, RUN, REVERSE_DEPS];
+
+/// The arguments to Bazel that `command` runs for `label`.
+pub fn bazel_command(command: &str, label: &str) -> Option<Vec<String>> {
+	let args = match command {
+		BUILD => vec!["build", label],
+		TEST => vec!["test", label],
+		RUN => vec!["run", label],
+		REVERSE_DEPS => return Some(vec!["query".to_string(), format!("rdeps(//..., {})", label)]),
+		_ => return None,
+	};
+	Some(args.into_iter().map(str::to_string).collect())
+}
+
+/// A call with a `name` at the top of a BUILD file.
+struct Target {
+	rule: String,
+	name: String,
+	// Where the rule is named, which is where the lenses go.
+	range: lsp::Range,
+}
+
+fn targets(contents: &str) -> Vec<Target> {
+	let (program, source) = match (parser::parse_program(contents), Source::new(contents)) {
+		(Ok(program), Some(source)) => (program, source),
+		_ => return vec![],
+	};
+	program
+		.statements
+		.iter()
+		.filter_map(|statement| {
+			let (function, keywords) = match &statement.node {
+				ast::StatementType::Expression { expression } => match &expression.node {
+					ast::ExpressionType::Call { function, keywords, .. } => (function, keywords),
+					_ => return None,
+				},
+				_ => return None,
+			};
+			let rule = match &function.node {
+				ast::ExpressionType::Identifier { name } => name.clone(),
+				_ => return None,
+			};
+			let name = keywords.iter().find_map(|keyword| match (&keyword.name, &keyword.value.node) {
+				(Some(keyword), ast::ExpressionType::String { value: ast::StringGroup::Constant { value } }) if keyword == "name" => {
+					Some(value.clone())
+				}
+				_ => None,
+			})?;
+			let (row, column) = source.start_of(source.statement_start(statement)?);
+			let start = lsp::Position::new(row as u32 - 1, column as u32 - 1);
+			let end = lsp::Position::new(start.line, start.character + rule.len() as u32);
+			Some(Target {
+				rule,
+				name,
+				range: lsp::Range::new(start, end),
+			})
+		})
+		.collect()
+}
+
+/// How many times each label is referred to in the indexed files of `workspace`.
+fn reference_counts(snapshot: &Snapshot, workspace: &BazelWorkspace) -> HashMap<String, usize> {
+	let mut counts = HashMap::new();
+	for file in snapshot.indexed_files() {
+		let document = match snapshot.get_doc(&file) {
+			Some(document) if !document.labels.is_empty() => document,
+			_ => continue,
+		};
+		let package = match package_of(workspace, &file) {
+			Some(package) => package,
+			None => continue,
+		};
+		for label in &document.labels {
+			if let Some(label) = absolute_label(&label.label, &package) {
+				*counts.entry(label).or_insert(0) += 1;
+			}
+		}
+	}
+	counts
+}
+
+/// Above each target of the BUILD file `path`, lenses to build it, test it if it's a test or a test suite,
+/// run it if it's a test or a binary, list its reverse dependencies, and count the references to it.
+pub fn code_lenses(snapshot: &Snapshot, workspace: &BazelWorkspace, path: &Path, contents: &str) -> Vec<lsp::CodeLens> {
+	let (package, uri) = match (package_of(workspace, path), lsp::Url::from_file_path(path)) {
+		(Some(package), Ok(uri)) if is_build_file(path) => (package, uri),
+		_ => return vec![],
+	};
+	let references = reference_counts(snapshot, workspace);
+	let mut lenses = vec![];
+	for target in targets(contents) {
+		let label = format!("{}:{}", package, target.name);
+		let mut commands = vec![("build", BUILD)];
+		if target.rule.ends_with("_test") || target.rule == "test_suite" {
+			commands.push(("test", TEST));
+		}
+		// A test suite only groups tests, which `bazel run` can't run.
+		if target.rule.ends_with("_test") || target.rule.ends_with("_binary") {
+			commands.push(("run", RUN));
+		}
+		commands.push(("show reverse deps", REVERSE_DEPS));
+		for (title, command) in commands {
+			lenses.push(lsp::CodeLens {
+				range: target.range,
+				command: Some(lsp::Command::new(
+					title.to_string(),
+					command.to_string(),
+					Some(vec![json!(label), json!(uri)]),
+				)),
+				data: None,
+			});
+		}
+		// Only counts, so there's nothing to run.
+		let count = references.get(&label).copied().unwrap_or(0);
+		lenses.push(lsp::CodeLens {
+			range: target.range,
+			command: Some(lsp::Command::new(
+				format!("{} reference{}", count, if count == 1 { "" } else { "s" }),
+				String::new(),
+				None,
+			)),
+			data: None,
+		});
+	}
+	lenses
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::index::Documents;
+	use std::fs;
+
+	#[test]
+	fn test_lenses_above_targets() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::create_dir(root.join("lib")).unwrap();
+		let contents = "cc_library(name = 'lib', srcs = ['lib.cc'])\n\ncc_test(\n    name = 'lib_test',\n    deps = [':lib'],\n)\ntest_suite(name = 'tests')\nexports_files(['lib.h'])\n";
+		fs::write(root.join("lib").join("BUILD"), contents).unwrap();
+		fs::write(root.join("BUILD"), "cc_binary(name = 'main', deps = ['//lib'])\n").unwrap();
+		let bazel = BazelWorkspace::new();
+		bazel.locate_workspace(root).unwrap();
+		let documents = Documents::default();
+		for file in &["BUILD", "lib/BUILD"] {
+			documents.index_single(&root.join(file), &bazel).unwrap();
+		}
+
+		let lib = root.join("lib").join("BUILD");
+		let lenses = code_lenses(&documents.snapshot(), &bazel, &lib, contents)
+			.into_iter()
+			.map(|lens| {
+				let command = lens.command.unwrap();
+				(lens.range.start.line, lens.range.end.character, command.title, command.command, command.arguments)
+			})
+			.collect::<Vec<_>>();
+		let arguments = |label: &str| Some(vec![json!(label), json!(lsp::Url::from_file_path(&lib).unwrap())]);
+		let lens = |line, end, title: &str, command: &str, label| (line, end, title.to_string(), command.to_string(), arguments(label));
+		assert_eq!(
+			lenses,
+			vec![
+				lens(0, 10, "build", BUILD, "//lib:lib"),
+				lens(0, 10, "show reverse deps", REVERSE_DEPS, "//lib:lib"),
+				(0, 10, "2 references".to_string(), String::new(), None),
+				lens(2, 7, "build", BUILD, "//lib:lib_test"),
+				lens(2, 7, "test", TEST, "//lib:lib_test"),
+				lens(2, 7, "run", RUN, "//lib:lib_test"),
+				lens(2, 7, "show reverse deps", REVERSE_DEPS, "//lib:lib_test"),
+				(2, 7, "0 references".to_string(), String::new(), None),
+				lens(6, 10, "build", BUILD, "//lib:tests"),
+				lens(6, 10, "test", TEST, "//lib:tests"),
+				lens(6, 10, "show reverse deps", REVERSE_DEPS, "//lib:tests"),
+				(6, 10, "0 references".to_string(), String::new(), None),
+			]
+		);
+		assert_eq!(code_lenses(&documents.snapshot(), &bazel, &root.join("defs.bzl"), contents), vec![]);
+		assert_eq!(
+			bazel_command(REVERSE_DEPS, "//lib:lib"),
+			Some(vec!["query".to_string(), "rdeps(//..., //lib:lib)".to_string()])
+		);
+	}
+}
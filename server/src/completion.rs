@@ -0,0 +1,179 @@
+use std::path::Path;
+
+use tower_lsp::lsp_types as lsp;
+
+use crate::builtins::Builtins;
+use crate::call_context::CallContext;
+use crate::index::definition::Definition;
+use crate::index::indexed_document::IndexedDocument;
+use crate::index::signature::Parameter;
+use crate::index::snapshot::Snapshot;
+
+// Macros can wrap macros that wrap macros, but not forever.
+const MAX_FORWARDING_DEPTH: usize = 8;
+
+/// The keywords `function_name` accepts when called from `doc`.
+///
+/// For macros that pass their `**kwargs` on, this includes the keywords of the function they pass them to.
+pub fn parameters_of(
+	snapshot: &Snapshot,
+	builtins: &Builtins,
+	doc: &Path,
+	function_name: &str,
+) -> Vec<Parameter> {
+	parameters_with_depth(snapshot, builtins, doc, function_name, MAX_FORWARDING_DEPTH)
+}
+
+fn parameters_with_depth(
+	snapshot: &Snapshot,
+	builtins: &Builtins,
+	doc: &Path,
+	function_name: &str,
+	depth: usize,
+) -> Vec<Parameter> {
+	if depth == 0 {
+		return vec![];
+	}
+	let definition = match snapshot.resolve_declaration(doc, function_name) {
+		Some((file, decl)) => decl.definition.map(|definition| (file, definition)),
+		None => builtins
+			.get(function_name)
+			.map(|definition| (doc.to_path_buf(), definition.clone())),
+	};
+	let (file, definition) = match definition {
+		Some(definition) => definition,
+		None => return vec![],
+	};
+	let mut parameters = definition
+		.signature()
+		.map(|signature| signature.parameters)
+		.unwrap_or_default()
+		.into_iter()
+		// Neither `*args` and `**kwargs` nor private attributes can be passed by keyword.
+		.filter(|param| !param.name.starts_with('*') && !param.name.starts_with('_'))
+		.collect::<Vec<_>>();
+	if let Definition::Function {
+		forwards_kwargs_to: Some(target),
+		..
+	} = &definition
+	{
+		for param in parameters_with_depth(snapshot, builtins, &file, target, depth - 1) {
+			if !parameters.iter().any(|known| known.name == param.name) {
+				parameters.push(param);
+			}
+		}
+	}
+	parameters
+}
+
+/// Completes the keywords of the call the cursor is in, leaving out the ones already there.
+pub fn attribute_completions(
+	snapshot: &Snapshot,
+	builtins: &Builtins,
+	doc: &Path,
+	call: &CallContext,
+) -> Vec<lsp::CompletionItem> {
+	if !call.at_keyword {
+		return vec![];
+	}
+	parameters_of(snapshot, builtins, doc, &call.function_name)
+		.iter()
+		.filter(|param| !call.used_keywords.contains(&param.name))
+		.map(Parameter::as_lsp_completion_item)
+		.collect()
+}
+
+fn name_completion(name: String, definition: Option<&Definition>) -> lsp::CompletionItem {
+	let callable = definition.and_then(Definition::signature).is_some();
+	lsp::CompletionItem {
+		kind: Some(if callable {
+			lsp::CompletionItemKind::FUNCTION
+		} else {
+			lsp::CompletionItemKind::VARIABLE
+		}),
+		documentation: definition
+			.and_then(Definition::doc)
+			.map(|doc| lsp::Documentation::String(doc.trim().to_string())),
+		label: name,
+		..lsp::CompletionItem::default()
+	}
+}
+
+/// Completes the name being typed: a member of a builtin module after a `.`, a global otherwise.
+pub fn name_completions(document: Option<&IndexedDocument>, builtins: &Builtins, typed: &str) -> Vec<lsp::CompletionItem> {
+	if let Some(dot) = typed.rfind('.') {
+		return builtins
+			.members(&typed[..dot])
+			.into_iter()
+			.map(|(name, definition)| name_completion(name, definition))
+			.collect();
+	}
+	let mut items = document
+		.map(|document| {
+			let mut declared = document.declarations.iter().collect::<Vec<_>>();
+			declared.sort_by(|a, b| a.0.cmp(b.0));
+			declared
+				.into_iter()
+				.map(|(name, decl)| name_completion(name.clone(), decl.definition.as_ref()))
+				.collect::<Vec<_>>()
+		})
+		.unwrap_or_default();
+	let globals = builtins
+		.globals()
+		.into_iter()
+		.filter(|(name, _)| !items.iter().any(|item| &item.label == name))
+		.map(|(name, definition)| name_completion(name, definition))
+		.collect::<Vec<_>>();
+	items.extend(globals);
+	items
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::bazel::BazelWorkspace;
+	use crate::call_context::call_at;
+	use crate::index::Documents;
+	use std::fs;
+
+	#[test]
+	fn test_complete_attributes_through_kwargs() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		let build = root.join("BUILD");
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(
+			root.join("defs.bzl"),
+			"my_rule = rule(implementation = _impl, attrs = {'srcs': attr.label_list(), 'deps': attr.label_list(), '_tool': attr.label()})\n\
+			 def my_macro(name, visibility = None, **kwargs):\n  my_rule(name = name, **kwargs)\n",
+		)
+		.unwrap();
+		let contents = "load('//:defs.bzl', 'my_macro')\nmy_macro(name = 'a', )\n";
+		fs::write(&build, contents).unwrap();
+		let bazel = BazelWorkspace::new();
+		bazel.locate_workspace(root).unwrap();
+		let documents = Documents::default();
+		documents.refresh_doc(&build, &bazel).unwrap();
+
+		let call = call_at(contents, lsp::Position::new(1, 20)).unwrap();
+		let labels = attribute_completions(&documents.snapshot(), &Builtins::bundled(), &build, &call)
+			.into_iter()
+			.map(|item| item.label)
+			.collect::<Vec<_>>();
+
+		assert_eq!(labels, vec!["visibility", "srcs", "deps"]);
+	}
+
+	#[test]
+	fn test_complete_builtin_names() {
+		let labels = |typed| {
+			name_completions(None, &Builtins::bundled(), typed)
+				.into_iter()
+				.map(|item| item.label)
+				.collect::<Vec<_>>()
+		};
+		assert!(labels("attr.str").contains(&"string_list".to_string()));
+		assert!(!labels("attr.str").contains(&"glob".to_string()));
+		assert!(labels("gl").contains(&"glob".to_string()));
+	}
+}
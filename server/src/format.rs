@@ -0,0 +1,1131 @@
+use std::path::Path;
+
+use rustpython_parser::ast;
+use rustpython_parser::parser;
+use rustpython_parser::token::Tok;
+use tower_lsp::lsp_types as lsp;
+
+use crate::source::{Comment, Pos, Source};
+
+// How buildifier orders the arguments of targets: these first, then everything else, then these.
+const ATTR_PRIORITIES: &[(&str, i32)] = &[
+	("name", -99),
+	("gwt_name", -98),
+	("package_name", -97),
+	("visible_node_name", -96),
+	("size", -95),
+	("timeout", -94),
+	("testonly", -93),
+	("src", -92),
+	("srcdir", -91),
+	("srcs", -90),
+	("out", -89),
+	("outs", -88),
+	("hdrs", -87),
+	("has_services", -86),
+	("include", -85),
+	("of", -84),
+	("baseline", -83),
+	("destdir", 1),
+	("exports", 2),
+	("runtime_deps", 3),
+	("deps", 4),
+	("implementation", 5),
+	("implements", 6),
+	("alwayslink", 7),
+];
+
+// The attributes of targets whose lists of strings buildifier sorts.
+const SORTED_ATTRS: &[&str] = &[
+	"compatible_with",
+	"data",
+	"deps",
+	"exports",
+	"hdrs",
+	"implementation_deps",
+	"restricted_to",
+	"runtime_deps",
+	"srcs",
+	"tests",
+	"textual_hdrs",
+	"visibility",
+];
+
+/// Which of buildifier's modes a file is formatted in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+	/// BUILD files, made of targets.
+	Build,
+	/// WORKSPACE and MODULE.bazel files, whose calls are laid out like targets but not reordered.
+	Workspace,
+	/// `.bzl` files, made of definitions.
+	Bzl,
+}
+
+impl Dialect {
+	pub fn of(path: &Path) -> Self {
+		match (path.file_name().and_then(|name| name.to_str()), path.extension()) {
+			(Some("WORKSPACE") | Some("WORKSPACE.bazel") | Some("MODULE.bazel"), _) => Dialect::Workspace,
+			(_, Some(extension)) if extension == "bzl" => Dialect::Bzl,
+			_ => Dialect::Build,
+		}
+	}
+}
+
+fn normalize_quotes(literal: &str) -> String {
+	let quote = match literal.find(['\'', '"']) {
+		Some(quote) => quote,
+		None => return literal.to_string(),
+	};
+	let (prefix, quoted) = literal.split_at(quote);
+	let raw = prefix.contains(['r', 'R']);
+	if quoted.starts_with("'''") && quoted.len() >= 6 {
+		let body = &quoted[3..quoted.len() - 3];
+		if body.contains('"') {
+			return literal.to_string();
+		}
+		return format!("{}\"\"\"{}\"\"\"", prefix, body);
+	}
+	if !quoted.starts_with('\'') || quoted.len() < 2 {
+		return literal.to_string();
+	}
+	let body = &quoted[1..quoted.len() - 1];
+	if raw {
+		return match body.contains('"') {
+			true => literal.to_string(),
+			false => format!("{}\"{}\"", prefix, body),
+		};
+	}
+	let mut normalized = format!("{}\"", prefix);
+	let mut chars = body.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => match chars.next() {
+				Some('\'') => normalized.push('\''),
+				Some(escaped) => {
+					normalized.push('\\');
+					normalized.push(escaped);
+				}
+				None => normalized.push('\\'),
+			},
+			'"' => normalized.push_str("\\\""),
+			c => normalized.push(c),
+		}
+	}
+	normalized.push('"');
+	normalized
+}
+
+/// How buildifier compares the strings in the lists it sorts: files, then local labels, then absolute labels.
+fn sort_key(value: &str) -> (u8, Vec<&str>) {
+	let phase = if value.starts_with(':') {
+		1
+	} else if value.starts_with("//") {
+		2
+	} else if value.starts_with('@') {
+		3
+	} else {
+		0
+	};
+	(phase, value.split([':', '.']).collect())
+}
+
+fn string_value(expr: &ast::Expression) -> Option<&str> {
+	match &expr.node {
+		ast::ExpressionType::String {
+			value: ast::StringGroup::Constant { value },
+		} => Some(value),
+		_ => None,
+	}
+}
+
+fn is_load(statement: &ast::Statement) -> bool {
+	match &statement.node {
+		ast::StatementType::Expression { expression } => match &expression.node {
+			ast::ExpressionType::Call { function, .. } => {
+				matches!(&function.node, ast::ExpressionType::Identifier { name } if name == "load")
+			}
+			_ => false,
+		},
+		_ => false,
+	}
+}
+
+fn binary_operator(op: &ast::Operator) -> &'static str {
+	match op {
+		ast::Operator::Add => "+",
+		ast::Operator::Sub => "-",
+		ast::Operator::Mult => "*",
+		ast::Operator::MatMult => "@",
+		ast::Operator::Div => "/",
+		ast::Operator::Mod => "%",
+		ast::Operator::Pow => "**",
+		ast::Operator::LShift => "<<",
+		ast::Operator::RShift => ">>",
+		ast::Operator::BitOr => "|",
+		ast::Operator::BitXor => "^",
+		ast::Operator::BitAnd => "&",
+		ast::Operator::FloorDiv => "//",
+	}
+}
+
+fn comparison(op: &ast::Comparison) -> &'static str {
+	match op {
+		ast::Comparison::Equal => "==",
+		ast::Comparison::NotEqual => "!=",
+		ast::Comparison::Less => "<",
+		ast::Comparison::LessOrEqual => "<=",
+		ast::Comparison::Greater => ">",
+		ast::Comparison::GreaterOrEqual => ">=",
+		ast::Comparison::In => "in",
+		ast::Comparison::NotIn => "not in",
+		ast::Comparison::Is => "is",
+		ast::Comparison::IsNot => "is not",
+	}
+}
+
+/// Layout decisions a parent makes for the expression it prints.
+#[derive(Debug, Clone, Copy, Default)]
+struct Style {
+	// One element per line, even if it was written on one line.
+	multi_line: bool,
+	// Sort a list of strings.
+	sorted: bool,
+	// The top-level call of a BUILD, WORKSPACE or MODULE.bazel file, e.g. a target.
+	target: bool,
+}
+
+/// Something separated by commas, e.g. an element of a list or an argument of a call.
+enum Item<'e> {
+	Expr(&'e ast::Expression, Style),
+	Keyword(&'e ast::Keyword, Style),
+	Entry(Option<&'e ast::Expression>, &'e ast::Expression),
+	Param(&'e ast::Parameter, &'static str, Option<&'e ast::Expression>),
+}
+
+/// The text a top-level statement, with the comments before it, was formatted to.
+#[derive(Debug)]
+struct Chunk {
+	rows: (usize, usize),
+	output: (usize, usize),
+}
+
+struct Printer<'a> {
+	source: &'a Source<'a>,
+	dialect: Dialect,
+	out: String,
+	indent: usize,
+	// How many brackets we are in, where lines can break.
+	depth: usize,
+	// The first comment we haven't printed yet.
+	next_comment: usize,
+	// Whether nothing was printed yet in the current block, which doesn't start with a blank line.
+	fresh: bool,
+	// Whether the next line starts after a blank line whatever the source has, if buildifier decides that.
+	blank_line: Option<bool>,
+	// The last row of the source we printed something from.
+	row: usize,
+	chunks: Vec<Chunk>,
+}
+
+const END: Pos = (usize::MAX, usize::MAX);
+
+impl<'a> Printer<'a> {
+	fn new(source: &'a Source<'a>, dialect: Dialect) -> Self {
+		Printer {
+			source,
+			dialect,
+			out: String::new(),
+			indent: 0,
+			depth: 0,
+			next_comment: 0,
+			fresh: true,
+			blank_line: None,
+			row: 0,
+			chunks: vec![],
+		}
+	}
+
+	fn write(&mut self, text: &str) {
+		self.out.push_str(text);
+		self.fresh = false;
+	}
+
+	fn newline(&mut self) {
+		let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+		self.out.truncate(trimmed);
+		if !self.out.is_empty() {
+			self.out.push('\n');
+		}
+		self.out.push_str(&"    ".repeat(self.indent));
+	}
+
+	/// Starts a line for what was at `row`, keeping one blank line if there was any before it.
+	fn line_break(&mut self, row: usize) {
+		let blank = match self.blank_line.take() {
+			Some(blank) => blank,
+			None => row > self.row + 1 && self.source.is_blank(row - 1),
+		};
+		if !self.fresh && blank {
+			self.newline();
+		}
+		self.newline();
+		self.fresh = false;
+		self.row = self.row.max(row);
+	}
+
+	fn next_comment(&self) -> Option<&'a Comment> {
+		self.source.comments.get(self.next_comment)
+	}
+
+	/// Prints the comments on lines of their own before `before`, stopping at any that's left of `min_column`.
+	/// Fails on comments that ended a line of code that we didn't print them after.
+	fn own_line_comments(&mut self, before: Pos, min_column: usize) -> Option<()> {
+		while let Some(comment) = self.next_comment().filter(|comment| comment.pos < before) {
+			if !comment.own_line {
+				return None;
+			}
+			if comment.pos.1 < min_column {
+				break;
+			}
+			self.line_break(comment.pos.0);
+			self.write(&comment.text);
+			self.next_comment += 1;
+		}
+		Some(())
+	}
+
+	/// Prints the comments that end the line we just printed.
+	fn trailing_comments(&mut self, before: Pos) {
+		while let Some(comment) = self.next_comment().filter(|comment| comment.pos < before && !comment.own_line) {
+			self.write("  ");
+			self.write(&comment.text);
+			self.next_comment += 1;
+		}
+	}
+
+	fn has_comments(&self, first: usize, last: usize) -> bool {
+		let (from, to) = (self.source.tokens[first].start, self.source.tokens[last].end);
+		self.source.comments[self.next_comment..]
+			.iter()
+			.any(|comment| comment.pos > from && comment.pos < to)
+	}
+
+	fn top_level(&mut self, statements: &[ast::Statement]) -> Option<()> {
+		for (i, statement) in statements.iter().enumerate() {
+			let until = match statements.get(i + 1) {
+				Some(next) => self.source.tokens[self.source.statement_start(next)?].start,
+				None => END,
+			};
+			let first = self.source.statement_start(statement)?;
+			let first_row = match self.next_comment() {
+				Some(comment) if comment.pos < self.source.tokens[first].start => comment.pos.0,
+				_ => self.source.tokens[first].start.0,
+			};
+			let blank_line = match i {
+				0 => None,
+				_ => self.separation(&statements[i - 1], statement, first_row < self.source.tokens[first].start.0),
+			};
+			self.blank_line = blank_line;
+			let (mark, next_comment) = (self.out.len(), self.next_comment);
+			if self.statement(statement, until).is_none() {
+				// Rather than lose what we can't reproduce, we leave the statement as it is.
+				self.out.truncate(mark);
+				self.next_comment = next_comment;
+				self.indent = 0;
+				self.depth = 0;
+				self.fresh = mark == 0;
+				self.blank_line = blank_line;
+				self.verbatim(first, until);
+			}
+			let start = mark + self.out[mark..].chars().take_while(|&c| c == '\n').count();
+			self.row = self.source.last_row(first, until);
+			self.chunks.push(Chunk {
+				rows: (first_row, self.row),
+				output: (start, self.out.len()),
+			});
+		}
+		self.own_line_comments(END, 0);
+		// Comments that end a line we left as it was are printed with it.
+		while let Some(comment) = self.next_comment() {
+			self.line_break(comment.pos.0);
+			self.write(&comment.text);
+			self.next_comment += 1;
+		}
+		if !self.out.is_empty() {
+			self.out.push('\n');
+		}
+		Some(())
+	}
+
+	/// Whether buildifier's `compactStmt` separates the top-level `statement` from the one before it
+	/// with a blank line, or not, or keeps what the source has (None).
+	fn separation(&self, previous: &ast::Statement, statement: &ast::Statement, commented: bool) -> Option<bool> {
+		let is_def = |statement: &ast::Statement| matches!(statement.node, ast::StatementType::FunctionDef { .. });
+		match (is_load(previous), is_load(statement)) {
+			_ if commented => Some(true),
+			(true, true) => Some(false),
+			(true, false) | (false, true) => Some(true),
+			_ if self.dialect != Dialect::Bzl => Some(true),
+			_ if is_def(previous) || is_def(statement) => Some(true),
+			_ => None,
+		}
+	}
+
+	fn verbatim(&mut self, first: usize, until: Pos) {
+		let start = self.source.tokens[first].start;
+		while let Some(comment) = self.next_comment().filter(|comment| comment.pos < start) {
+			self.line_break(comment.pos.0);
+			self.write(&comment.text);
+			self.next_comment += 1;
+		}
+		let last_row = self.source.last_row(first, until);
+		self.line_break(start.0);
+		self.write(&self.source.lines[start.0 - 1..last_row].join("\n"));
+		while self.next_comment().map(|comment| comment.pos.0 <= last_row).unwrap_or(false) {
+			self.next_comment += 1;
+		}
+	}
+
+	/// Prints `statement`, and everything before it that's not in the statement before, up to `until`.
+	fn statement(&mut self, statement: &ast::Statement, until: Pos) -> Option<()> {
+		let first = self.source.statement_start(statement)?;
+		let start = self.source.tokens[first].start;
+		self.own_line_comments(start, 0)?;
+		self.line_break(start.0);
+		match &statement.node {
+			ast::StatementType::Expression { expression } => {
+				let target = self.dialect != Dialect::Bzl && self.indent == 0;
+				self.expr(expression, Style { target, ..Style::default() })?
+			}
+			ast::StatementType::Assign { targets, value } => {
+				for target in targets {
+					self.bare_tuple(target)?;
+					self.write(" = ");
+				}
+				self.bare_tuple(value)?;
+			}
+			ast::StatementType::AugAssign { target, op, value } => {
+				self.expr(target, Style::default())?;
+				self.write(&format!(" {}= ", binary_operator(op)));
+				self.expr(value, Style::default())?;
+			}
+			ast::StatementType::Return { value } => {
+				self.write("return");
+				if let Some(value) = value {
+					self.write(" ");
+					self.bare_tuple(value)?;
+				}
+			}
+			ast::StatementType::Pass => self.write("pass"),
+			ast::StatementType::Break => self.write("break"),
+			ast::StatementType::Continue => self.write("continue"),
+			ast::StatementType::FunctionDef {
+				name,
+				args,
+				body,
+				decorator_list,
+				returns: None,
+				..
+			} if decorator_list.is_empty() => {
+				self.write(&format!("def {}", name));
+				// `def`, the name, then the parameters.
+				self.parameters(args, first + 2)?;
+				self.write(":");
+				return self.block(first, body, until);
+			}
+			ast::StatementType::If { test, body, orelse } => {
+				let keyword = if self.source.is(first, &Tok::Elif) { "elif" } else { "if" };
+				self.write(&format!("{} ", keyword));
+				self.expr(test, Style::default())?;
+				self.write(":");
+				let orelse = match orelse {
+					Some(orelse) if !orelse.is_empty() => orelse,
+					_ => return self.block(first, body, until),
+				};
+				let orelse_first = self.source.statement_start(&orelse[0])?;
+				if self.source.is(orelse_first, &Tok::Elif) {
+					self.block(first, body, self.source.tokens[orelse_first].start)?;
+					return self.statement(&orelse[0], until);
+				}
+				let else_token = (first..orelse_first).rev().find(|&index| self.source.is(index, &Tok::Else))?;
+				self.block(first, body, self.source.tokens[else_token].start)?;
+				self.own_line_comments(self.source.tokens[else_token].start, 0)?;
+				self.line_break(self.source.tokens[else_token].start.0);
+				self.write("else:");
+				return self.block(else_token, orelse, until);
+			}
+			ast::StatementType::For {
+				target,
+				iter,
+				body,
+				orelse: None,
+				..
+			} => {
+				self.write("for ");
+				self.bare_tuple(target)?;
+				self.write(" in ");
+				self.expr(iter, Style::default())?;
+				self.write(":");
+				return self.block(first, body, until);
+			}
+			_ => return None,
+		}
+		let end = self.source.end_of_line(first);
+		self.trailing_comments(self.source.tokens[end].start);
+		match self.next_comment() {
+			Some(comment) if comment.pos < self.source.tokens[end].start => None,
+			_ => Some(()),
+		}
+	}
+
+	/// Prints the body of the statement whose header starts at `header`, indented.
+	fn block(&mut self, header: usize, body: &[ast::Statement], until: Pos) -> Option<()> {
+		let end = self.source.end_of_line(header);
+		self.trailing_comments(self.source.tokens[end].start);
+		self.indent += 1;
+		self.fresh = true;
+		let column = self.source.start_of(self.source.statement_start(body.first()?)?).1;
+		for (i, statement) in body.iter().enumerate() {
+			let next = match body.get(i + 1) {
+				Some(next) => self.source.tokens[self.source.statement_start(next)?].start,
+				None => until,
+			};
+			self.statement(statement, next)?;
+			self.row = self.source.last_row(self.source.statement_start(statement)?, next);
+		}
+		// Comments after the last statement stay in the block if they are indented like it.
+		self.own_line_comments(until, column)?;
+		self.indent -= 1;
+		Some(())
+	}
+
+	fn parameters(&mut self, args: &ast::Parameters, open: usize) -> Option<()> {
+		if !self.source.is(open, &Tok::Lpar) {
+			return None;
+		}
+		let mut items = vec![];
+		let first_default = args.args.len() - args.defaults.len();
+		for (i, arg) in args.args.iter().enumerate() {
+			let default = i.checked_sub(first_default).and_then(|i| args.defaults.get(i));
+			items.push(Item::Param(arg, "", default));
+		}
+		match &args.vararg {
+			ast::Varargs::Named(arg) => items.push(Item::Param(arg, "*", None)),
+			ast::Varargs::Unnamed => return None,
+			ast::Varargs::None => {}
+		}
+		for (arg, default) in args.kwonlyargs.iter().zip(&args.kw_defaults) {
+			items.push(Item::Param(arg, "", default.as_ref()));
+		}
+		if let ast::Varargs::Named(arg) = &args.kwarg {
+			items.push(Item::Param(arg, "**", None));
+		}
+		let close = *self.source.closing.get(&open)?;
+		self.bracketed(("(", ")"), open, close, &items, Style::default())
+	}
+
+	/// Prints the tuples that don't need parentheses, like the targets of assignments, without any.
+	fn bare_tuple(&mut self, expr: &ast::Expression) -> Option<()> {
+		match &expr.node {
+			ast::ExpressionType::Tuple { elements } if !elements.is_empty() && self.source.outer(expr)?.2 == 0 => {
+				for (i, element) in elements.iter().enumerate() {
+					if i > 0 {
+						self.write(", ");
+					}
+					self.expr(element, Style::default())?;
+				}
+				if elements.len() == 1 {
+					self.write(",");
+				}
+				Some(())
+			}
+			_ => self.expr(expr, Style::default()),
+		}
+	}
+
+	fn expr(&mut self, expr: &ast::Expression, style: Style) -> Option<()> {
+		let (first, _, mut parens) = self.source.outer(expr)?;
+		// The parentheses of a tuple are its own.
+		if let ast::ExpressionType::Tuple { elements } = &expr.node {
+			if !elements.is_empty() && parens > 0 {
+				parens -= 1;
+			}
+		}
+		self.parenthesized(expr, first, parens, style)
+	}
+
+	fn parenthesized(&mut self, expr: &ast::Expression, first: usize, parens: usize, style: Style) -> Option<()> {
+		if parens == 0 {
+			return self.bare(expr, style);
+		}
+		let multi_line = self.source.tokens[first + 1].start.0 > self.source.tokens[first].start.0;
+		self.write("(");
+		self.depth += 1;
+		if multi_line {
+			self.indent += 1;
+			self.newline();
+		}
+		self.parenthesized(expr, first + 1, parens - 1, style)?;
+		if multi_line {
+			self.indent -= 1;
+			self.newline();
+		}
+		self.depth -= 1;
+		self.write(")");
+		Some(())
+	}
+
+	/// Continues on the next line if the source did, between the tokens `before` and `after`.
+	fn operand_break(&mut self, before: usize, after: usize) -> Option<()> {
+		if self.source.tokens[after].start.0 == self.source.tokens[before].end.0 {
+			self.write(" ");
+			return Some(());
+		}
+		// Lines can only break in brackets.
+		if self.depth == 0 {
+			return None;
+		}
+		self.indent += 1;
+		self.newline();
+		self.indent -= 1;
+		Some(())
+	}
+
+	fn bare(&mut self, expr: &ast::Expression, style: Style) -> Option<()> {
+		match &expr.node {
+			ast::ExpressionType::Identifier { name } => self.write(name),
+			ast::ExpressionType::True => self.write("True"),
+			ast::ExpressionType::False => self.write("False"),
+			ast::ExpressionType::None => self.write("None"),
+			ast::ExpressionType::Number { .. } => {
+				let token = &self.source.tokens[self.source.token_at(&expr.location)?];
+				self.write(&self.source.slice(token.start, token.end));
+			}
+			ast::ExpressionType::String {
+				value: ast::StringGroup::Constant { .. },
+			} => {
+				// As written, but in double quotes where that doesn't change the string.
+				let token = self.source.token_at(&expr.location)?;
+				let literal = self.source.slice(self.source.start_of(token), self.source.tokens[token].end);
+				self.write(&normalize_quotes(&literal));
+			}
+			ast::ExpressionType::Unop { op, a } => {
+				self.write(match op {
+					ast::UnaryOperator::Pos => "+",
+					ast::UnaryOperator::Neg => "-",
+					ast::UnaryOperator::Not => "not ",
+					ast::UnaryOperator::Inv => "~",
+				});
+				self.expr(a, Style::default())?;
+			}
+			ast::ExpressionType::Binop { a, op, b } => {
+				self.expr(a, Style::default())?;
+				self.write(&format!(" {}", binary_operator(op)));
+				self.operand_break(self.source.outer(a)?.1, self.source.outer(b)?.0)?;
+				self.expr(b, Style::default())?;
+			}
+			ast::ExpressionType::BoolOp { op, values } => {
+				let op = match op {
+					ast::BooleanOperator::And => "and",
+					ast::BooleanOperator::Or => "or",
+				};
+				for (i, value) in values.iter().enumerate() {
+					if i > 0 {
+						self.write(&format!(" {}", op));
+						self.operand_break(self.source.outer(&values[i - 1])?.1, self.source.outer(value)?.0)?;
+					}
+					self.expr(value, Style::default())?;
+				}
+			}
+			ast::ExpressionType::Compare { vals, ops } => {
+				self.expr(vals.first()?, Style::default())?;
+				for (op, value) in ops.iter().zip(&vals[1..]) {
+					self.write(&format!(" {} ", comparison(op)));
+					self.expr(value, Style::default())?;
+				}
+			}
+			ast::ExpressionType::Attribute { value, name } => {
+				self.expr(value, Style::default())?;
+				self.write(&format!(".{}", name));
+			}
+			ast::ExpressionType::Subscript { a, b } => {
+				self.expr(a, Style::default())?;
+				self.write("[");
+				self.depth += 1;
+				match &b.node {
+					ast::ExpressionType::Slice { elements } => self.slice(elements)?,
+					_ => self.expr(b, Style::default())?,
+				}
+				self.depth -= 1;
+				self.write("]");
+			}
+			ast::ExpressionType::Call {
+				function,
+				args,
+				keywords,
+			} => self.call(expr, function, args, keywords, style)?,
+			ast::ExpressionType::List { elements } => {
+				let mut items = elements.iter().collect::<Vec<_>>();
+				let open = self.source.token_at(&expr.location)?;
+				let close = *self.source.closing.get(&open)?;
+				// We don't move comments or blank lines around, so lists that have any keep their order.
+				let untouched = self.has_comments(open, close)
+					|| (self.source.tokens[open].start.0..self.source.tokens[close].start.0).any(|row| self.source.is_blank(row + 1));
+				if style.sorted && !untouched {
+					if let Some(mut strings) = elements.iter().map(|e| Some((string_value(e)?, e))).collect::<Option<Vec<_>>>() {
+						strings.sort_by(|a, b| (sort_key(a.0), a.0).cmp(&(sort_key(b.0), b.0)));
+						items = strings.into_iter().map(|(_, e)| e).collect();
+					}
+				}
+				let items = items.into_iter().map(|e| Item::Expr(e, Style::default())).collect::<Vec<_>>();
+				let style = Style {
+					multi_line: style.multi_line && items.len() > 1,
+					..Style::default()
+				};
+				self.bracketed(("[", "]"), open, close, &items, style)?;
+			}
+			ast::ExpressionType::Tuple { elements } => {
+				if elements.is_empty() {
+					self.write("()");
+					return Some(());
+				}
+				let (first, last, _) = self.source.outer(expr)?;
+				let items = elements.iter().map(|e| Item::Expr(e, Style::default())).collect::<Vec<_>>();
+				let (open, close) = (first, last);
+				let single_line = self.source.tokens[open].start.0 == self.source.tokens[close].start.0;
+				if elements.len() == 1 && single_line {
+					self.write("(");
+					self.depth += 1;
+					self.expr(&elements[0], Style::default())?;
+					self.depth -= 1;
+					self.write(",)");
+				} else {
+					self.bracketed(("(", ")"), open, close, &items, Style::default())?;
+				}
+			}
+			ast::ExpressionType::Dict { elements } => {
+				let open = self.source.token_at(&expr.location)?;
+				let close = *self.source.closing.get(&open)?;
+				let items = elements
+					.iter()
+					.map(|(key, value)| Item::Entry(key.as_ref(), value))
+					.collect::<Vec<_>>();
+				self.bracketed(("{", "}"), open, close, &items, Style::default())?;
+			}
+			ast::ExpressionType::Comprehension { kind, generators } => {
+				let (open, close) = match **kind {
+					ast::ComprehensionKind::List { .. } => ("[", "]"),
+					ast::ComprehensionKind::Dict { .. } => ("{", "}"),
+					_ => return None,
+				};
+				self.write(open);
+				self.depth += 1;
+				match &**kind {
+					ast::ComprehensionKind::List { element } => self.expr(element, Style::default())?,
+					ast::ComprehensionKind::Dict { key, value } => {
+						self.expr(key, Style::default())?;
+						self.write(": ");
+						self.expr(value, Style::default())?;
+					}
+					_ => return None,
+				}
+				for generator in generators {
+					self.write(" for ");
+					self.bare_tuple(&generator.target)?;
+					self.write(" in ");
+					self.expr(&generator.iter, Style::default())?;
+					for condition in &generator.ifs {
+						self.write(" if ");
+						self.expr(condition, Style::default())?;
+					}
+				}
+				self.depth -= 1;
+				self.write(close);
+			}
+			ast::ExpressionType::Lambda { args, body } => {
+				self.write("lambda");
+				let mut params = args.args.iter().map(|arg| arg.arg.clone()).collect::<Vec<_>>();
+				let first_default = args.args.len() - args.defaults.len();
+				for (param, default) in params[first_default..].iter_mut().zip(&args.defaults) {
+					// Defaults of lambdas are rare enough that we only print simple ones.
+					let token = &self.source.tokens[self.source.token_at(&default.location)?];
+					if self.source.outer(default)?.0 != self.source.outer(default)?.1 {
+						return None;
+					}
+					*param = format!("{} = {}", param, self.source.slice(token.start, token.end));
+				}
+				if args.vararg != ast::Varargs::None || args.kwarg != ast::Varargs::None || !args.kwonlyargs.is_empty() {
+					return None;
+				}
+				if !params.is_empty() {
+					self.write(&format!(" {}", params.join(", ")));
+				}
+				self.write(": ");
+				self.expr(body, Style::default())?;
+			}
+			ast::ExpressionType::IfExpression { test, body, orelse } => {
+				self.expr(body, Style::default())?;
+				self.write(" if ");
+				self.expr(test, Style::default())?;
+				self.write(" else ");
+				self.expr(orelse, Style::default())?;
+			}
+			ast::ExpressionType::Starred { value } => {
+				self.write("*");
+				self.expr(value, Style::default())?;
+			}
+			_ => return None,
+		}
+		Some(())
+	}
+
+	/// `a[lower:upper:step]`, where the parser fills in the parts that were left out with `None`s.
+	fn slice(&mut self, elements: &[ast::Expression]) -> Option<()> {
+		let given = |element: &ast::Expression| match element.node {
+			ast::ExpressionType::None => self.source.is(self.source.token_at(&element.location).unwrap_or(0), &Tok::None),
+			_ => true,
+		};
+		let parts = elements.iter().map(|element| Some(element).filter(|element| given(element))).collect::<Vec<_>>();
+		for (i, part) in parts.iter().enumerate() {
+			if i == 1 || (i == 2 && part.is_some()) {
+				self.write(":");
+			}
+			if let Some(part) = part {
+				self.expr(part, Style::default())?;
+			}
+		}
+		Some(())
+	}
+
+	fn call(
+		&mut self,
+		expr: &ast::Expression,
+		function: &ast::Expression,
+		args: &[ast::Expression],
+		keywords: &[ast::Keyword],
+		style: Style,
+	) -> Option<()> {
+		self.expr(function, Style::default())?;
+		let open = self.source.token_at(&expr.location)?;
+		let close = *self.source.closing.get(&open)?;
+		let is_target = style.target && keywords.iter().any(|keyword| keyword.name.as_deref() == Some("name"));
+		// Only the targets of BUILD files have their arguments reordered and sorted.
+		let reordered = is_target && self.dialect == Dialect::Build;
+		let mut items = args.iter().map(|arg| Item::Expr(arg, Style::default())).collect::<Vec<_>>();
+		items.extend(keywords.iter().map(|keyword| {
+			let sorted = reordered && keyword.name.as_deref().map(|name| SORTED_ATTRS.contains(&name)).unwrap_or(false);
+			Item::Keyword(keyword, Style { multi_line: is_target, sorted, target: false })
+		}));
+		items.sort_by_key(|item| self.span(item).map(|span| span.0));
+		let untouched = self.has_comments(open, close);
+		let is_load = matches!(&function.node, ast::ExpressionType::Identifier { name } if name == "load");
+		if is_load && !untouched && !items.is_empty() {
+			// The label stays first, and the symbols are sorted by the names they are loaded as,
+			// with the aliases after the rest like keyword arguments have to be.
+			items[1..].sort_by_key(|item| match item {
+				Item::Expr(symbol, _) => (false, string_value(symbol).map(str::to_string)),
+				Item::Keyword(alias, _) => (true, alias.name.clone()),
+				_ => (true, None),
+			});
+		}
+		if reordered && !untouched {
+			let priority = |item: &Item| match item {
+				Item::Keyword(keyword, _) => keyword
+					.name
+					.as_deref()
+					.and_then(|name| ATTR_PRIORITIES.iter().find(|(attr, _)| *attr == name))
+					.map(|(_, priority)| *priority)
+					.unwrap_or(0),
+				_ => i32::MIN,
+			};
+			items.sort_by_key(priority);
+		}
+		let style = Style {
+			multi_line: is_target,
+			..Style::default()
+		};
+		self.bracketed(("(", ")"), open, close, &items, style)
+	}
+
+	/// The first and last tokens of `item`.
+	fn span(&self, item: &Item) -> Option<(usize, usize)> {
+		match item {
+			Item::Expr(expr, _) => self.source.outer(expr).map(|(first, last, _)| (first, last)),
+			Item::Keyword(keyword, _) => {
+				let (first, last, _) = self.source.outer(&keyword.value)?;
+				// `name =` or `**`.
+				let prefix = if keyword.name.is_some() { 2 } else { 1 };
+				Some((first.checked_sub(prefix)?, last))
+			}
+			Item::Entry(Some(key), value) => Some((self.source.outer(key)?.0, self.source.outer(value)?.1)),
+			Item::Entry(None, value) => {
+				let (first, last, _) = self.source.outer(value)?;
+				Some((first.checked_sub(1)?, last))
+			}
+			Item::Param(param, prefix, default) => {
+				let name = self.source.token_at(&param.location)?;
+				let first = if prefix.is_empty() { name } else { name.checked_sub(1)? };
+				match default {
+					Some(default) => Some((first, self.source.outer(default)?.1)),
+					None => Some((first, name)),
+				}
+			}
+		}
+	}
+
+	fn item(&mut self, item: &Item) -> Option<()> {
+		match item {
+			Item::Expr(expr, style) => self.expr(expr, *style),
+			Item::Keyword(keyword, style) => {
+				match &keyword.name {
+					Some(name) => self.write(&format!("{} = ", name)),
+					None => self.write("**"),
+				}
+				self.expr(&keyword.value, *style)
+			}
+			Item::Entry(key, value) => {
+				match key {
+					Some(key) => {
+						self.expr(key, Style::default())?;
+						self.write(": ");
+					}
+					None => self.write("**"),
+				}
+				self.expr(value, Style::default())
+			}
+			Item::Param(param, prefix, default) => {
+				self.write(&format!("{}{}", prefix, param.arg));
+				match default {
+					Some(default) => {
+						self.write(" = ");
+						self.expr(default, Style::default())
+					}
+					None => Some(()),
+				}
+			}
+		}
+	}
+
+	/// Prints `items` in brackets, one per line if they were written that way or `style` asks for it,
+	/// and otherwise all on one line.
+	fn bracketed(&mut self, brackets: (&str, &str), open: usize, close: usize, items: &[Item], style: Style) -> Option<()> {
+		let (open_pos, close_pos) = (self.source.tokens[open].start, self.source.tokens[close].start);
+		let multi_line = (open_pos.0 != close_pos.0 && (!items.is_empty() || self.has_comments(open, close)))
+			|| (style.multi_line && !items.is_empty());
+		self.write(brackets.0);
+		self.depth += 1;
+		if !multi_line {
+			for (i, item) in items.iter().enumerate() {
+				if i > 0 {
+					self.write(", ");
+				}
+				self.item(item)?;
+			}
+			self.depth -= 1;
+			self.write(brackets.1);
+			return Some(());
+		}
+		self.indent += 1;
+		let spans = items.iter().map(|item| self.span(item)).collect::<Option<Vec<_>>>()?;
+		let first_pos = spans.first().map(|span| self.source.tokens[span.0].start).unwrap_or(close_pos);
+		self.trailing_comments(first_pos.min((open_pos.0 + 1, 0)));
+		self.fresh = true;
+		for (i, (item, span)) in items.iter().zip(&spans).enumerate() {
+			let start = self.source.tokens[span.0].start;
+			self.own_line_comments(start, 0)?;
+			self.line_break(start.0);
+			self.item(item)?;
+			self.write(",");
+			self.row = self.row.max(self.source.tokens[span.1].end.0);
+			let next = spans
+				.get(i + 1)
+				.map(|span| self.source.tokens[span.0].start)
+				.unwrap_or(close_pos);
+			// Only comments on the line the item ends on are about it.
+			let item_end = self.source.tokens[span.1].end.0;
+			self.trailing_comments(next.min((item_end + 1, 0)));
+		}
+		self.own_line_comments(close_pos, 0)?;
+		self.indent -= 1;
+		self.newline();
+		self.depth -= 1;
+		self.write(brackets.1);
+		Some(())
+	}
+}
+
+fn print(contents: &str, dialect: Dialect) -> Option<(String, Vec<Chunk>)> {
+	let program = parser::parse_program(contents).ok()?;
+	let source = Source::new(contents)?;
+	let mut printer = Printer::new(&source, dialect);
+	printer.top_level(&program.statements)?;
+	Some((printer.out, printer.chunks))
+}
+
+/// Formats `contents` like buildifier would, or returns None if it doesn't parse.
+pub fn format(contents: &str, dialect: Dialect) -> Option<String> {
+	Some(print(contents, dialect)?.0)
+}
+
+fn end_of(contents: &str) -> lsp::Position {
+	let last_line = contents.split('\n').next_back().unwrap_or("");
+	lsp::Position::new(
+		contents.matches('\n').count() as u32,
+		last_line.encode_utf16().count() as u32,
+	)
+}
+
+/// The range of all of `contents`, for edits that replace it.
+pub fn document_range(contents: &str) -> lsp::Range {
+	lsp::Range::new(lsp::Position::new(0, 0), end_of(contents))
+}
+
+/// The edit that formats the whole of `contents`, if it needs any.
+pub fn format_document(contents: &str, dialect: Dialect) -> Option<Vec<lsp::TextEdit>> {
+	let formatted = format(contents, dialect)?;
+	if formatted == contents {
+		return Some(vec![]);
+	}
+	Some(vec![lsp::TextEdit::new(document_range(contents), formatted)])
+}
+
+/// The edit that formats the top-level statements `range` touches, with the comments before them.
+pub fn format_range(contents: &str, dialect: Dialect, range: lsp::Range) -> Option<Vec<lsp::TextEdit>> {
+	let (formatted, chunks) = print(contents, dialect)?;
+	let (first_row, last_row) = (range.start.line as usize + 1, range.end.line as usize + 1);
+	let touched = chunks
+		.iter()
+		.filter(|chunk| chunk.rows.0 <= last_row && chunk.rows.1 >= first_row)
+		.collect::<Vec<_>>();
+	let (first, last) = match (touched.first(), touched.last()) {
+		(Some(first), Some(last)) => (first, last),
+		_ => return Some(vec![]),
+	};
+	let start = lsp::Position::new(first.rows.0 as u32 - 1, 0);
+	let lines = contents.split('\n').count();
+	let (end, text) = match last.rows.1 < lines {
+		true => (lsp::Position::new(last.rows.1 as u32, 0), format!("{}\n", &formatted[first.output.0..last.output.1])),
+		false => (end_of(contents), formatted[first.output.0..].to_string()),
+	};
+	let original = contents.split('\n').skip(start.line as usize).take(end.line as usize - start.line as usize);
+	if end.character == 0 && original.map(|line| format!("{}\n", line)).collect::<String>() == text {
+		return Some(vec![]);
+	}
+	Some(vec![lsp::TextEdit::new(lsp::Range::new(start, end), text)])
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_formats_build_files_like_buildifier() {
+		let contents = "load('//:defs.bzl', 'z', 'a', c = 'c')\n\
+		                # The library.\n\
+		                cc_library(deps = [':b', '//x:y', ':a'], srcs = ['b.cc', 'a.cc'], name = 'lib')\n\
+		                cc_binary(\n\
+		                  name = 'bin',  # the binary\n\
+		                  deps = [\n\
+		                    ':lib',\n\
+		                \n\
+		                    '//other',\n\
+		                  ])\n";
+		let expected = "load(\"//:defs.bzl\", \"a\", \"z\", c = \"c\")\n\
+		                \n\
+		                # The library.\n\
+		                cc_library(\n    name = \"lib\",\n    srcs = [\n        \"a.cc\",\n        \"b.cc\",\n    ],\n    \
+		                deps = [\n        \":a\",\n        \":b\",\n        \"//x:y\",\n    ],\n)\n\
+		                \n\
+		                cc_binary(\n    name = \"bin\",  # the binary\n    deps = [\n        \":lib\",\n\n        \"//other\",\n    ],\n)\n";
+		assert_eq!(format(contents, Dialect::Build).as_deref(), Some(expected));
+		assert_eq!(format(expected, Dialect::Build).as_deref(), Some(expected));
+		assert_eq!(format_document(expected, Dialect::Build), Some(vec![]));
+	}
+
+	#[test]
+	fn test_formats_the_statements_in_range() {
+		let contents = "def f(a,b):\n  return a+b\n\nx = f(1,2)\nwhile x:\n  pass\n";
+		let range = |line| lsp::Range::new(lsp::Position::new(line, 0), lsp::Position::new(line, 1));
+		let edit = lsp::TextEdit::new(
+			lsp::Range::new(lsp::Position::new(0, 0), lsp::Position::new(2, 0)),
+			"def f(a, b):\n    return a + b\n".to_string(),
+		);
+		assert_eq!(format_range(contents, Dialect::Bzl, range(1)), Some(vec![edit]));
+		// Statements the formatter doesn't know, like `while`, are left as they are.
+		assert_eq!(format_range(contents, Dialect::Bzl, range(4)), Some(vec![]));
+	}
+
+	#[test]
+	fn test_keeps_comments_with_what_they_are_about() {
+		let contents = "# Header.\n\
+		                \n\
+		                load(':a.bzl', 'a')  # trailing\n\
+		                \n\
+		                load(':b.bzl', 'b')\n\
+		                def f(x):  # about f\n\
+		                \x20 # Inside.\n\
+		                \x20 return [\n\
+		                \x20   x,  # the x\n\
+		                \x20   # before y\n\
+		                \x20   1,\n\
+		                \x20 ]\n\
+		                X = 1\n\
+		                # Trailing.\n";
+		let expected = "# Header.\n\
+		                \n\
+		                load(\":a.bzl\", \"a\")  # trailing\n\
+		                load(\":b.bzl\", \"b\")\n\
+		                \n\
+		                def f(x):  # about f\n\
+		                \x20   # Inside.\n\
+		                \x20   return [\n\
+		                \x20       x,  # the x\n\
+		                \x20       # before y\n\
+		                \x20       1,\n\
+		                \x20   ]\n\
+		                \n\
+		                X = 1\n\
+		                # Trailing.\n";
+		assert_eq!(format(contents, Dialect::Bzl).as_deref(), Some(expected));
+		assert_eq!(format(expected, Dialect::Bzl).as_deref(), Some(expected));
+	}
+
+	#[test]
+	fn test_formats_nested_lists() {
+		let contents = "X = [[1,2],[3,\n  4], {'a': [5,\n 6]}]\nY = [\n  [1, 2],\n]\n";
+		let expected = "X = [\n    [1, 2],\n    [\n        3,\n        4,\n    ],\n    {\n        \"a\": [\n            5,\n            6,\n        ],\n    },\n]\n\
+		                Y = [\n    [1, 2],\n]\n";
+		assert_eq!(format(contents, Dialect::Bzl).as_deref(), Some(expected));
+	}
+
+	#[test]
+	fn test_sorts_the_lists_of_sorted_attributes() {
+		let contents = "sh_test(args = ['b', 'a'], name = 't', data = ['y', 'x'], tests = ['b', 'a'], visibility = ['//z', '//a'])\n\
+		                sh_test(name = 'u', deps = [\n  ':b',  # first\n  ':a',\n], srcs = ['b.sh', 'a.sh'])\n";
+		let expected = "sh_test(\n    name = \"t\",\n    args = [\n        \"b\",\n        \"a\",\n    ],\n    \
+		                data = [\n        \"x\",\n        \"y\",\n    ],\n    \
+		                tests = [\n        \"a\",\n        \"b\",\n    ],\n    \
+		                visibility = [\n        \"//a\",\n        \"//z\",\n    ],\n)\n\
+		                \n\
+		                sh_test(\n    name = \"u\",\n    deps = [\n        \":b\",  # first\n        \":a\",\n    ],\n    \
+		                srcs = [\n        \"a.sh\",\n        \"b.sh\",\n    ],\n)\n";
+		// Nothing is moved around a comment, so the second target keeps the order of its attributes.
+		assert_eq!(format(contents, Dialect::Build).as_deref(), Some(expected));
+	}
+
+	#[test]
+	fn test_formats_workspace_files_without_reordering() {
+		assert_eq!(Dialect::of(Path::new("/ws/WORKSPACE")), Dialect::Workspace);
+		assert_eq!(Dialect::of(Path::new("/ws/WORKSPACE.bazel")), Dialect::Workspace);
+		assert_eq!(Dialect::of(Path::new("/ws/MODULE.bazel")), Dialect::Workspace);
+		assert_eq!(Dialect::of(Path::new("/ws/BUILD.bazel")), Dialect::Build);
+		assert_eq!(Dialect::of(Path::new("/ws/defs.bzl")), Dialect::Bzl);
+
+		let contents = "bazel_dep(version = '1', name = 'a')\nhttp_archive(urls = ['b', 'a'], name = 'rules')\n";
+		let expected = "bazel_dep(\n    version = \"1\",\n    name = \"a\",\n)\n\
+		                \n\
+		                http_archive(\n    urls = [\n        \"b\",\n        \"a\",\n    ],\n    name = \"rules\",\n)\n";
+		assert_eq!(format(contents, Dialect::Workspace).as_deref(), Some(expected));
+	}
+}
@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::bazel::BazelWorkspace;
+use crate::index::indexed_document::IndexedDocument;
+
+/// Bump this whenever the parser or `IndexedDocument` changes in a way
+/// that makes previously cached entries wrong.
+const INDEX_FORMAT_VERSION: u32 = 13;
+
+fn parser_version() -> String {
+	format!("{}-{}", env!("CARGO_PKG_VERSION"), INDEX_FORMAT_VERSION)
+}
+
+pub fn content_hash(contents: &str) -> String {
+	format!("{:x}", md5::compute(contents.as_bytes()))
+}
+
+/// Where a document was indexed, since that decides what the labels it loads resolve to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkspaceRoots {
+	pub workspace_root: PathBuf,
+	pub output_base: Option<PathBuf>,
+}
+
+impl WorkspaceRoots {
+	pub fn of(bazel: &BazelWorkspace) -> Option<Self> {
+		Some(WorkspaceRoots {
+			workspace_root: bazel.workspace_root()?,
+			output_base: bazel.output_base(),
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheEntry {
+	path: PathBuf,
+	roots: WorkspaceRoots,
+	content_hash: String,
+	parser_version: String,
+	document: IndexedDocument,
+}
+
+/// On-disk cache of indexed documents, so that we don't have to re-parse
+/// every file (especially the ones in external repositories) on every restart.
+///
+/// There is one file per indexed document and workspace, named after the hash of both.
+/// Entries are only valid for the same contents and parser version that produced them.
+#[derive(Debug, Clone)]
+pub struct IndexCache {
+	dir: PathBuf,
+}
+
+impl IndexCache {
+	pub fn new(dir: &Path) -> Self {
+		IndexCache { dir: dir.to_path_buf() }
+	}
+
+	pub fn in_user_cache_dir() -> Option<Self> {
+		dirs::cache_dir().map(|dir| IndexCache::new(&dir.join("bazel-lsp").join("index")))
+	}
+
+	fn entry_file(&self, path: &Path, roots: &WorkspaceRoots) -> PathBuf {
+		let output_base = roots.output_base.as_deref().unwrap_or_else(|| Path::new(""));
+		let key = content_hash(&format!(
+			"{}\n{}\n{}",
+			path.to_string_lossy(),
+			roots.workspace_root.to_string_lossy(),
+			output_base.to_string_lossy()
+		));
+		self.dir.join(format!("{}.json", key))
+	}
+
+	fn read_entry(file: &Path) -> Option<CacheEntry> {
+		let contents = std::fs::read_to_string(file).ok()?;
+		serde_json::from_str::<CacheEntry>(&contents)
+			.ok()
+			.filter(|entry| entry.parser_version == parser_version())
+	}
+
+	/// Returns the cached index of `path`, if it was produced from exactly `contents` in the same workspace.
+	pub fn load(&self, path: &Path, roots: &WorkspaceRoots, contents: &str) -> Option<IndexedDocument> {
+		IndexCache::read_entry(&self.entry_file(path, roots))
+			.filter(|entry| entry.path == path && entry.roots == *roots && entry.content_hash == content_hash(contents))
+			.map(|entry| entry.document)
+	}
+
+	pub fn store(
+		&self,
+		path: &Path,
+		roots: &WorkspaceRoots,
+		contents: &str,
+		document: &IndexedDocument,
+	) -> Result<(), String> {
+		std::fs::create_dir_all(&self.dir)
+			.map_err(|err| format!("Error creating cache dir {:?}: {:?}", self.dir, err))?;
+		let entry = CacheEntry {
+			path: path.to_path_buf(),
+			roots: roots.clone(),
+			content_hash: content_hash(contents),
+			parser_version: parser_version(),
+			document: document.clone(),
+		};
+		let serialized = serde_json::to_string(&entry)
+			.map_err(|err| format!("Error serializing index of {:?}: {:?}", path, err))?;
+		// Write to a temporary file and rename, so that readers never see half-written entries.
+		let file = self.entry_file(path, roots);
+		let tmp_file = file.with_extension("json.tmp");
+		std::fs::write(&tmp_file, serialized)
+			.and_then(|_| std::fs::rename(&tmp_file, &file))
+			.map_err(|err| format!("Error writing cache entry {:?}: {:?}", file, err))
+	}
+
+	/// Returns every entry of the workspace at `roots` that is still valid for the current contents of its file.
+	///
+	/// Entries of other workspaces are left alone, for when those are opened.
+	/// The second element has the paths of entries that are stale, and need to be re-indexed.
+	/// Fresh entries come with the hash of the contents they were indexed from.
+	pub fn load_all(&self, roots: &WorkspaceRoots) -> (Vec<(PathBuf, String, IndexedDocument)>, Vec<PathBuf>) {
+		let mut fresh = vec![];
+		let mut stale = vec![];
+		let entries = match std::fs::read_dir(&self.dir) {
+			Ok(entries) => entries,
+			Err(_) => return (fresh, stale),
+		};
+		for file in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+			if file.extension().map(|ext| ext != "json").unwrap_or(true) {
+				continue;
+			}
+			let entry = match IndexCache::read_entry(&file) {
+				Some(entry) => entry,
+				None => {
+					// Written by a different version of the server, or corrupted.
+					let _ = std::fs::remove_file(&file);
+					continue;
+				}
+			};
+			if entry.roots != *roots {
+				continue;
+			}
+			match std::fs::read_to_string(&entry.path) {
+				Ok(contents) if content_hash(&contents) == entry.content_hash => {
+					fresh.push((entry.path, entry.content_hash, entry.document))
+				}
+				Ok(_) => stale.push(entry.path),
+				Err(_) => {
+					// The file is gone, so there is nothing to re-index.
+					let _ = std::fs::remove_file(&file);
+				}
+			}
+		}
+		(fresh, stale)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::index::function_decl::FunctionDecl;
+	use rustpython_parser::ast;
+
+	fn document() -> IndexedDocument {
+		IndexedDocument::finished(
+			hashmap! {
+				"a".to_string() => FunctionDecl::declared_in_file("a", ast::Location::new(1, 1))
+			},
+			vec![],
+		)
+	}
+
+	fn roots(workspace_root: &Path) -> WorkspaceRoots {
+		WorkspaceRoots {
+			workspace_root: workspace_root.to_path_buf(),
+			output_base: Some(workspace_root.join("output_base")),
+		}
+	}
+
+	#[test]
+	fn test_entries_are_keyed_by_contents() {
+		let tmp = tempfile::tempdir().unwrap();
+		let cache = IndexCache::new(&tmp.path().join("cache"));
+		let path = tmp.path().join("BUILD");
+		let roots = roots(tmp.path());
+
+		cache.store(&path, &roots, "a = 3", &document()).unwrap();
+
+		assert_eq!(cache.load(&path, &roots, "a = 3"), Some(document()));
+		assert_eq!(cache.load(&path, &roots, "a = 4"), None);
+		assert_eq!(cache.load(&tmp.path().join("other.bzl"), &roots, "a = 3"), None);
+	}
+
+	#[test]
+	fn test_entries_are_keyed_by_workspace() {
+		let tmp = tempfile::tempdir().unwrap();
+		let cache = IndexCache::new(&tmp.path().join("cache"));
+		let path = tmp.path().join("defs.bzl");
+		let outer = roots(tmp.path());
+		let inner = roots(&tmp.path().join("inner"));
+		let without_output_base = WorkspaceRoots {
+			output_base: None,
+			..outer.clone()
+		};
+
+		cache.store(&path, &outer, "a = 3", &document()).unwrap();
+
+		assert_eq!(cache.load(&path, &outer, "a = 3"), Some(document()));
+		assert_eq!(cache.load(&path, &inner, "a = 3"), None);
+		assert_eq!(cache.load(&path, &without_output_base, "a = 3"), None);
+	}
+
+	#[test]
+	fn test_load_all_separates_fresh_and_stale_entries() {
+		let tmp = tempfile::tempdir().unwrap();
+		let cache = IndexCache::new(&tmp.path().join("cache"));
+		let fresh = tmp.path().join("fresh.bzl");
+		let stale = tmp.path().join("stale.bzl");
+		let deleted = tmp.path().join("deleted.bzl");
+		std::fs::write(&fresh, "a = 3").unwrap();
+		std::fs::write(&stale, "a = 4").unwrap();
+		let workspace = roots(tmp.path());
+		let other_workspace = roots(&tmp.path().join("other"));
+
+		cache.store(&fresh, &workspace, "a = 3", &document()).unwrap();
+		cache.store(&stale, &workspace, "a = 3", &document()).unwrap();
+		cache.store(&deleted, &workspace, "a = 3", &document()).unwrap();
+		cache.store(&deleted, &other_workspace, "a = 3", &document()).unwrap();
+
+		let (fresh_entries, stale_entries) = cache.load_all(&workspace);
+		assert_eq!(fresh_entries, vec![(fresh, content_hash("a = 3"), document())]);
+		assert_eq!(stale_entries, vec![stale]);
+		assert!(!cache.entry_file(&deleted, &workspace).exists());
+		// The entries of other workspaces wait until those are opened.
+		assert!(cache.entry_file(&deleted, &other_workspace).exists());
+	}
+}
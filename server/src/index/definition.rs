@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+
+use crate::index::range::Range;
+use crate::index::signature::{Parameter, Signature};
+
+/// The builtins that define a rule-like symbol out of a dict of attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RuleKind {
+	Rule,
+	RepositoryRule,
+	Macro,
+	Aspect,
+	TagClass,
+}
+
+impl RuleKind {
+	pub fn from_function(name: &str) -> Option<Self> {
+		match name {
+			"rule" => Some(RuleKind::Rule),
+			"repository_rule" => Some(RuleKind::RepositoryRule),
+			"macro" => Some(RuleKind::Macro),
+			"aspect" => Some(RuleKind::Aspect),
+			"tag_class" => Some(RuleKind::TagClass),
+			_ => None,
+		}
+	}
+
+	fn function(&self) -> &'static str {
+		match self {
+			RuleKind::Rule => "rule",
+			RuleKind::RepositoryRule => "repository_rule",
+			RuleKind::Macro => "macro",
+			RuleKind::Aspect => "aspect",
+			RuleKind::TagClass => "tag_class",
+		}
+	}
+
+	/// Whether it's instantiated by calling it with a `name`, which it doesn't have to declare.
+	pub fn is_instantiated(&self) -> bool {
+		matches!(self, RuleKind::Rule | RuleKind::RepositoryRule | RuleKind::Macro)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Field {
+	pub name: String,
+	pub doc: Option<String>,
+	// Where the field is declared, for goto definition.
+	pub range: Range,
+}
+
+/// What we know about a symbol from the statement that defines it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Definition {
+	Function {
+		signature: Signature,
+		doc: Option<String>,
+		// The function its `**kwargs` are passed on to, as written in the call, e.g. `native.cc_library`.
+		forwards_kwargs_to: Option<String>,
+	},
+	Rule {
+		kind: RuleKind,
+		attrs: Signature,
+		doc: Option<String>,
+		// The function it's implemented by, if it's declared in the same file.
+		implementation: Option<String>,
+	},
+	Provider {
+		fields: Vec<Field>,
+		doc: Option<String>,
+	},
+	ModuleExtension {
+		tag_classes: Vec<String>,
+		doc: Option<String>,
+	},
+	/// A `struct(...)`, often used to group symbols like a module would.
+	Struct {
+		fields: Vec<Field>,
+	},
+	/// Anything else we can describe but not call, e.g. the `native` module.
+	Value {
+		doc: Option<String>,
+	},
+}
+
+impl Definition {
+	pub fn doc(&self) -> Option<&str> {
+		match self {
+			Definition::Function { doc, .. }
+			| Definition::Rule { doc, .. }
+			| Definition::Provider { doc, .. }
+			| Definition::ModuleExtension { doc, .. }
+			| Definition::Value { doc } => doc.as_deref(),
+			Definition::Struct { .. } => None,
+		}
+	}
+
+	/// What calling the symbol takes, if it can be called.
+	pub fn signature(&self) -> Option<Signature> {
+		match self {
+			Definition::Function { signature, .. } => Some(signature.clone()),
+			Definition::Rule { kind, attrs, .. } if kind.is_instantiated() => Some(attrs.clone()),
+			Definition::Provider { fields, .. } => Some(Signature {
+				parameters: fields
+					.iter()
+					.map(|field| Parameter {
+						doc: field.doc.clone(),
+						..Parameter::new(&field.name)
+					})
+					.collect(),
+			}),
+			_ => None,
+		}
+	}
+
+	/// A description of the symbol, for hovers.
+	pub fn as_markdown(&self, name: &str) -> String {
+		let header = match self {
+			Definition::Function { signature, .. } => format!("def {}{}", name, signature.as_arguments()),
+			Definition::Rule { kind, attrs, .. } => format!("{} {}{}", kind.function(), name, attrs.as_arguments()),
+			Definition::Provider { .. } => format!("provider {}", name),
+			Definition::ModuleExtension { .. } => format!("module_extension {}", name),
+			Definition::Struct { .. } => format!("struct {}", name),
+			Definition::Value { .. } => name.to_string(),
+		};
+		let mut sections = vec![format!("```python\n{}\n```", header)];
+		if let Some(doc) = self.doc() {
+			sections.push(doc.trim().to_string());
+		}
+		let members = match self {
+			Definition::Rule { attrs, .. } => attrs
+				.parameters
+				.iter()
+				.map(|attr| {
+					let mut details = attr.attr_type.iter().cloned().collect::<Vec<_>>();
+					if attr.mandatory {
+						details.push("mandatory".to_string());
+					}
+					if let Some(default) = &attr.default {
+						details.push(format!("default `{}`", default));
+					}
+					member(&attr.name, &details.join(", "), attr.doc.as_deref())
+				})
+				.collect(),
+			Definition::Provider { fields, .. } | Definition::Struct { fields } => fields
+				.iter()
+				.map(|field| member(&field.name, "", field.doc.as_deref()))
+				.collect(),
+			Definition::ModuleExtension { tag_classes, .. } => {
+				tag_classes.iter().map(|tag_class| member(tag_class, "", None)).collect()
+			}
+			Definition::Function { .. } | Definition::Value { .. } => vec![],
+		};
+		if !members.is_empty() {
+			sections.push(members.join("\n"));
+		}
+		sections.join("\n\n")
+	}
+}
+
+fn member(name: &str, details: &str, doc: Option<&str>) -> String {
+	let mut line = format!("- `{}`", name);
+	if !details.is_empty() {
+		line.push_str(&format!(" ({})", details));
+	}
+	if let Some(doc) = doc {
+		line.push_str(&format!(": {}", doc.trim()));
+	}
+	line
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_only_instantiated_kinds_can_be_called() {
+		let attrs = Signature {
+			parameters: vec![Parameter::new("name")],
+		};
+		let definition = |kind| Definition::Rule {
+			kind,
+			attrs: attrs.clone(),
+			doc: None,
+			implementation: None,
+		};
+		assert_eq!(definition(RuleKind::Rule).signature(), Some(attrs.clone()));
+		assert_eq!(definition(RuleKind::Aspect).signature(), None);
+		assert_eq!(definition(RuleKind::TagClass).signature(), None);
+	}
+
+	#[test]
+	fn test_rule_markdown() {
+		let srcs = Parameter {
+			attr_type: Some("label_list".to_string()),
+			mandatory: true,
+			doc: Some("The sources.".to_string()),
+			..Parameter::new("srcs")
+		};
+		let definition = Definition::Rule {
+			kind: RuleKind::Rule,
+			attrs: Signature { parameters: vec![srcs] },
+			doc: Some("Builds things.".to_string()),
+			implementation: None,
+		};
+		assert_eq!(
+			definition.as_markdown("my_rule"),
+			"```python\nrule my_rule(srcs: label_list)\n```\n\nBuilds things.\n\n- `srcs` (label_list, mandatory): The sources."
+		);
+	}
+}
@@ -1,107 +1,356 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::sync::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use crate::ast::process_document;
-use tower_lsp::lsp_types as lsp;
 
 use crate::bazel::BazelWorkspace;
-use crate::index::function_decl::{CallableSymbolSource, FunctionDecl};
+use crate::index::cache::{content_hash, IndexCache, WorkspaceRoots};
 use crate::index::indexed_document::IndexedDocument;
+use crate::index::snapshot::{Change, Snapshot};
 
+/// What an update changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Update {
+	/// The files that were indexed again, because their contents changed.
+	pub reindexed: Vec<PathBuf>,
+	/// The files whose diagnostics changed.
+	pub diagnostics_changed: Vec<PathBuf>,
+	/// Why we couldn't write the index to the cache, the first time it happens.
+	pub cache_error: Option<String>,
+}
+
+/// The index, as a series of immutable snapshots.
+///
+/// Readers take the latest snapshot and keep it for as long as they need it.
+/// Writers parse outside of any lock, and then take turns to publish the next snapshot.
 #[derive(Default, Debug)]
 pub struct Documents {
-	// TODO This really wants to be its own type,
-	// so that we don't need to pass maps around in index_document_inner
-	docs: RwLock<HashMap<PathBuf, Arc<IndexedDocument>>>,
+	snapshot: RwLock<Arc<Snapshot>>,
+	// Serializes writers, so that none of them overwrites the snapshot of another.
+	writer: Mutex<()>,
+	cache: Option<IndexCache>,
+	// The contents of the open documents, as the editor has them, which we index instead of what's on disk.
+	open_documents: RwLock<HashMap<PathBuf, String>>,
+	// Set once we fail to write to the cache, so that we only complain about it once.
+	cache_failed: AtomicBool,
 }
 
 impl Documents {
-	pub fn list_docs(&self) -> Vec<PathBuf> {
-		let docs = &*self.docs.read().expect("Failed to lock");
-		docs.keys().map(|e|e.clone()).collect::<Vec<_>>()
+	pub fn with_cache(cache: Option<IndexCache>) -> Self {
+		Documents {
+			cache,
+			..Documents::default()
+		}
 	}
 
-	pub fn refresh_doc(&self, doc: &PathBuf, bazel: &BazelWorkspace) {
-		self.index_document(doc, bazel)
-			.expect(&format!("Trouble refreshing doc {:?}", doc));
+	/// Indexes `doc` from `text` from now on, until it's closed.
+	pub fn open_doc(&self, doc: &Path, text: String) {
+		if let Ok(mut open_documents) = self.open_documents.write() {
+			open_documents.insert(doc.to_path_buf(), text);
+		}
 	}
 
-	pub fn get_doc(&self, doc: &PathBuf) -> Option<Arc<IndexedDocument>> {
-		let docs = &*self.docs.read().expect("Failed to lock");
-		docs.get(doc).cloned()
+	/// Goes back to indexing `doc` from disk.
+	pub fn close_doc(&self, doc: &Path) {
+		if let Ok(mut open_documents) = self.open_documents.write() {
+			open_documents.remove(doc);
+		}
 	}
 
-	fn index_document(&self, path: &PathBuf, bazel: &BazelWorkspace) -> Result<(), String> {
-		let index = &mut *self
-			.docs
-			.write()
+	pub fn is_open(&self, doc: &Path) -> bool {
+		self.open_documents
+			.read()
+			.map(|open_documents| open_documents.contains_key(doc))
+			.unwrap_or(false)
+	}
+
+	/// The contents of `doc` in the editor, if it's open.
+	pub fn open_text(&self, doc: &Path) -> Option<String> {
+		self.open_documents.read().ok()?.get(doc).cloned()
+	}
+
+	pub fn snapshot(&self) -> Arc<Snapshot> {
+		self.snapshot.read().expect("Failed to lock").clone()
+	}
+
+	fn commit(&self, changes: Vec<Change>) -> Result<Vec<PathBuf>, String> {
+		let _writer = self
+			.writer
+			.lock()
 			.map_err(|err| format!("Failed to lock documents: {:?}", err))?;
-		Documents::index_document_inner(index, path, bazel)
+		let mut next = (*self.snapshot()).clone();
+		// Another writer may have indexed the same contents since we parsed them.
+		let changes = changes
+			.into_iter()
+			.filter(|change| match change {
+				Change::Indexed { path, hash, .. } => !next.is_up_to_date(path, hash),
+				Change::Removed(_) => true,
+			})
+			.collect::<Vec<_>>();
+		if changes.is_empty() {
+			return Ok(vec![]);
+		}
+		let diagnostics_changed = next.apply(changes);
+		*self
+			.snapshot
+			.write()
+			.map_err(|err| format!("Failed to lock documents: {:?}", err))? = Arc::new(next);
+		Ok(diagnostics_changed)
 	}
 
-	fn index_document_inner(
-		index: &mut HashMap<PathBuf, Arc<IndexedDocument>>,
-		path: &PathBuf,
-		bazel: &BazelWorkspace,
-	) -> Result<(), String> {
-		let contents = std::fs::read_to_string(path).map_err(|err| format!("Error reading {:?}: {:?}", path, &err))?;
-		let (indexed_doc, docs_to_load) = process_document(&contents, bazel)?;
-		index.insert(path.clone(), Arc::new(indexed_doc));
-		for doc in docs_to_load {
-			// We unconditionally update the current document,
-			// but not any other one, because they haven't changed.
-			//
-			// If they had, we'd have updated them on did_change.
-			if !index.contains_key(&doc) {
-		        let contents = std::fs::read_to_string(path).map_err(|err| format!("Error reading {:?}: {:?}", path, &err))?;
-				let (indexed_doc, _) = process_document(&contents, bazel)?;
-				index.insert(doc.clone(), Arc::new(indexed_doc));
+	/// Re-indexes `doc` and the files it loads, if their contents changed.
+	pub fn refresh_doc(&self, doc: &Path, bazel: &BazelWorkspace) -> Result<Update, String> {
+		self.index_document(doc, bazel)
+			.map_err(|err| format!("Trouble refreshing doc {:?}: {}", doc, err))
+	}
+
+	/// Forgets `doc`, e.g. because it was deleted, and returns the files whose diagnostics changed.
+	pub fn remove_doc(&self, doc: &Path) -> Result<Vec<PathBuf>, String> {
+		self.commit(vec![Change::Removed(doc.to_path_buf())])
+	}
+
+	/// Loads every document of `bazel` from the on-disk cache whose contents haven't changed,
+	/// and returns the paths of the ones that have, so that they can be re-indexed.
+	pub fn restore_from_cache(&self, bazel: &BazelWorkspace) -> Result<Vec<PathBuf>, String> {
+		let (fresh, stale) = match (&self.cache, WorkspaceRoots::of(bazel)) {
+			(Some(cache), Some(roots)) => cache.load_all(&roots),
+			_ => return Ok(vec![]),
+		};
+		let snapshot = self.snapshot();
+		let changes = fresh
+			.into_iter()
+			.filter(|(path, _, _)| !snapshot.is_indexed(path))
+			.map(|(path, hash, document)| Change::Indexed { path, hash, document })
+			.collect();
+		self.commit(changes)?;
+		Ok(stale)
+	}
+
+	/// Indexes just `path`, without touching the files it loads,
+	/// and returns those so that the caller can decide whether to index them,
+	/// along with why we couldn't cache the index, the first time it happens.
+	///
+	/// Several files can be indexed in parallel, since we only wait for other writers
+	/// to publish the result.
+	pub fn index_single(&self, path: &Path, bazel: &BazelWorkspace) -> Result<(Vec<PathBuf>, Option<String>), String> {
+		let snapshot = self.snapshot();
+		let mut cache_error = None;
+		match self.parse_if_changed(&snapshot, path, bazel, &mut cache_error)? {
+			Some((change, loads)) => {
+				self.commit(vec![change])?;
+				Ok((loads, cache_error))
 			}
+			None => Ok((snapshot.loads_of(path), None)),
 		}
-		Ok(())
 	}
 
-	pub fn locate_declaration_of_call_at(
-		&self,
-		doc: &PathBuf,
-		position: lsp::Position,
-	) -> Option<lsp::Location> {
-		let indexed_doc = self.get_doc(doc);
-		if let Some(indexed_doc) = indexed_doc {
-			let maybe_call = indexed_doc.call_at(position);
-			if let Some(call) = maybe_call {
-				if let Some(decl) = indexed_doc.declaration_of(&call.function_name) {
-					self.locate_declaration(&decl, doc)
-				} else {
-					None
-				}
-			} else {
-				None
+	fn index_document(&self, path: &Path, bazel: &BazelWorkspace) -> Result<Update, String> {
+		let snapshot = self.snapshot();
+		let mut changes = vec![];
+		let mut cache_error = None;
+		let loads = match self.parse_if_changed(&snapshot, path, bazel, &mut cache_error)? {
+			Some((change, loads)) => {
+				changes.push(change);
+				loads
+			}
+			None => snapshot.loads_of(path),
+		};
+		// Loaded files can change without us hearing about it,
+		// e.g. when an external repository is fetched again, so we check them too.
+		for doc in loads {
+			if let Some((change, _)) = self.parse_if_changed(&snapshot, &doc, bazel, &mut cache_error)? {
+				changes.push(change);
 			}
-		} else {
-			None
 		}
+		let reindexed = changes
+			.iter()
+			.filter_map(|change| match change {
+				Change::Indexed { path, .. } => Some(path.clone()),
+				Change::Removed(_) => None,
+			})
+			.collect();
+		let diagnostics_changed = self.commit(changes)?;
+		Ok(Update {
+			reindexed,
+			diagnostics_changed,
+			cache_error,
+		})
 	}
 
-	fn locate_declaration(
+	fn parse_if_changed(
 		&self,
-		start: &FunctionDecl,
-		current_file: &PathBuf,
-	) -> Option<lsp::Location> {
-		match &start.source {
-			CallableSymbolSource::DeclaredInFile(range) => Some(lsp::Location::new(
-				lsp::Url::from_file_path(current_file.clone()).expect("Err!"),
-				range.as_lsp_range(),
-			)),
-			CallableSymbolSource::Loaded(loaded_path) => {
-				let new_declaration = self
-					.get_doc(&loaded_path)
-					.expect("Error")
-					.declaration_of(&start.real_name);
-				new_declaration.and_then(|decl| self.locate_declaration(&decl, &loaded_path))
+		snapshot: &Snapshot,
+		path: &Path,
+		bazel: &BazelWorkspace,
+		cache_error: &mut Option<String>,
+	) -> Result<Option<(Change, Vec<PathBuf>)>, String> {
+		let contents = self.read_file(path)?;
+		let hash = content_hash(&contents);
+		if snapshot.is_up_to_date(path, &hash) {
+			return Ok(None);
+		}
+		let (document, docs_to_load) = self.parse_contents(path, &contents, bazel, cache_error)?;
+		let change = Change::Indexed {
+			path: path.to_path_buf(),
+			hash,
+			document,
+		};
+		Ok(Some((change, docs_to_load)))
+	}
+
+	fn read_file(&self, path: &Path) -> Result<String, String> {
+		match self.open_text(path) {
+			Some(text) => Ok(text),
+			None => std::fs::read_to_string(path).map_err(|err| format!("Error reading {:?}: {:?}", path, &err)),
+		}
+	}
+
+	fn parse_contents(
+		&self,
+		path: &Path,
+		contents: &str,
+		bazel: &BazelWorkspace,
+		// Where we report why we couldn't cache the index, which we only do once.
+		cache_error: &mut Option<String>,
+	) -> Result<(IndexedDocument, Vec<PathBuf>), String> {
+		let cache = match (&self.cache, WorkspaceRoots::of(bazel)) {
+			(Some(cache), Some(roots)) => Some((cache, roots)),
+			_ => None,
+		};
+		match cache.as_ref().and_then(|(cache, roots)| cache.load(path, roots, contents)) {
+			Some(cached) => {
+				let docs_to_load = cached.loaded_files();
+				Ok((cached, docs_to_load))
+			}
+			None => {
+				let (indexed_doc, docs_to_load) = process_document(contents, &bazel.resolver_for(path))?;
+				if let Some((cache, roots)) = cache {
+					// The cache only saves time on the next start, so we index without it if we must.
+					if let Err(err) = cache.store(path, &roots, contents, &indexed_doc) {
+						if !self.cache_failed.swap(true, Ordering::Relaxed) {
+							*cache_error = Some(format!("Not caching the index: {}", err));
+						}
+					}
+				}
+				Ok((indexed_doc, docs_to_load))
 			}
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::fs;
+
+	fn workspace(root: &Path) -> BazelWorkspace {
+		let workspace = BazelWorkspace::new();
+		workspace.locate_workspace(root).unwrap();
+		workspace
+	}
+
+	#[test]
+	fn test_refresh_only_reindexes_changed_files() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		let build = root.join("BUILD");
+		let defs = root.join("defs.bzl");
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(&build, "load('//:defs.bzl', 'my_macro')\nmy_macro()\n").unwrap();
+		fs::write(&defs, "def my_macro():\n  pass\n").unwrap();
+		let bazel = workspace(root);
+		let documents = Documents::default();
+
+		let reindexed = |documents: &Documents| documents.refresh_doc(&build, &bazel).map(|update| update.reindexed);
+
+		assert_eq!(reindexed(&documents), Ok(vec![build.clone(), defs.clone()]));
+		// The loaded file is indexed from its own contents, not from the loading file's.
+		assert!(documents.snapshot().get_doc(&defs).unwrap().declaration_of("my_macro").is_some());
+		assert_eq!(documents.snapshot().loads_of(&build), vec![defs.clone()]);
+
+		assert_eq!(reindexed(&documents), Ok(vec![]));
+
+		fs::write(&defs, "def my_macro():\n  pass\ndef other_macro():\n  pass\n").unwrap();
+		assert_eq!(reindexed(&documents), Ok(vec![defs.clone()]));
+		assert!(documents.snapshot().get_doc(&defs).unwrap().declaration_of("other_macro").is_some());
+	}
+
+	#[test]
+	fn test_readers_keep_their_snapshot() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		let build = root.join("BUILD");
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(&build, "a = 3\n").unwrap();
+		let bazel = workspace(root);
+		let documents = Documents::default();
+
+		let before = documents.snapshot();
+		documents.refresh_doc(&build, &bazel).unwrap();
+
+		assert!(!before.is_indexed(&build));
+		assert!(documents.snapshot().is_indexed(&build));
+	}
+
+	#[test]
+	fn test_contents_indexed_meanwhile_are_not_committed_again() {
+		let documents = Documents::default();
+		let change = || Change::Indexed {
+			path: PathBuf::from("defs.bzl"),
+			hash: content_hash("a = 3"),
+			document: IndexedDocument::new(),
+		};
+		documents.commit(vec![change()]).unwrap();
+		let before = documents.snapshot();
+
+		documents.commit(vec![change()]).unwrap();
+
+		assert!(Arc::ptr_eq(&before, &documents.snapshot()));
+	}
+
+	#[test]
+	fn test_open_documents_are_indexed_from_the_editor() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		let defs = root.join("defs.bzl");
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(&defs, "def on_disk():\n  pass\n").unwrap();
+		let bazel = workspace(root);
+		let documents = Documents::default();
+		let declares = |name| documents.snapshot().get_doc(&defs).unwrap().declaration_of(name).is_some();
+
+		documents.open_doc(&defs, "def in_editor():\n  pass\n".to_string());
+		documents.refresh_doc(&defs, &bazel).unwrap();
+		assert!(declares("in_editor"));
+		assert!(!declares("on_disk"));
+
+		documents.close_doc(&defs);
+		documents.refresh_doc(&defs, &bazel).unwrap();
+		assert!(declares("on_disk"));
+	}
+
+	#[test]
+	fn test_indexes_without_a_writable_cache() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		let build = root.join("BUILD");
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(&build, "a = 3\n").unwrap();
+		// The cache can't create its directory where there is a file.
+		let not_a_dir = root.join("cache");
+		fs::write(&not_a_dir, "").unwrap();
+		let bazel = workspace(root);
+		let documents = Documents::with_cache(Some(IndexCache::new(&not_a_dir)));
+
+		let (loads, cache_error) = documents.index_single(&build, &bazel).unwrap();
+		assert_eq!(loads, Vec::<PathBuf>::new());
+		assert!(cache_error.unwrap().starts_with("Not caching the index"));
+		assert!(documents.snapshot().is_indexed(&build));
+
+		// We only say so once.
+		fs::write(root.join("defs.bzl"), "b = 4\n").unwrap();
+		assert_eq!(documents.index_single(&root.join("defs.bzl"), &bazel), Ok((vec![], None)));
+	}
+}
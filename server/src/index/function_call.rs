@@ -1,22 +1,48 @@
 use tower_lsp::lsp_types as lsp;
 use rustpython_parser::ast;
+use serde::{Deserialize, Serialize};
 
 use crate::index::range::Range;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FunctionCall {
 	range: Range,
 	pub function_name: String,
+	/// The `def` it's made from, where names can also be local.
+	pub function: Option<String>,
+	/// The positional arguments, if the name is called.
+	pub arguments: Vec<Argument>,
+}
+
+/// A positional argument of a call, as much as hints about its parameter need.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Argument {
+	/// Where it starts, including the parentheses around it, if we could tell.
+	pub start: Option<lsp::Position>,
+	/// The name it passes, if it's only that.
+	pub name: Option<String>,
+	/// Whether it's `*args`, after which we can't tell where the arguments go.
+	pub is_starred: bool,
 }
 
 impl FunctionCall {
-	pub fn from_identifier(name: &String, location: ast::Location) -> Self {
+	pub fn from_identifier(name: &str, location: ast::Location) -> Self {
 		FunctionCall {
 			range: Range::from_identifier(name, location),
-			function_name: name.clone(),
+			function_name: name.to_string(),
+			function: None,
+			arguments: vec![],
 		}
 	}
 
+	pub fn with_arguments(self, arguments: Vec<Argument>) -> Self {
+		FunctionCall { arguments, ..self }
+	}
+
+	pub fn range(&self) -> &Range {
+		&self.range
+	}
+
 	pub fn contains_position(&self, position: lsp::Position) -> bool {
 		self.range.contains_position(position)
 	}
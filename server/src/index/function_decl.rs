@@ -1,36 +1,57 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use rustpython_parser::ast;
+use serde::{Deserialize, Serialize};
 
 use crate::index::range::Range;
+use crate::index::definition::Definition;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CallableSymbolSource {
 	DeclaredInFile(Range),
 	Loaded(PathBuf),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FunctionDecl {
 	pub imported_name: String,
 	pub real_name: String,
 	pub source: CallableSymbolSource,
+	// None for loaded symbols, and for assignments we don't recognize.
+	pub definition: Option<Definition>,
 }
 
 impl FunctionDecl {
-	pub fn declared_in_file(name: &String, location: ast::Location) -> Self {
+	pub fn declared_in_file(name: &str, location: ast::Location) -> Self {
 		// We account for the "def " keyword here, which the parser doesn't pick up on.
 		FunctionDecl {
-			imported_name: name.clone(),
-			real_name: name.clone(),
+			imported_name: name.to_string(),
+			real_name: name.to_string(),
 			source: CallableSymbolSource::DeclaredInFile(Range::from_identifier(name, location)),
+			definition: None,
 		}
 	}
 
-	pub fn loaded(name: &String, imported_name: &String, source: &PathBuf) -> Self {
+	pub fn loaded(name: &str, imported_name: &str, source: &Path) -> Self {
 		FunctionDecl {
-			imported_name: imported_name.clone(),
-			real_name: name.clone(),
-			source: CallableSymbolSource::Loaded(source.clone()),
+			imported_name: imported_name.to_string(),
+			real_name: name.to_string(),
+			source: CallableSymbolSource::Loaded(source.to_path_buf()),
+			definition: None,
+		}
+	}
+
+	/// Where it's declared, if that's in the file it's in.
+	pub fn declared_range(&self) -> Option<&Range> {
+		match &self.source {
+			CallableSymbolSource::DeclaredInFile(range) => Some(range),
+			CallableSymbolSource::Loaded(_) => None,
+		}
+	}
+
+	pub fn with_definition(self, definition: Definition) -> Self {
+		FunctionDecl {
+			definition: Some(definition),
+			..self
 		}
 	}
 }
@@ -1,14 +1,24 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use tower_lsp::lsp_types as lsp;
 
 use crate::index::function_decl::FunctionDecl;
 use crate::index::function_call::FunctionCall;
+use crate::index::label_string::LabelString;
+use crate::index::load_statement::LoadStatement;
+use crate::index::local_decl::LocalDecl;
+use crate::index::member_access::MemberAccess;
+use crate::index::range::Range;
 
-#[derive(Default, Debug, Clone, PartialEq)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IndexedDocument {
 	pub declarations: HashMap<String, FunctionDecl>,
 	pub calls: Vec<FunctionCall>,
+	pub loads: Vec<LoadStatement>,
+	pub members: Vec<MemberAccess>,
+	pub labels: Vec<LabelString>,
+	pub locals: Vec<LocalDecl>,
 }
 
 impl IndexedDocument {
@@ -16,12 +26,17 @@ impl IndexedDocument {
 		IndexedDocument {
 			declarations: HashMap::default(),
 			calls: Vec::default(),
+			loads: Vec::default(),
+			members: Vec::default(),
+			labels: Vec::default(),
+			locals: Vec::default(),
 		}
 	}
 
-    pub fn finished(declarations: HashMap<String, FunctionDecl>, calls: Vec<FunctionCall>) -> Self {
+	#[cfg(test)]
+	pub fn finished(declarations: HashMap<String, FunctionDecl>, calls: Vec<FunctionCall>) -> Self {
 		IndexedDocument {
-			declarations, calls
+			declarations, calls, loads: vec![], members: vec![], labels: vec![], locals: vec![],
 		}
 	}
 
@@ -33,7 +48,122 @@ impl IndexedDocument {
 			.cloned()
 	}
 
-	pub fn declaration_of(&self, name: &String) -> Option<FunctionDecl> {
+	pub fn member_at(&self, position: lsp::Position) -> Option<MemberAccess> {
+		self.members
+			.iter()
+			.find(|member| member.contains_position(position))
+			.cloned()
+	}
+
+	/// The name declared at `position`, if the cursor is on a declaration rather than a use.
+	pub fn declaration_at(&self, position: lsp::Position) -> Option<&str> {
+		self.declarations
+			.iter()
+			.find(|(_, decl)| decl.declared_range().map(|range| range.contains_position(position)).unwrap_or(false))
+			.map(|(name, _)| name.as_str())
+	}
+
+	pub fn is_local(&self, name: &str, function: Option<&str>) -> bool {
+		self.locals
+			.iter()
+			.any(|local| Some(local.function.as_str()) == function && local.name == name)
+	}
+
+	/// Highlights the declaration of the symbol at `position`, and its uses in this file,
+	/// without mixing up parameters and the file's declarations of the same name.
+	pub fn highlights(&self, position: lsp::Position) -> Vec<lsp::DocumentHighlight> {
+		let (name, function) = if let Some(call) = self.calls.iter().find(|call| call.contains_position(position)) {
+			let function = call.function.clone().filter(|function| self.is_local(&call.function_name, Some(function)));
+			(call.function_name.clone(), function)
+		} else if let Some(local) = self.locals.iter().find(|local| local.contains_position(position)) {
+			(local.name.clone(), Some(local.function.clone()))
+		} else if let Some(name) = self.declaration_at(position) {
+			(name.to_string(), None)
+		} else {
+			return vec![];
+		};
+		let highlight = |range: &Range, kind| lsp::DocumentHighlight {
+			range: range.as_lsp_range(),
+			kind: Some(kind),
+		};
+		let declarations = match &function {
+			Some(function) => self
+				.locals
+				.iter()
+				.filter(|local| &local.function == function && local.name == name)
+				.map(LocalDecl::range)
+				.collect::<Vec<_>>(),
+			None => self
+				.declarations
+				.get(&name)
+				.and_then(FunctionDecl::declared_range)
+				.into_iter()
+				.collect(),
+		};
+		let uses = self.calls.iter().filter(|call| {
+			call.function_name == name
+				&& match &function {
+					Some(function) => call.function.as_ref() == Some(function),
+					None => !self.is_local(&name, call.function.as_deref()),
+				}
+		});
+		declarations
+			.into_iter()
+			.map(|range| highlight(range, lsp::DocumentHighlightKind::WRITE))
+			.chain(uses.map(|call| highlight(call.range(), lsp::DocumentHighlightKind::READ)))
+			.collect()
+	}
+
+	pub fn declaration_of(&self, name: &str) -> Option<FunctionDecl> {
 		self.declarations.get(name).cloned()
 	}
-}
\ No newline at end of file
+
+	pub fn loaded_files(&self) -> Vec<PathBuf> {
+		let mut files = self
+			.loads
+			.iter()
+			.filter_map(|load| load.path.clone())
+			.collect::<Vec<_>>();
+		files.sort();
+		files.dedup();
+		files
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::ast::process_document;
+	use crate::bazel::BazelWorkspace;
+
+	fn highlights(contents: &str, line: u32, character: u32) -> Vec<(u32, u32, lsp::DocumentHighlightKind)> {
+		let (document, _) = process_document(contents, &BazelWorkspace::new()).unwrap();
+		document
+			.highlights(lsp::Position::new(line, character))
+			.into_iter()
+			.map(|highlight| (highlight.range.start.line, highlight.range.start.character, highlight.kind.unwrap()))
+			.collect()
+	}
+
+	#[test]
+	fn test_highlights_honor_parameters() {
+		let contents = "srcs = []
+def my_macro(srcs):
+  f(srcs)
+f(srcs)
+";
+		let global = vec![
+			(0, 0, lsp::DocumentHighlightKind::WRITE),
+			(3, 2, lsp::DocumentHighlightKind::READ),
+		];
+		let local = vec![
+			(1, 13, lsp::DocumentHighlightKind::WRITE),
+			(2, 4, lsp::DocumentHighlightKind::READ),
+		];
+		assert_eq!(highlights(contents, 0, 1), global);
+		assert_eq!(highlights(contents, 3, 3), global);
+		assert_eq!(highlights(contents, 1, 14), local);
+		assert_eq!(highlights(contents, 2, 5), local);
+		assert_eq!(highlights(contents, 1, 0), vec![]);
+	}
+}
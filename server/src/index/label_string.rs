@@ -0,0 +1,38 @@
+use rustpython_parser::ast;
+use serde::{Deserialize, Serialize};
+
+use crate::index::range::Range;
+
+/// A string argument that could be a label, e.g. in `deps = ["//foo:bar"]` or `srcs = ["lib.rs"]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LabelString {
+	pub label: String,
+	range: Range,
+	/// Whether it's the argument of `Label(...)`, which makes it a label wherever it is.
+	pub in_label_call: bool,
+}
+
+impl LabelString {
+	/// Strings that can't be labels, like messages or glob patterns, give None.
+	pub fn from_string(value: &str, location: ast::Location) -> Option<Self> {
+		let could_be_label = value.len() < 256
+			&& value
+				.chars()
+				.next()
+				.map(|first| first.is_alphanumeric() || "/@:_.".contains(first))
+				.unwrap_or(false)
+			&& !value.contains(|c: char| c.is_whitespace() || "*{}%$".contains(c));
+		if !could_be_label {
+			return None;
+		}
+		Some(LabelString {
+			label: value.to_string(),
+			range: Range::from_identifier(value, location),
+			in_label_call: false,
+		})
+	}
+
+	pub fn range(&self) -> &Range {
+		&self.range
+	}
+}
@@ -0,0 +1,150 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// Which files load which, in both directions.
+///
+/// Forward edges tell us what to index next and where loaded symbols come from.
+/// Reverse edges tell us who needs to be refreshed when a file changes.
+///
+/// The edges live in persistent maps, so that every snapshot can have its own graph
+/// without copying it: a clone shares everything with the original until either changes.
+#[derive(Default, Debug, Clone)]
+pub struct LoadGraph {
+	loads: im::HashMap<PathBuf, Vec<PathBuf>>,
+	loaded_by: im::HashMap<PathBuf, im::HashSet<PathBuf>>,
+}
+
+impl LoadGraph {
+	/// Replaces the outgoing edges of `file`.
+	pub fn set_loads(&mut self, file: &Path, loads: Vec<PathBuf>) {
+		self.remove(file);
+		for loaded in &loads {
+			self.loaded_by
+				.entry(loaded.clone())
+				.or_default()
+				.insert(file.to_path_buf());
+		}
+		self.loads.insert(file.to_path_buf(), loads);
+	}
+
+	/// Forgets the outgoing edges of `file`. Files that load it still do.
+	pub fn remove(&mut self, file: &Path) {
+		for loaded in self.loads.remove(file).unwrap_or_default() {
+			if let Some(loaders) = self.loaded_by.get_mut(&loaded) {
+				loaders.remove(file);
+			}
+		}
+	}
+
+	pub fn loads_of(&self, file: &Path) -> Vec<PathBuf> {
+		self.loads.get(file).cloned().unwrap_or_default()
+	}
+
+	/// The files that load `file` directly.
+	pub fn loaded_by(&self, file: &Path) -> Vec<PathBuf> {
+		let mut loaders = self
+			.loaded_by
+			.get(file)
+			.map(|loaders| loaders.iter().cloned().collect::<Vec<_>>())
+			.unwrap_or_default();
+		loaders.sort();
+		loaders
+	}
+
+	/// Every file that loads `file`, directly or not, closest first.
+	pub fn transitive_loaders(&self, file: &Path) -> Vec<PathBuf> {
+		let mut visited = HashSet::new();
+		visited.insert(file.to_path_buf());
+		let mut pending = VecDeque::from(vec![file.to_path_buf()]);
+		let mut loaders = vec![];
+		while let Some(current) = pending.pop_front() {
+			for loader in self.loaded_by(&current) {
+				if visited.insert(loader.clone()) {
+					loaders.push(loader.clone());
+					pending.push_back(loader);
+				}
+			}
+		}
+		loaders
+	}
+
+	/// Finds the shortest chain of loads that leads from `file` back to itself, if any.
+	///
+	/// The result starts and ends with `file`.
+	pub fn find_cycle(&self, file: &Path) -> Option<Vec<PathBuf>> {
+		let mut parents: HashMap<PathBuf, PathBuf> = HashMap::new();
+		let mut pending = VecDeque::from(vec![file.to_path_buf()]);
+		while let Some(current) = pending.pop_front() {
+			for loaded in self.loads_of(&current) {
+				if loaded == file {
+					let mut cycle = vec![file.to_path_buf()];
+					let mut node = current;
+					// Every file we reached has a parent, except `file` itself.
+					while let Some(parent) = parents.get(&node) {
+						let parent = parent.clone();
+						cycle.push(node);
+						node = parent;
+					}
+					cycle.push(file.to_path_buf());
+					cycle.reverse();
+					return Some(cycle);
+				}
+				if !parents.contains_key(&loaded) {
+					parents.insert(loaded.clone(), current.clone());
+					pending.push_back(loaded);
+				}
+			}
+		}
+		None
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn paths(names: &[&str]) -> Vec<PathBuf> {
+		names.iter().map(PathBuf::from).collect()
+	}
+
+	fn graph(edges: &[(&str, &[&str])]) -> LoadGraph {
+		let mut graph = LoadGraph::default();
+		for (file, loads) in edges {
+			graph.set_loads(Path::new(file), paths(loads));
+		}
+		graph
+	}
+
+	#[test]
+	fn test_reverse_edges_follow_updates() {
+		let mut graph = graph(&[("BUILD", &["a.bzl", "b.bzl"]), ("a.bzl", &["b.bzl"])]);
+		assert_eq!(graph.loaded_by(Path::new("b.bzl")), paths(&["BUILD", "a.bzl"]));
+		assert_eq!(graph.transitive_loaders(Path::new("b.bzl")), paths(&["BUILD", "a.bzl"]));
+
+		graph.set_loads(Path::new("BUILD"), paths(&["a.bzl"]));
+		assert_eq!(graph.loaded_by(Path::new("b.bzl")), paths(&["a.bzl"]));
+		assert_eq!(graph.transitive_loaders(Path::new("b.bzl")), paths(&["a.bzl", "BUILD"]));
+	}
+
+	#[test]
+	fn test_find_cycle() {
+		let graph = graph(&[
+			("BUILD", &["a.bzl"]),
+			("a.bzl", &["b.bzl"]),
+			("b.bzl", &["c.bzl"]),
+			("c.bzl", &["a.bzl"]),
+		]);
+		assert_eq!(graph.find_cycle(Path::new("BUILD")), None);
+		assert_eq!(
+			graph.find_cycle(Path::new("a.bzl")),
+			Some(paths(&["a.bzl", "b.bzl", "c.bzl", "a.bzl"]))
+		);
+		assert_eq!(graph.transitive_loaders(Path::new("a.bzl")), paths(&["BUILD", "c.bzl", "b.bzl"]));
+	}
+
+	#[test]
+	fn test_self_load_is_a_cycle() {
+		let graph = graph(&[("a.bzl", &["a.bzl"])]);
+		assert_eq!(graph.find_cycle(Path::new("a.bzl")), Some(paths(&["a.bzl", "a.bzl"])));
+	}
+}
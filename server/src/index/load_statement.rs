@@ -0,0 +1,50 @@
+use rustpython_parser::ast;
+use tower_lsp::lsp_types as lsp;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::index::range::Range;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoadStatement {
+	pub label: String,
+	// None if we couldn't resolve the label to a file.
+	pub path: Option<PathBuf>,
+	label_range: Range,
+	// The names this load binds in the loading file, in the order they appear.
+	pub symbols: Vec<String>,
+	// Where each symbol is named, both as the alias and as the loaded string for `alias = "name"`.
+	symbol_ranges: Vec<(String, Range)>,
+}
+
+impl LoadStatement {
+	pub fn new(label: &str, label_location: ast::Location, path: Option<PathBuf>, symbols: Vec<String>) -> Self {
+		LoadStatement {
+			label: label.to_string(),
+			path,
+			label_range: Range::from_identifier(label, label_location),
+			symbols,
+			symbol_ranges: vec![],
+		}
+	}
+
+	pub fn with_symbol_ranges(self, symbol_ranges: Vec<(String, Range)>) -> Self {
+		LoadStatement { symbol_ranges, ..self }
+	}
+
+	/// The name the symbol at `position` is bound to in the loading file.
+	pub fn symbol_at(&self, position: lsp::Position) -> Option<&str> {
+		self.symbol_ranges
+			.iter()
+			.find(|(_, range)| range.contains_position(position))
+			.map(|(symbol, _)| symbol.as_str())
+	}
+
+	pub fn symbol_ranges(&self) -> &[(String, Range)] {
+		&self.symbol_ranges
+	}
+
+	pub fn label_range(&self) -> &Range {
+		&self.label_range
+	}
+}
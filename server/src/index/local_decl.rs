@@ -0,0 +1,42 @@
+use tower_lsp::lsp_types as lsp;
+use rustpython_parser::ast;
+use serde::{Deserialize, Serialize};
+
+use crate::index::range::Range;
+
+/// A name bound in the body of a `def`, which shadows the file's declarations there.
+///
+/// These are its parameters, and the names it assigns to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocalDecl {
+	range: Range,
+	pub name: String,
+	pub function: String,
+	pub is_parameter: bool,
+}
+
+impl LocalDecl {
+	pub fn parameter(name: &str, function: &str, location: ast::Location) -> Self {
+		LocalDecl {
+			range: Range::from_identifier(name, location),
+			name: name.to_string(),
+			function: function.to_string(),
+			is_parameter: true,
+		}
+	}
+
+	pub fn variable(name: &str, function: &str, location: ast::Location) -> Self {
+		LocalDecl {
+			is_parameter: false,
+			..LocalDecl::parameter(name, function, location)
+		}
+	}
+
+	pub fn range(&self) -> &Range {
+		&self.range
+	}
+
+	pub fn contains_position(&self, position: lsp::Position) -> bool {
+		self.range.contains_position(position)
+	}
+}
@@ -0,0 +1,44 @@
+use tower_lsp::lsp_types as lsp;
+use rustpython_parser::ast;
+use serde::{Deserialize, Serialize};
+
+use crate::index::range::Range;
+
+/// What a member is accessed on, as far as we can tell without evaluating anything.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Receiver {
+	/// A name, e.g. `providers` in `providers.MyInfo`. Dotted when it's a member itself, e.g. `ctx.attr`.
+	Name(String),
+	/// The instance of a provider that a target carries, e.g. `dep[MyInfo]`.
+	Provider(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemberAccess {
+	// The range of the member's name.
+	range: Range,
+	pub receiver: Receiver,
+	pub member: String,
+	/// The `def` it's in, if any.
+	pub function: Option<String>,
+}
+
+impl MemberAccess {
+	/// The parser locates attribute expressions at their `.`, so the member's name starts right after it.
+	pub fn after_dot(receiver: Receiver, member: &str, dot_location: ast::Location) -> Self {
+		MemberAccess {
+			range: Range::from_identifier(member, ast::Location::new(dot_location.row(), dot_location.column() + 1)),
+			receiver,
+			member: member.to_string(),
+			function: None,
+		}
+	}
+
+	pub fn range(&self) -> &Range {
+		&self.range
+	}
+
+	pub fn contains_position(&self, position: lsp::Position) -> bool {
+		self.range.contains_position(position)
+	}
+}
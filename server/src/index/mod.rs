@@ -1,6 +1,16 @@
+pub mod cache;
+pub mod definition;
 pub mod documents;
 pub mod indexed_document;
+pub mod label_string;
+pub mod load_graph;
+pub mod load_statement;
+pub mod local_decl;
+pub mod member_access;
+pub mod query;
 pub mod range;
+pub mod signature;
+pub mod snapshot;
 pub mod function_call;
 pub mod function_decl;
 
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+/// A counter that goes up every time the index changes.
+pub type Revision = u64;
+
+/// The memoized result of a query, and when it was last computed and last changed.
+///
+/// Keeping the two apart is what lets us stop early: if a query is recomputed and
+/// gives the same value as before, we keep the old `changed_at` ("backdating", in salsa's
+/// terms), so queries that depend on it can tell that they don't need to be recomputed.
+#[derive(Debug, Clone)]
+pub struct Memo<T> {
+	value: Arc<T>,
+	computed_at: Revision,
+	changed_at: Revision,
+}
+
+impl<T: PartialEq> Memo<T> {
+	pub fn new(value: T, revision: Revision) -> Self {
+		Memo {
+			value: Arc::new(value),
+			computed_at: revision,
+			changed_at: revision,
+		}
+	}
+
+	pub fn update(&self, value: T, revision: Revision) -> Self {
+		if *self.value == value {
+			Memo {
+				value: self.value.clone(),
+				computed_at: revision,
+				changed_at: self.changed_at,
+			}
+		} else {
+			Memo::new(value, revision)
+		}
+	}
+
+	pub fn value(&self) -> &Arc<T> {
+		&self.value
+	}
+
+	pub fn computed_at(&self) -> Revision {
+		self.computed_at
+	}
+
+	pub fn changed_at(&self) -> Revision {
+		self.changed_at
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_equal_values_are_backdated() {
+		let memo = Memo::new(vec![1, 2], 1);
+
+		let same = memo.update(vec![1, 2], 2);
+		assert_eq!((same.computed_at(), same.changed_at()), (2, 1));
+
+		let different = same.update(vec![1, 2, 3], 3);
+		assert_eq!((different.computed_at(), different.changed_at()), (3, 3));
+		assert_eq!(**different.value(), vec![1, 2, 3]);
+	}
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types as lsp;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Parameter {
+	// Includes the stars of `*args` and `**kwargs`.
+	pub name: String,
+	// The type of rule attributes, e.g. `label_list` for `attr.label_list()`.
+	pub attr_type: Option<String>,
+	pub mandatory: bool,
+	// As it appears in the source.
+	pub default: Option<String>,
+	pub doc: Option<String>,
+}
+
+impl Parameter {
+	/// Completes the parameter as the keyword of an argument.
+	pub fn as_lsp_completion_item(&self) -> lsp::CompletionItem {
+		let mut detail = self.attr_type.clone().unwrap_or_default();
+		if self.mandatory {
+			detail = format!("{} (mandatory)", detail).trim().to_string();
+		}
+		lsp::CompletionItem {
+			label: self.name.clone(),
+			kind: Some(lsp::CompletionItemKind::FIELD),
+			detail: Some(detail).filter(|detail| !detail.is_empty()),
+			documentation: self.doc.as_ref().map(|doc| lsp::Documentation::String(doc.trim().to_string())),
+			insert_text: Some(format!("{} = ", self.name)),
+			..lsp::CompletionItem::default()
+		}
+	}
+
+	pub fn new(name: &str) -> Self {
+		Parameter {
+			name: name.to_string(),
+			attr_type: None,
+			mandatory: false,
+			default: None,
+			doc: None,
+		}
+	}
+
+	fn is_variadic(&self) -> bool {
+		self.name.starts_with('*')
+	}
+
+	/// Whether this is `*args`, which takes the positional arguments left over.
+	fn takes_extra_arguments(&self) -> bool {
+		self.name.starts_with('*') && !self.name.starts_with("**") && self.name.len() > 1
+	}
+
+	fn label(&self) -> String {
+		let mut label = self.name.clone();
+		if let Some(attr_type) = &self.attr_type {
+			label.push_str(&format!(": {}", attr_type));
+		}
+		if let Some(default) = &self.default {
+			label.push_str(&format!(" = {}", default));
+		}
+		label
+	}
+
+	fn documentation(&self) -> Option<lsp::Documentation> {
+		let mut lines = vec![];
+		if self.mandatory {
+			lines.push("Mandatory".to_string());
+		}
+		lines.extend(self.doc.iter().map(|doc| doc.trim().to_string()));
+		if lines.is_empty() {
+			None
+		} else {
+			Some(lsp::Documentation::String(lines.join("\n\n")))
+		}
+	}
+}
+
+/// The parameters a callable takes: the arguments of a `def`, or the attributes of a rule.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Signature {
+	pub parameters: Vec<Parameter>,
+}
+
+impl Signature {
+	/// The parameter an argument goes to, given its position and keyword, if it has one.
+	pub fn parameter_for(&self, argument: usize, keyword: Option<&str>) -> Option<usize> {
+		match keyword {
+			Some(keyword) => self.parameters.iter().position(|param| param.name == keyword).or_else(|| {
+				self.parameters.iter().position(|param| param.name.starts_with("**"))
+			}),
+			None => {
+				// Positional arguments go up to the first of `*args`, `*` or `**kwargs`,
+				// and only `*args` takes the ones left over.
+				let positional = self
+					.parameters
+					.iter()
+					.position(Parameter::is_variadic)
+					.unwrap_or(self.parameters.len());
+				match self.parameters.get(positional) {
+					Some(param) if argument >= positional && param.takes_extra_arguments() => Some(positional),
+					_ => Some(argument).filter(|argument| *argument < positional),
+				}
+			}
+		}
+	}
+
+	/// The parameters as they would be written after the name, e.g. `(a, b = 1)`.
+	pub fn as_arguments(&self) -> String {
+		let labels = self.parameters.iter().map(Parameter::label).collect::<Vec<_>>();
+		format!("({})", labels.join(", "))
+	}
+
+	pub fn as_lsp_signature_help(&self, name: &str, doc: Option<&str>, active_parameter: Option<usize>) -> lsp::SignatureHelp {
+		let labels = self.parameters.iter().map(Parameter::label).collect::<Vec<_>>();
+		let parameters = self
+			.parameters
+			.iter()
+			.zip(&labels)
+			.map(|(param, label)| lsp::ParameterInformation {
+				label: lsp::ParameterLabel::Simple(label.clone()),
+				documentation: param.documentation(),
+			})
+			.collect();
+		lsp::SignatureHelp {
+			signatures: vec![lsp::SignatureInformation {
+				label: format!("{}{}", name, self.as_arguments()),
+				documentation: doc.map(|doc| lsp::Documentation::String(doc.trim().to_string())),
+				parameters: Some(parameters),
+				active_parameter: None,
+			}],
+			active_signature: Some(0),
+			active_parameter: active_parameter.map(|active| active as u32),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn signature(names: &[&str]) -> Signature {
+		Signature {
+			parameters: names.iter().map(|name| Parameter::new(name)).collect(),
+		}
+	}
+
+	#[test]
+	fn test_arguments_go_to_their_parameters() {
+		let signature = signature(&["a", "b", "*args", "**kwargs"]);
+		assert_eq!(signature.parameter_for(1, None), Some(1));
+		assert_eq!(signature.parameter_for(5, None), Some(2));
+		assert_eq!(signature.parameter_for(0, Some("b")), Some(1));
+		assert_eq!(signature.parameter_for(0, Some("c")), Some(3));
+
+		let signature = self::signature(&["a"]);
+		assert_eq!(signature.parameter_for(1, None), None);
+		assert_eq!(signature.parameter_for(0, Some("c")), None);
+
+		// Neither `**kwargs` nor keyword-only parameters take positional arguments.
+		let signature = self::signature(&["a", "**kwargs"]);
+		assert_eq!(signature.parameter_for(0, None), Some(0));
+		assert_eq!(signature.parameter_for(1, None), None);
+		let signature = self::signature(&["a", "*", "b"]);
+		assert_eq!(signature.parameter_for(1, None), None);
+		assert_eq!(signature.parameter_for(0, Some("b")), Some(2));
+	}
+}
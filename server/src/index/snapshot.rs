@@ -0,0 +1,609 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tower_lsp::lsp_types as lsp;
+
+use crate::index::definition::Definition;
+use crate::index::function_decl::{CallableSymbolSource, FunctionDecl};
+use crate::index::indexed_document::IndexedDocument;
+use crate::index::load_graph::LoadGraph;
+use crate::index::member_access::Receiver;
+use crate::index::range::Range;
+use crate::index::query::{Memo, Revision};
+
+/// What other files can see of a file: the names it binds.
+///
+/// Unlike the document, this doesn't change when a function body does,
+/// so the files that load it don't need to resolve their loads again.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileSymbols {
+	names: BTreeSet<String>,
+}
+
+impl FileSymbols {
+	fn of(doc: &IndexedDocument) -> Self {
+		FileSymbols {
+			names: doc.declarations.keys().cloned().collect(),
+		}
+	}
+
+	pub fn contains(&self, name: &str) -> bool {
+		self.names.contains(name)
+	}
+}
+
+/// A symbol loaded by a file, and whether the loaded file defines it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedLoad {
+	pub label: String,
+	pub label_range: lsp::Range,
+	pub symbol: String,
+	// None if we couldn't resolve the label, or haven't indexed the loaded file yet.
+	pub defined: Option<bool>,
+}
+
+/// Every query we memoize for a file, from its parsed contents to its diagnostics.
+#[derive(Debug, Clone)]
+struct FileEntry {
+	// The hash of the contents the document was parsed from.
+	hash: String,
+	document: Memo<IndexedDocument>,
+	symbols: Memo<FileSymbols>,
+	resolved_loads: Memo<Vec<ResolvedLoad>>,
+	diagnostics: Memo<Vec<lsp::Diagnostic>>,
+}
+
+/// A change to the inputs of the index.
+#[derive(Debug)]
+pub enum Change {
+	Indexed {
+		path: PathBuf,
+		hash: String,
+		document: IndexedDocument,
+	},
+	Removed(PathBuf),
+}
+
+/// An immutable view of the whole index at one revision.
+///
+/// Requests work on a snapshot of their own, so they always see documents, load graph and
+/// diagnostics that agree with each other, and never wait for the index to be updated.
+/// Updates build the next snapshot out of this one, recomputing only the queries
+/// whose inputs changed; everything else is shared with the previous snapshot.
+/// Since the maps are persistent, cloning a snapshot is cheap no matter how big the index is.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+	revision: Revision,
+	files: im::HashMap<PathBuf, Arc<FileEntry>>,
+	load_graph: LoadGraph,
+	// The `.bzl` files that declare each public name, which other files could load it from.
+	exporters: im::HashMap<String, im::OrdSet<PathBuf>>,
+}
+
+impl Snapshot {
+	pub fn get_doc(&self, doc: &Path) -> Option<Arc<IndexedDocument>> {
+		self.files.get(doc).map(|entry| entry.document.value().clone())
+	}
+
+	pub fn is_indexed(&self, doc: &Path) -> bool {
+		self.files.contains_key(doc)
+	}
+
+	/// Whether `doc` was indexed from contents with this hash.
+	pub fn is_up_to_date(&self, doc: &Path, hash: &str) -> bool {
+		self.files.get(doc).map(|entry| entry.hash == hash).unwrap_or(false)
+	}
+
+	pub fn loads_of(&self, doc: &Path) -> Vec<PathBuf> {
+		self.load_graph.loads_of(doc)
+	}
+
+	/// The files that load `doc` directly.
+	pub fn loaded_by(&self, doc: &Path) -> Vec<PathBuf> {
+		self.load_graph.loaded_by(doc)
+	}
+
+	/// The `.bzl` files that declare `name`, so that it can be loaded from them.
+	pub fn exporters_of(&self, name: &str) -> Vec<PathBuf> {
+		self.exporters
+			.get(name)
+			.map(|files| files.iter().cloned().collect())
+			.unwrap_or_default()
+	}
+
+	/// Every file that loads `doc`, directly or not, closest first.
+	pub fn transitive_loaders(&self, doc: &Path) -> Vec<PathBuf> {
+		self.load_graph.transitive_loaders(doc)
+	}
+
+	pub fn indexed_files(&self) -> Vec<PathBuf> {
+		let mut files = self.files.keys().cloned().collect::<Vec<_>>();
+		files.sort();
+		files
+	}
+
+	pub fn diagnostics(&self, doc: &Path) -> Vec<lsp::Diagnostic> {
+		self.files
+			.get(doc)
+			.map(|entry| (**entry.diagnostics.value()).clone())
+			.unwrap_or_default()
+	}
+
+	/// Applies `changes` and brings every query that depends on them up to date.
+	///
+	/// Returns the files whose diagnostics changed.
+	pub fn apply(&mut self, changes: Vec<Change>) -> Vec<PathBuf> {
+		self.revision += 1;
+		let revision = self.revision;
+		// Files whose loads might resolve differently now.
+		let mut unresolved = BTreeSet::new();
+		// Files whose outgoing loads changed, which might have closed or broken a cycle.
+		let mut relinked = BTreeSet::new();
+		for change in changes {
+			match change {
+				Change::Indexed { path, hash, document } => {
+					let loads = document.loaded_files();
+					if !self.files.contains_key(&path) || self.load_graph.loads_of(&path) != loads {
+						self.load_graph.set_loads(&path, loads);
+						relinked.insert(path.clone());
+					}
+					let symbols = FileSymbols::of(&document);
+					if let Some(previous) = self.files.get(&path).cloned() {
+						self.unexport(&path, previous.document.value());
+					}
+					self.export(&path, &document);
+					let entry = match self.files.get(&path) {
+						Some(previous) => FileEntry {
+							hash,
+							document: Memo::new(document, revision),
+							symbols: previous.symbols.update(symbols, revision),
+							resolved_loads: previous.resolved_loads.clone(),
+							diagnostics: previous.diagnostics.clone(),
+						},
+						None => FileEntry {
+							hash,
+							document: Memo::new(document, revision),
+							symbols: Memo::new(symbols, revision),
+							// Never computed, so that it's stale below.
+							resolved_loads: Memo::new(vec![], 0),
+							diagnostics: Memo::new(vec![], revision),
+						},
+					};
+					self.files.insert(path.clone(), Arc::new(entry));
+					unresolved.insert(path);
+				}
+				Change::Removed(path) => {
+					if let Some(previous) = self.files.remove(&path) {
+						self.unexport(&path, previous.document.value());
+						self.load_graph.remove(&path);
+						unresolved.extend(self.load_graph.loaded_by(&path));
+						relinked.insert(path);
+					}
+				}
+			}
+		}
+
+		// Only the files that load a file whose symbols changed might need to look at it again.
+		let symbols_changed = unresolved
+			.iter()
+			.filter(|path| self.symbols_changed_at(path) == Some(revision))
+			.cloned()
+			.collect::<Vec<_>>();
+		for path in symbols_changed {
+			unresolved.extend(self.load_graph.loaded_by(&path));
+		}
+
+		let mut to_diagnose = BTreeSet::new();
+		for path in unresolved {
+			let entry = match self.files.get(&path) {
+				Some(entry) if self.resolved_loads_are_stale(entry) => entry,
+				_ => continue,
+			};
+			let resolved_loads = entry
+				.resolved_loads
+				.update(self.compute_resolved_loads(entry.document.value()), revision);
+			if resolved_loads.changed_at() == revision || entry.document.changed_at() == revision {
+				to_diagnose.insert(path.clone());
+			}
+			let mut entry = (**entry).clone();
+			entry.resolved_loads = resolved_loads;
+			self.files.insert(path, Arc::new(entry));
+		}
+		// A cycle through a file goes through every file it loads and every file that loads it.
+		for path in relinked {
+			to_diagnose.extend(self.load_graph.transitive_loaders(&path));
+			to_diagnose.insert(path);
+		}
+
+		let mut diagnostics_changed = vec![];
+		for path in to_diagnose {
+			let entry = match self.files.get(&path) {
+				Some(entry) => entry,
+				None => continue,
+			};
+			let diagnostics = entry
+				.diagnostics
+				.update(self.compute_diagnostics(&path, entry), revision);
+			if diagnostics.changed_at() == revision {
+				diagnostics_changed.push(path.clone());
+			}
+			let mut entry = (**entry).clone();
+			entry.diagnostics = diagnostics;
+			self.files.insert(path, Arc::new(entry));
+		}
+		diagnostics_changed
+	}
+
+	fn export(&mut self, path: &Path, doc: &IndexedDocument) {
+		for name in exported_names(path, doc) {
+			self.exporters.entry(name).or_default().insert(path.to_path_buf());
+		}
+	}
+
+	fn unexport(&mut self, path: &Path, doc: &IndexedDocument) {
+		for name in exported_names(path, doc) {
+			if let Some(files) = self.exporters.get_mut(&name) {
+				files.remove(path);
+				if files.is_empty() {
+					self.exporters.remove(&name);
+				}
+			}
+		}
+	}
+
+	fn symbols_changed_at(&self, doc: &Path) -> Option<Revision> {
+		self.files.get(doc).map(|entry| entry.symbols.changed_at())
+	}
+
+	/// Whether the document, or the symbols of any file it loads, changed since we last resolved its loads.
+	fn resolved_loads_are_stale(&self, entry: &FileEntry) -> bool {
+		let computed_at = entry.resolved_loads.computed_at();
+		entry.document.changed_at() > computed_at
+			|| entry.document.value().loaded_files().iter().any(|loaded| {
+				self.symbols_changed_at(loaded)
+					.map(|changed_at| changed_at > computed_at)
+					// Gone, so whatever we resolved against it is.
+					.unwrap_or(true)
+			})
+	}
+
+	fn compute_resolved_loads(&self, doc: &IndexedDocument) -> Vec<ResolvedLoad> {
+		doc.loads
+			.iter()
+			.flat_map(|load| {
+				let symbols = load
+					.path
+					.as_ref()
+					.and_then(|path| self.files.get(path))
+					.map(|entry| entry.symbols.value().clone());
+				load.symbols.iter().map(move |imported_name| {
+					// The load binds `imported_name`, but the loaded file might know it by another name.
+					let real_name = doc
+						.declaration_of(imported_name)
+						.map(|decl| decl.real_name)
+						.unwrap_or_else(|| imported_name.clone());
+					ResolvedLoad {
+						label: load.label.clone(),
+						label_range: load.label_range().as_lsp_range(),
+						defined: symbols.as_ref().map(|symbols| symbols.contains(&real_name)),
+						symbol: real_name,
+					}
+				})
+			})
+			.collect()
+	}
+
+	fn compute_diagnostics(&self, path: &Path, entry: &FileEntry) -> Vec<lsp::Diagnostic> {
+		let mut diagnostics = entry
+			.resolved_loads
+			.value()
+			.iter()
+			.filter(|resolved| resolved.defined == Some(false))
+			.map(|resolved| lsp::Diagnostic {
+				range: resolved.label_range,
+				severity: Some(lsp::DiagnosticSeverity::ERROR),
+				source: Some("bazel-lsp".to_string()),
+				message: format!("'{}' is not defined in {}", resolved.symbol, resolved.label),
+				..lsp::Diagnostic::default()
+			})
+			.collect::<Vec<_>>();
+		if let Some(cycle) = self.load_graph.find_cycle(path) {
+			let chain = cycle
+				.iter()
+				.map(|file| format!("{}", file.display()))
+				.collect::<Vec<_>>()
+				.join(" -> ");
+			diagnostics.extend(
+				entry
+					.document
+					.value()
+					.loads
+					.iter()
+					.filter(|load| load.path.as_ref() == cycle.get(1))
+					.map(|load| lsp::Diagnostic {
+						range: load.label_range().as_lsp_range(),
+						severity: Some(lsp::DiagnosticSeverity::ERROR),
+						source: Some("bazel-lsp".to_string()),
+						message: format!("Load cycle: {}", chain),
+						..lsp::Diagnostic::default()
+					}),
+			);
+		}
+		diagnostics
+	}
+
+	/// Goes to what the symbol at `position` refers to, whether it's used, loaded or declared there.
+	pub fn locate_definition_at(&self, doc: &Path, position: lsp::Position) -> Option<lsp::Location> {
+		self.locate_declaration_of_call_at(doc, position)
+			.or_else(|| self.locate_declaration_of_member_at(doc, position))
+			.or_else(|| self.locate_load_at(doc, position))
+			.or_else(|| self.locate_declaration_at(doc, position))
+	}
+
+	fn locate_declaration_of_call_at(&self, doc: &Path, position: lsp::Position) -> Option<lsp::Location> {
+		let indexed_doc = self.get_doc(doc)?;
+		let call = indexed_doc.call_at(position)?;
+		// Parameters shadow the file's declarations.
+		let parameter = indexed_doc
+			.locals
+			.iter()
+			.find(|local| Some(&local.function) == call.function.as_ref() && local.name == call.function_name);
+		match parameter {
+			Some(parameter) => location(doc, parameter.range()),
+			None => self.locate_declaration(doc, &call.function_name),
+		}
+	}
+
+	/// Goes from the strings of a load to what they load: a symbol, or the file itself.
+	fn locate_load_at(&self, doc: &Path, position: lsp::Position) -> Option<lsp::Location> {
+		let indexed_doc = self.get_doc(doc)?;
+		let load = indexed_doc
+			.loads
+			.iter()
+			.find(|load| load.symbol_at(position).is_some() || load.label_range().contains_position(position))?;
+		let file = || Some(lsp::Location::new(lsp::Url::from_file_path(load.path.as_ref()?).ok()?, lsp::Range::default()));
+		match load.symbol_at(position) {
+			Some(symbol) => self.locate_declaration(doc, symbol).or_else(file),
+			None => file(),
+		}
+	}
+
+	/// Declarations go to themselves, so that clients can offer their references instead.
+	fn locate_declaration_at(&self, doc: &Path, position: lsp::Position) -> Option<lsp::Location> {
+		let indexed_doc = self.get_doc(doc)?;
+		match indexed_doc.declaration_at(position) {
+			Some(name) => location(doc, indexed_doc.declarations.get(name)?.declared_range()?),
+			None => location(doc, indexed_doc.locals.iter().find(|local| local.contains_position(position))?.range()),
+		}
+	}
+
+	fn locate_declaration(&self, doc: &Path, name: &str) -> Option<lsp::Location> {
+		let (file, decl) = self.resolve_declaration(doc, name)?;
+		location(&file, decl.declared_range()?)
+	}
+
+	/// Goes from `foo.bar` to the `bar = ...` of `foo = struct(...)`, and from `dep[MyInfo].bar`
+	/// to the `bar` field of `MyInfo = provider(...)`.
+	fn locate_declaration_of_member_at(&self, doc: &Path, position: lsp::Position) -> Option<lsp::Location> {
+		let access = self.get_doc(doc)?.member_at(position)?;
+		let (file, definition) = match &access.receiver {
+			Receiver::Name(name) | Receiver::Provider(name) => {
+				let (file, decl) = self.resolve_declaration(doc, name)?;
+				(file, decl.definition?)
+			}
+		};
+		let fields = match (&access.receiver, definition) {
+			(Receiver::Name(_), Definition::Struct { fields }) => fields,
+			(Receiver::Provider(_), Definition::Provider { fields, .. }) => fields,
+			_ => return None,
+		};
+		let field = fields.into_iter().find(|field| field.name == access.member)?;
+		location(&file, &field.range)
+	}
+
+	/// Follows `name`, as seen from `doc`, through loads to the file that declares it.
+	pub fn resolve_declaration(&self, doc: &Path, name: &str) -> Option<(PathBuf, FunctionDecl)> {
+		let decl = self.get_doc(doc)?.declaration_of(name)?;
+		self.follow_loads(decl, doc, &mut HashSet::new())
+	}
+
+	// `visited` guards against re-exports that load each other in a loop.
+	fn follow_loads(
+		&self,
+		start: FunctionDecl,
+		current_file: &Path,
+		visited: &mut HashSet<(PathBuf, String)>,
+	) -> Option<(PathBuf, FunctionDecl)> {
+		if !visited.insert((current_file.to_path_buf(), start.imported_name.clone())) {
+			return None;
+		}
+		match &start.source {
+			CallableSymbolSource::DeclaredInFile(_) => Some((current_file.to_path_buf(), start)),
+			CallableSymbolSource::Loaded(loaded_path) => {
+				let new_declaration = self
+					.get_doc(loaded_path)?
+					.declaration_of(&start.real_name)?;
+				self.follow_loads(new_declaration, loaded_path, visited)
+			}
+		}
+	}
+}
+
+/// The names `doc` declares for other files to load, if it's a `.bzl` file: the public ones it doesn't load itself.
+fn exported_names<'d>(path: &Path, doc: &'d IndexedDocument) -> impl Iterator<Item = String> + 'd {
+	let is_bzl = path.extension().map(|extension| extension == "bzl").unwrap_or(false);
+	doc.declarations
+		.iter()
+		.filter(move |(name, decl)| is_bzl && !name.starts_with('_') && decl.declared_range().is_some())
+		.map(|(name, _)| name.clone())
+}
+
+fn location(file: &Path, range: &Range) -> Option<lsp::Location> {
+	Some(lsp::Location::new(lsp::Url::from_file_path(file).ok()?, range.as_lsp_range()))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::index::definition::Field;
+	use crate::index::load_statement::LoadStatement;
+	use crate::index::member_access::MemberAccess;
+	use rustpython_parser::ast;
+
+	fn defs(functions: &[(&str, usize)]) -> IndexedDocument {
+		IndexedDocument::finished(
+			functions
+				.iter()
+				.map(|(name, row)| (name.to_string(), FunctionDecl::declared_in_file(name, ast::Location::new(*row, 5))))
+				.collect(),
+			vec![],
+		)
+	}
+
+	fn loading(path: &str, symbols: &[&str]) -> IndexedDocument {
+		let mut doc = IndexedDocument::new();
+		for symbol in symbols {
+			doc.declarations
+				.insert(symbol.to_string(), FunctionDecl::loaded(symbol, symbol, Path::new(path)));
+		}
+		doc.loads.push(LoadStatement::new(
+			path,
+			ast::Location::new(1, 7),
+			Some(PathBuf::from(path)),
+			symbols.iter().map(|symbol| symbol.to_string()).collect(),
+		));
+		doc
+	}
+
+	fn indexed(path: &str, document: IndexedDocument) -> Change {
+		Change::Indexed {
+			path: PathBuf::from(path),
+			hash: format!("{:?}", document),
+			document,
+		}
+	}
+
+	fn entry<'a>(snapshot: &'a Snapshot, path: &str) -> &'a FileEntry {
+		snapshot.files.get(Path::new(path)).unwrap()
+	}
+
+	#[test]
+	fn test_editing_a_body_does_not_resolve_loaders_again() {
+		let mut snapshot = Snapshot::default();
+		snapshot.apply(vec![
+			indexed("defs.bzl", defs(&[("my_macro", 1)])),
+			indexed("BUILD", loading("defs.bzl", &["my_macro"])),
+		]);
+		let resolved_at = entry(&snapshot, "BUILD").resolved_loads.computed_at();
+
+		// Moving the declaration around changes the document, but not what it defines.
+		let changed = snapshot.apply(vec![indexed("defs.bzl", defs(&[("my_macro", 3)]))]);
+
+		assert_eq!(changed, Vec::<PathBuf>::new());
+		assert_eq!(entry(&snapshot, "defs.bzl").document.changed_at(), 2);
+		assert_eq!(entry(&snapshot, "defs.bzl").symbols.changed_at(), 1);
+		assert_eq!(entry(&snapshot, "BUILD").resolved_loads.computed_at(), resolved_at);
+	}
+
+	#[test]
+	fn test_loaders_are_diagnosed_when_symbols_go_away() {
+		let mut snapshot = Snapshot::default();
+		snapshot.apply(vec![
+			indexed("defs.bzl", defs(&[("my_macro", 1)])),
+			indexed("BUILD", loading("defs.bzl", &["my_macro"])),
+		]);
+		assert_eq!(snapshot.diagnostics(Path::new("BUILD")), vec![]);
+		let before = snapshot.clone();
+
+		let changed = snapshot.apply(vec![indexed("defs.bzl", defs(&[("other_macro", 1)]))]);
+
+		assert_eq!(changed, vec![PathBuf::from("BUILD")]);
+		let diagnostics = snapshot.diagnostics(Path::new("BUILD"));
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].message, "'my_macro' is not defined in defs.bzl");
+		// Older snapshots are untouched.
+		assert_eq!(before.diagnostics(Path::new("BUILD")), vec![]);
+	}
+
+	#[test]
+	fn test_cycles_are_diagnosed_in_every_file() {
+		let mut snapshot = Snapshot::default();
+		snapshot.apply(vec![
+			indexed("a.bzl", loading("b.bzl", &[])),
+			indexed("b.bzl", defs(&[])),
+		]);
+
+		let changed = snapshot.apply(vec![indexed("b.bzl", loading("a.bzl", &[]))]);
+		assert_eq!(changed, vec![PathBuf::from("a.bzl"), PathBuf::from("b.bzl")]);
+
+		let changed = snapshot.apply(vec![Change::Removed(PathBuf::from("b.bzl"))]);
+		assert_eq!(changed, vec![PathBuf::from("a.bzl")]);
+		assert_eq!(snapshot.diagnostics(Path::new("a.bzl")), vec![]);
+	}
+
+	#[test]
+	fn test_members_resolve_to_fields_through_loads() {
+		let field = |name: &str, row| Field {
+			name: name.to_string(),
+			doc: None,
+			range: Range::from_identifier(name, ast::Location::new(row, 3)),
+		};
+		let mut defs = defs(&[("providers", 1), ("MyInfo", 2)]);
+		defs.declarations.get_mut("providers").unwrap().definition = Some(Definition::Struct {
+			fields: vec![field("MyInfo", 1)],
+		});
+		defs.declarations.get_mut("MyInfo").unwrap().definition = Some(Definition::Provider {
+			fields: vec![field("files", 2)],
+			doc: None,
+		});
+		let mut build = loading("/ws/defs.bzl", &["providers", "MyInfo"]);
+		build.members = vec![
+			MemberAccess::after_dot(Receiver::Name("providers".to_string()), "MyInfo", ast::Location::new(2, 10)),
+			MemberAccess::after_dot(Receiver::Provider("MyInfo".to_string()), "files", ast::Location::new(3, 12)),
+			MemberAccess::after_dot(Receiver::Name("MyInfo".to_string()), "files", ast::Location::new(4, 7)),
+		];
+		let mut snapshot = Snapshot::default();
+		snapshot.apply(vec![indexed("/ws/defs.bzl", defs), indexed("/ws/BUILD", build)]);
+
+		let goto = |line, character| {
+			snapshot
+				.locate_declaration_of_member_at(Path::new("/ws/BUILD"), lsp::Position::new(line, character))
+				.map(|location| (location.uri.path().to_string(), location.range.start))
+		};
+		assert_eq!(goto(1, 12), Some(("/ws/defs.bzl".to_string(), lsp::Position::new(0, 2))));
+		assert_eq!(goto(2, 13), Some(("/ws/defs.bzl".to_string(), lsp::Position::new(1, 2))));
+		// Providers themselves don't have their fields.
+		assert_eq!(goto(3, 8), None);
+	}
+
+	#[test]
+	fn test_goto_from_loads_and_declarations() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		std::fs::write(root.join("WORKSPACE"), "").unwrap();
+		std::fs::write(root.join("defs.bzl"), "def other_func():\n  pass\n").unwrap();
+		let build = root.join("BUILD");
+		std::fs::write(&build, "load('//:defs.bzl', alias = 'other_func')\ndef my_macro(srcs):\n  alias(srcs)\n").unwrap();
+		let bazel = crate::bazel::BazelWorkspace::new();
+		bazel.locate_workspace(root).unwrap();
+		let documents = crate::index::Documents::default();
+		documents.refresh_doc(&build, &bazel).unwrap();
+		let snapshot = documents.snapshot();
+
+		let goto = |line, character| {
+			snapshot
+				.locate_definition_at(&build, lsp::Position::new(line, character))
+				.map(|location| (location.uri.to_file_path().unwrap(), location.range.start))
+		};
+		let other_func = Some((root.join("defs.bzl"), lsp::Position::new(0, 4)));
+		// The alias, the loaded name, and a call.
+		assert_eq!(goto(0, 21), other_func);
+		assert_eq!(goto(0, 30), other_func);
+		assert_eq!(goto(2, 3), other_func);
+		assert_eq!(goto(0, 8), Some((root.join("defs.bzl"), lsp::Position::new(0, 0))));
+		// Declarations and parameters go to themselves, and so do uses of parameters.
+		assert_eq!(goto(1, 5), Some((build.clone(), lsp::Position::new(1, 4))));
+		assert_eq!(goto(2, 9), Some((build.clone(), lsp::Position::new(1, 13))));
+	}
+}
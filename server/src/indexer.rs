@@ -0,0 +1,245 @@
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tower_lsp::lsp_types::MessageType;
+use tower_lsp::Client;
+
+use crate::bazel::{starlark_files, BazelWorkspaces};
+use crate::index::Documents;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Priority {
+	/// Files open in the editor, and the files they load.
+	Open,
+	/// Everything else in the workspace.
+	Background,
+}
+
+/// Files left to index, with the open ones always ahead of the rest.
+#[derive(Debug, Default)]
+struct WorkQueue {
+	open: VecDeque<PathBuf>,
+	background: VecDeque<PathBuf>,
+	// The files that are queued or being indexed, so that we index each of them only once,
+	// no matter how many files load it. Indexed files are in the index instead.
+	seen: HashSet<PathBuf>,
+}
+
+impl WorkQueue {
+	fn push(&mut self, path: &Path, priority: Priority) {
+		match priority {
+			Priority::Open => {
+				self.background.retain(|queued| queued != path);
+				if !self.open.iter().any(|queued| queued == path) {
+					self.open.push_back(path.to_path_buf());
+				}
+			}
+			Priority::Background => {
+				if !self.seen.contains(path) {
+					self.background.push_back(path.to_path_buf());
+				}
+			}
+		}
+		self.seen.insert(path.to_path_buf());
+	}
+
+	fn pop(&mut self) -> Option<(PathBuf, Priority)> {
+		self.open
+			.pop_front()
+			.map(|path| (path, Priority::Open))
+			.or_else(|| self.background.pop_front().map(|path| (path, Priority::Background)))
+	}
+
+	fn len(&self) -> usize {
+		self.open.len() + self.background.len()
+	}
+
+	/// Forgets `path` once it's indexed.
+	fn done(&mut self, path: &Path) {
+		self.seen.remove(path);
+	}
+
+	/// Forgets every file under `folder`, queued or not.
+	fn remove_folder(&mut self, folder: &Path) {
+		self.open.retain(|path| !path.starts_with(folder));
+		self.background.retain(|path| !path.starts_with(folder));
+		self.seen.retain(|path| !path.starts_with(folder));
+	}
+}
+
+/// Indexes the whole workspace in the background, and every `.bzl` file it loads, transitively.
+///
+/// A fixed number of workers pull files from a shared queue, so that we never parse
+/// more files at once than we have cores. Each file is published into `Documents`
+/// as soon as it's indexed, and the files it loads are queued with the same priority.
+#[derive(Debug, Clone)]
+pub struct BackgroundIndexer {
+	queue: Arc<Mutex<WorkQueue>>,
+	notify: Arc<Notify>,
+	documents: Arc<Documents>,
+	bazel: Arc<BazelWorkspaces>,
+	client: Client,
+}
+
+impl BackgroundIndexer {
+	pub fn new(documents: Arc<Documents>, bazel: Arc<BazelWorkspaces>, client: Client) -> Self {
+		BackgroundIndexer {
+			queue: Arc::new(Mutex::new(WorkQueue::default())),
+			notify: Arc::new(Notify::new()),
+			documents,
+			bazel,
+			client,
+		}
+	}
+
+	pub fn start(&self) {
+		let parallelism = std::thread::available_parallelism()
+			.map(|n| n.get())
+			.unwrap_or(1);
+		for _ in 0..parallelism {
+			let worker = self.clone();
+			tokio::spawn(async move { worker.run().await });
+		}
+	}
+
+	/// Queues every Starlark file in the workspaces under `folder`.
+	///
+	/// Walking the workspaces takes a while, so we do it off the runtime.
+	pub fn enqueue_folder(&self, folder: &Path) {
+		let indexer = self.clone();
+		let folder = folder.to_path_buf();
+		tokio::task::spawn_blocking(move || {
+			let files = indexer
+				.bazel
+				.roots()
+				.into_iter()
+				.filter(|root| root.starts_with(&folder))
+				.flat_map(|root| starlark_files(&root))
+				.collect::<Vec<_>>();
+			indexer.enqueue(files, Priority::Background);
+		});
+	}
+
+	/// Stops indexing the files under `folder`, e.g. because it was removed from the workspace.
+	pub fn remove_folder(&self, folder: &Path) {
+		if let Ok(mut queue) = self.queue.lock() {
+			queue.remove_folder(folder);
+		}
+	}
+
+	/// Moves `files` to the front of the queue, along with the files they load.
+	pub fn prioritize(&self, files: Vec<PathBuf>) {
+		self.enqueue(files, Priority::Open);
+	}
+
+	fn enqueue(&self, files: Vec<PathBuf>, priority: Priority) {
+		if let Ok(mut queue) = self.queue.lock() {
+			for file in files {
+				queue.push(&file, priority);
+			}
+		}
+		self.notify.notify_one();
+	}
+
+	fn next(&self) -> Option<(PathBuf, Priority)> {
+		let mut queue = self.queue.lock().ok()?;
+		let next = queue.pop();
+		if queue.len() > 0 {
+			// Wake up another worker, since there is more work than we can take.
+			self.notify.notify_one();
+		}
+		next
+	}
+
+	async fn run(&self) {
+		loop {
+			let (path, priority) = match self.next() {
+				Some(next) => next,
+				None => {
+					self.notify.notified().await;
+					continue;
+				}
+			};
+			let documents = self.documents.clone();
+			let bazel = self.bazel.clone();
+			let indexed_path = path.clone();
+			let indexed = tokio::task::spawn_blocking(move || {
+				let workspace = bazel
+					.workspace_for(&indexed_path)
+					.ok_or_else(|| format!("No Bazel workspace contains {:?}", indexed_path))?;
+				documents.index_single(&indexed_path, &workspace)
+			})
+			.await
+			.map_err(|err| format!("Indexing task failed: {:?}", err))
+			.and_then(|res| res);
+			// From now on the index knows about it, or we couldn't index it, and can try again when asked to.
+			if let Ok(mut queue) = self.queue.lock() {
+				queue.done(&path);
+			}
+			match indexed {
+				Ok((loaded, cache_error)) => {
+					if let Some(msg) = cache_error {
+						self.client.log_message(MessageType::WARNING, msg).await;
+					}
+					let snapshot = self.documents.snapshot();
+					let to_index = loaded
+						.into_iter()
+						.filter(|file| !snapshot.is_indexed(file))
+						.collect::<Vec<_>>();
+					self.enqueue(to_index, priority);
+				}
+				Err(msg) => self.client.log_message(MessageType::LOG, msg).await,
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_open_files_are_indexed_first() {
+		let mut queue = WorkQueue::default();
+		queue.push(Path::new("a.bzl"), Priority::Background);
+		queue.push(Path::new("b.bzl"), Priority::Background);
+		queue.push(Path::new("b.bzl"), Priority::Open);
+		queue.push(Path::new("a.bzl"), Priority::Background);
+
+		assert_eq!(queue.pop(), Some((PathBuf::from("b.bzl"), Priority::Open)));
+		assert_eq!(queue.pop(), Some((PathBuf::from("a.bzl"), Priority::Background)));
+		assert_eq!(queue.pop(), None);
+	}
+
+	#[test]
+	fn test_background_files_are_queued_once() {
+		let mut queue = WorkQueue::default();
+		queue.push(Path::new("a.bzl"), Priority::Background);
+		assert_eq!(queue.pop(), Some((PathBuf::from("a.bzl"), Priority::Background)));
+
+		queue.push(Path::new("a.bzl"), Priority::Background);
+		assert_eq!(queue.pop(), None);
+
+		queue.push(Path::new("a.bzl"), Priority::Open);
+		assert_eq!(queue.pop(), Some((PathBuf::from("a.bzl"), Priority::Open)));
+
+		queue.done(Path::new("a.bzl"));
+		assert!(queue.seen.is_empty());
+	}
+
+	#[test]
+	fn test_removed_folders_are_forgotten() {
+		let mut queue = WorkQueue::default();
+		queue.push(Path::new("ws/a.bzl"), Priority::Background);
+		queue.push(Path::new("ws/b.bzl"), Priority::Open);
+		queue.push(Path::new("other/c.bzl"), Priority::Background);
+
+		queue.remove_folder(Path::new("ws"));
+
+		assert_eq!(queue.seen, vec![PathBuf::from("other/c.bzl")].into_iter().collect());
+		assert_eq!(queue.pop(), Some((PathBuf::from("other/c.bzl"), Priority::Background)));
+		assert_eq!(queue.pop(), None);
+	}
+}
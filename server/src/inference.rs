@@ -0,0 +1,298 @@
+use std::path::Path;
+
+use tower_lsp::lsp_types as lsp;
+
+use crate::index::definition::{Definition, RuleKind};
+use crate::index::indexed_document::IndexedDocument;
+use crate::index::member_access::{MemberAccess, Receiver};
+use crate::index::signature::Parameter;
+use crate::index::snapshot::Snapshot;
+
+// Attributes Bazel adds to every rule, on top of `name`.
+const COMMON_ATTRS: &[&str] = &[
+	"applicable_licenses",
+	"args",
+	"aspect_hints",
+	"compatible_with",
+	"deprecation",
+	"env",
+	"exec_compatible_with",
+	"exec_properties",
+	"features",
+	"flaky",
+	"licenses",
+	"local",
+	"package_metadata",
+	"restricted_to",
+	"shard_count",
+	"size",
+	"tags",
+	"target_compatible_with",
+	"testonly",
+	"timeout",
+	"toolchains",
+	"visibility",
+];
+
+/// What `ctx` is in the implementation function of a rule.
+#[derive(Debug)]
+pub struct RuleContext<'a> {
+	rule: &'a str,
+	// The name of the `ctx` parameter.
+	ctx: &'a str,
+	attrs: &'a [Parameter],
+}
+
+/// The parts of `ctx` that hold one entry per attribute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AttrsView {
+	// `ctx.attr`
+	Values,
+	// `ctx.files`
+	Files,
+	// `ctx.file` and `ctx.executable`
+	File,
+}
+
+fn is_label(attr: &Parameter) -> bool {
+	attr.attr_type
+		.as_deref()
+		.map(|attr_type| attr_type.starts_with("label"))
+		.unwrap_or(true)
+}
+
+/// What reading an attribute of a given type from `ctx.attr` gives.
+fn value_type(attr_type: &str) -> &str {
+	match attr_type {
+		"label" => "Target",
+		"label_list" => "list of Target",
+		"label_keyed_string_dict" => "dict of Target to string",
+		"output" => "File",
+		"output_list" => "list of File",
+		"string_list" => "list of string",
+		"int_list" => "list of int",
+		"string_dict" => "dict of string to string",
+		"string_list_dict" => "dict of string to list of string",
+		attr_type => attr_type,
+	}
+}
+
+impl<'a> RuleContext<'a> {
+	/// Finds the rule `function` implements in `document`, if any.
+	pub fn of(document: &'a IndexedDocument, function: &str) -> Option<Self> {
+		let parameters = match &document.declarations.get(function)?.definition {
+			Some(Definition::Function { signature, .. }) => &signature.parameters,
+			_ => return None,
+		};
+		document
+			.declarations
+			.iter()
+			.filter_map(|(name, decl)| match &decl.definition {
+				Some(Definition::Rule {
+					kind,
+					attrs,
+					implementation: Some(implementation),
+					..
+				}) if implementation == function => {
+					// Aspects are implemented by `_impl(target, ctx)`.
+					let ctx = match kind {
+						RuleKind::Rule | RuleKind::RepositoryRule => parameters.first()?,
+						RuleKind::Aspect => parameters.get(1)?,
+						_ => return None,
+					};
+					Some(RuleContext {
+						rule: name,
+						ctx: &ctx.name,
+						attrs: &attrs.parameters,
+					})
+				}
+				_ => None,
+			})
+			// Rules can share an implementation, so we pick one that doesn't depend on the map's order.
+			.min_by_key(|context| context.rule)
+	}
+
+	fn view(&self, receiver: &str) -> Option<AttrsView> {
+		match receiver.strip_prefix(self.ctx)? {
+			".attr" => Some(AttrsView::Values),
+			".files" => Some(AttrsView::Files),
+			".file" | ".executable" => Some(AttrsView::File),
+			_ => None,
+		}
+	}
+
+	/// The attributes that can be read from `receiver`, e.g. all of them from `ctx.attr`,
+	/// or None if it isn't a part of `ctx` that we know.
+	pub fn members(&self, receiver: &str) -> Option<Vec<&'a Parameter>> {
+		let view = self.view(receiver)?;
+		Some(
+			self.attrs
+				.iter()
+				.filter(|attr| view == AttrsView::Values || is_label(attr))
+				.collect(),
+		)
+	}
+
+	/// Describes `receiver.member`, or explains why it doesn't exist.
+	pub fn describe(&self, receiver: &str, member: &str) -> Option<Result<String, String>> {
+		let view = self.view(receiver)?;
+		let attr = match self.attrs.iter().find(|attr| attr.name == member) {
+			Some(attr) => attr,
+			None if view == AttrsView::Values && COMMON_ATTRS.contains(&member) => {
+				return Some(Ok(format!("```python\n{}.{}\n```", receiver, member)))
+			}
+			None => return Some(Err(format!("'{}' is not an attribute of {}", member, self.rule))),
+		};
+		let value_type = match view {
+			AttrsView::Values => attr.attr_type.as_deref().map(value_type),
+			_ if !is_label(attr) => return Some(Err(format!("'{}' is not a label attribute of {}", member, self.rule))),
+			AttrsView::Files => Some("list of File"),
+			AttrsView::File => Some("File"),
+		};
+		let mut sections = vec![match value_type {
+			Some(value_type) => format!("```python\n{}.{}: {}\n```", receiver, member, value_type),
+			None => format!("```python\n{}.{}\n```", receiver, member),
+		}];
+		sections.extend(attr.doc.as_ref().map(|doc| doc.trim().to_string()));
+		Some(Ok(sections.join("\n\n")))
+	}
+}
+
+/// Describes the member `access` reads, or explains why it doesn't exist, if we know its receiver.
+fn describe(snapshot: &Snapshot, path: &Path, document: &IndexedDocument, access: &MemberAccess) -> Option<Result<String, String>> {
+	match &access.receiver {
+		Receiver::Name(receiver) => RuleContext::of(document, access.function.as_deref()?)?.describe(receiver, &access.member),
+		Receiver::Provider(provider) => {
+			let fields = match snapshot.resolve_declaration(path, provider)?.1.definition? {
+				Definition::Provider { fields, .. } => fields,
+				_ => return None,
+			};
+			// Providers without a list of fields accept any.
+			if fields.is_empty() {
+				return None;
+			}
+			Some(match fields.iter().find(|field| field.name == access.member) {
+				Some(field) => {
+					let mut sections = vec![format!("```python\n{}.{}\n```", provider, field.name)];
+					sections.extend(field.doc.as_ref().map(|doc| doc.trim().to_string()));
+					Ok(sections.join("\n\n"))
+				}
+				None => Err(format!("'{}' is not a field of {}", access.member, provider)),
+			})
+		}
+	}
+}
+
+/// A hover for the member at `position`, e.g. `ctx.attr.srcs` or `dep[MyInfo].files`.
+pub fn member_hover(snapshot: &Snapshot, path: &Path, position: lsp::Position) -> Option<(String, lsp::Range)> {
+	let document = snapshot.get_doc(path)?;
+	let access = document.member_at(position)?;
+	let description = describe(snapshot, path, &document, &access)?.ok()?;
+	Some((description, access.range().as_lsp_range()))
+}
+
+/// Errors about attributes and provider fields that don't exist, e.g. a misspelled `ctx.attr.sources`.
+pub fn member_diagnostics(snapshot: &Snapshot, path: &Path) -> Vec<lsp::Diagnostic> {
+	let document = match snapshot.get_doc(path) {
+		Some(document) => document,
+		None => return vec![],
+	};
+	document
+		.members
+		.iter()
+		.filter_map(|access| {
+			let message = describe(snapshot, path, &document, access)?.err()?;
+			Some(lsp::Diagnostic {
+				range: access.range().as_lsp_range(),
+				severity: Some(lsp::DiagnosticSeverity::ERROR),
+				source: Some("bazel-lsp".to_string()),
+				message,
+				..lsp::Diagnostic::default()
+			})
+		})
+		.collect()
+}
+
+/// Completes the members of `receiver`, e.g. the attributes after `ctx.attr.`, in the body of `function`.
+pub fn member_completions(document: &IndexedDocument, function: &str, receiver: &str) -> Option<Vec<lsp::CompletionItem>> {
+	let members = RuleContext::of(document, function)?.members(receiver)?;
+	Some(
+		members
+			.into_iter()
+			.map(|attr| lsp::CompletionItem {
+				insert_text: None,
+				..attr.as_lsp_completion_item()
+			})
+			.collect(),
+	)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::ast::process_document;
+	use crate::bazel::BazelWorkspace;
+
+	fn document(contents: &str) -> IndexedDocument {
+		process_document(contents, &BazelWorkspace::new()).unwrap().0
+	}
+
+	#[test]
+	fn test_ctx_members() {
+		let document = document(
+			"def _impl(ctx):\n  pass\n\
+			 my_rule = rule(implementation = _impl, attrs = {'srcs': attr.label_list(doc = 'Sources.'), 'edition': attr.string()})\n",
+		);
+		let context = RuleContext::of(&document, "_impl").unwrap();
+
+		assert_eq!(
+			context.describe("ctx.attr", "srcs"),
+			Some(Ok("```python\nctx.attr.srcs: list of Target\n```\n\nSources.".to_string()))
+		);
+		assert_eq!(
+			context.describe("ctx.files", "srcs"),
+			Some(Ok("```python\nctx.files.srcs: list of File\n```\n\nSources.".to_string()))
+		);
+		assert_eq!(context.describe("ctx.attr", "tags"), Some(Ok("```python\nctx.attr.tags\n```".to_string())));
+		assert_eq!(
+			context.describe("ctx.attr", "sources"),
+			Some(Err("'sources' is not an attribute of my_rule".to_string()))
+		);
+		assert_eq!(
+			context.describe("ctx.file", "edition"),
+			Some(Err("'edition' is not a label attribute of my_rule".to_string()))
+		);
+		assert_eq!(context.describe("ctx.actions", "run"), None);
+		let names = |receiver| {
+			context
+				.members(receiver)
+				.unwrap()
+				.into_iter()
+				.map(|attr| attr.name.as_str())
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(names("ctx.attr"), vec!["name", "srcs", "edition"]);
+		assert_eq!(names("ctx.files"), vec!["srcs"]);
+	}
+
+	#[test]
+	fn test_misspelled_members_are_diagnosed() {
+		let tmp = tempfile::tempdir().unwrap();
+		let path = tmp.path().join("defs.bzl");
+		let contents = "MyInfo = provider(fields = ['files'])\n\
+		                def _impl(ctx):\n  ctx.attr.dep[MyInfo].file\n  ctx.attr.deps\n\
+		                my_rule = rule(implementation = _impl, attrs = {'dep': attr.label()})\n";
+		std::fs::write(&path, contents).unwrap();
+		let documents = crate::index::Documents::default();
+		documents.refresh_doc(&path, &BazelWorkspace::new()).unwrap();
+
+		let messages = member_diagnostics(&documents.snapshot(), &path)
+			.into_iter()
+			.map(|diagnostic| diagnostic.message)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			messages,
+			vec!["'file' is not a field of MyInfo", "'deps' is not an attribute of my_rule"]
+		);
+	}
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use tower_lsp::lsp_types as lsp;
+
+use crate::bazel::{is_build_file, BazelWorkspace};
+use crate::index::definition::Definition;
+use crate::index::function_call::FunctionCall;
+use crate::index::function_decl::CallableSymbolSource;
+use crate::index::indexed_document::IndexedDocument;
+use crate::index::range::Range;
+use crate::index::snapshot::Snapshot;
+use crate::links::{absolute_label, package_of};
+
+/// A hint at `position`, padded on the side away from the text it's about.
+fn hint(position: lsp::Position, label: String, kind: Option<lsp::InlayHintKind>, before_text: bool) -> lsp::InlayHint {
+	lsp::InlayHint {
+		position,
+		label: lsp::InlayHintLabel::String(label),
+		kind,
+		text_edits: None,
+		tooltip: None,
+		padding_left: Some(!before_text),
+		padding_right: Some(before_text),
+		data: None,
+	}
+}
+
+/// A hint after the string at `range`, past its closing quote.
+fn after_string(range: &Range, label: String) -> lsp::InlayHint {
+	let end = range.as_lsp_range().end;
+	hint(lsp::Position::new(end.line, end.character + 1), label, None, false)
+}
+
+/// What `label` stands for if it's a short form like `:lib`, `//foo` or `@repo`.
+fn expand_label(label: &str, package: &str) -> Option<String> {
+	let short = label.starts_with(':') || ((label.starts_with("//") || label.starts_with('@')) && !label.contains(':'));
+	match short {
+		true => absolute_label(label, package),
+		false => None,
+	}
+}
+
+/// The names of the parameters that the positional arguments of `call` go to, if it calls a macro.
+///
+/// Only `def`s take positional arguments worth naming; rules and providers only take keywords.
+fn parameter_hints(snapshot: &Snapshot, path: &Path, document: &IndexedDocument, call: &FunctionCall) -> Vec<lsp::InlayHint> {
+	if call.arguments.is_empty() || document.is_local(&call.function_name, call.function.as_deref()) {
+		return vec![];
+	}
+	let signature = match snapshot.resolve_declaration(path, &call.function_name).and_then(|(_, decl)| decl.definition) {
+		Some(Definition::Function { signature, .. }) => signature,
+		_ => return vec![],
+	};
+	let mut hints = vec![];
+	for (arg, parameter) in call.arguments.iter().zip(&signature.parameters) {
+		// From there on, the arguments all go to `*args`, or we can't tell where they go.
+		if parameter.name.starts_with('*') || arg.is_starred {
+			break;
+		}
+		if arg.name.as_ref() == Some(&parameter.name) {
+			continue;
+		}
+		if let Some(start) = arg.start {
+			hints.push(hint(start, format!("{} =", parameter.name), Some(lsp::InlayHintKind::PARAMETER), true));
+		}
+	}
+	hints
+}
+
+/// The hints in `range` of `path`: the parameters of positional arguments
+/// to macros, what short labels stand for, and the names that aliases were loaded as.
+pub fn inlay_hints(
+	snapshot: &Snapshot,
+	workspace: Option<&BazelWorkspace>,
+	path: &Path,
+	range: lsp::Range,
+) -> Vec<lsp::InlayHint> {
+	let document = match snapshot.get_doc(path) {
+		Some(document) => document,
+		None => return vec![],
+	};
+	let mut hints = document
+		.calls
+		.iter()
+		.flat_map(|call| parameter_hints(snapshot, path, &document, call))
+		.collect::<Vec<_>>();
+	if let Some(package) = workspace.and_then(|workspace| package_of(workspace, path)) {
+		// Strings are only labels for sure in BUILD files, or elsewhere when wrapped in `Label(...)`.
+		let in_build_file = is_build_file(path);
+		let labels = document
+			.loads
+			.iter()
+			.filter(|_| in_build_file)
+			.map(|load| (&load.label, load.label_range()))
+			.chain(
+				document
+					.labels
+					.iter()
+					.filter(|label| in_build_file || label.in_label_call)
+					.map(|label| (&label.label, label.range())),
+			);
+		for (label, range) in labels {
+			if let Some(expanded) = expand_label(label, &package) {
+				hints.push(after_string(range, expanded));
+			}
+		}
+	}
+	for call in &document.calls {
+		if document.is_local(&call.function_name, call.function.as_deref()) {
+			continue;
+		}
+		let decl = match document.declarations.get(&call.function_name) {
+			Some(decl) if matches!(decl.source, CallableSymbolSource::Loaded(_)) && decl.imported_name != decl.real_name => decl,
+			_ => continue,
+		};
+		hints.push(hint(call.range().as_lsp_range().end, format!("= {}", decl.real_name), None, false));
+	}
+	hints.retain(|hint| range.start <= hint.position && hint.position <= range.end);
+	hints.sort_by_key(|hint| hint.position);
+	hints
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::index::Documents;
+	use std::fs;
+
+	#[test]
+	fn test_expands_short_labels() {
+		assert_eq!(expand_label(":lib", "//pkg").as_deref(), Some("//pkg:lib"));
+		assert_eq!(expand_label(":lib", "//").as_deref(), Some("//:lib"));
+		assert_eq!(expand_label("//foo/bar", "//pkg").as_deref(), Some("//foo/bar:bar"));
+		assert_eq!(expand_label("@repo//foo", "//pkg").as_deref(), Some("@repo//foo:foo"));
+		assert_eq!(expand_label("@repo", "//pkg").as_deref(), Some("@repo//:repo"));
+		assert_eq!(expand_label("//foo:bar", "//pkg"), None);
+		assert_eq!(expand_label("lib.cc", "//pkg"), None);
+		assert_eq!(expand_label("//", "//pkg"), None);
+	}
+
+	#[test]
+	fn test_hints_parameters_labels_and_aliases() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(root.join("BUILD"), "").unwrap();
+		fs::write(root.join("defs.bzl"), "def my_macro(name, srcs, *args, deps = []):\n  pass\n").unwrap();
+		fs::create_dir(root.join("pkg")).unwrap();
+		let contents = "load('//:defs.bzl', lib = 'my_macro')\nsrcs = []\nlib('a', srcs, 'b', deps = [':dep', '//other'])\n";
+		fs::write(root.join("pkg").join("BUILD"), contents).unwrap();
+		fs::write(root.join("pkg").join("macros.bzl"), "MESSAGE = ':not_a_label'\nDEP = Label(':dep')\n").unwrap();
+		let bazel = BazelWorkspace::new();
+		bazel.locate_workspace(root).unwrap();
+		let documents = Documents::default();
+		for file in &["defs.bzl", "pkg/BUILD", "pkg/macros.bzl"] {
+			documents.index_single(&root.join(file), &bazel).unwrap();
+		}
+		let build = root.join("pkg").join("BUILD");
+		let everything = lsp::Range::new(lsp::Position::new(0, 0), lsp::Position::new(3, 0));
+
+		let hints = inlay_hints(&documents.snapshot(), Some(&bazel), &build, everything)
+			.into_iter()
+			.map(|hint| match hint.label {
+				lsp::InlayHintLabel::String(label) => (hint.position.line, hint.position.character, label),
+				lsp::InlayHintLabel::LabelParts(_) => panic!("{:?} has parts", hint),
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			hints,
+			vec![
+				(2, 3, "= my_macro".to_string()),
+				(2, 4, "name =".to_string()),
+				(2, 34, "//pkg:dep".to_string()),
+				(2, 45, "//other:other".to_string()),
+			]
+		);
+		let first_line = lsp::Range::new(lsp::Position::new(0, 0), lsp::Position::new(1, 0));
+		assert!(inlay_hints(&documents.snapshot(), Some(&bazel), &build, first_line).is_empty());
+
+		// Outside of BUILD files, only `Label(...)` says that a string is a label.
+		let macros = root.join("pkg").join("macros.bzl");
+		let positions = inlay_hints(&documents.snapshot(), Some(&bazel), &macros, everything)
+			.into_iter()
+			.map(|hint| hint.position)
+			.collect::<Vec<_>>();
+		assert_eq!(positions, vec![lsp::Position::new(1, 18)]);
+	}
+}
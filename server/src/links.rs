@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types as lsp;
+
+use crate::bazel::{BazelResolver, BazelWorkspace};
+use crate::index::indexed_document::IndexedDocument;
+
+/// The package `file` is in, e.g. `//pkg` or `@repo//pkg`.
+pub fn package_of(workspace: &BazelWorkspace, file: &Path) -> Option<String> {
+	let label = workspace.label_for(file)?;
+	Some(label[..label.find(':')?].to_string())
+}
+
+/// The full form of `label`, as written in `package`, e.g. `//pkg:lib` for `:lib` or `//foo:foo` for `//foo`.
+pub fn absolute_label(label: &str, package: &str) -> Option<String> {
+	let label = label.strip_prefix('@').filter(|label| label.starts_with("//")).unwrap_or(label);
+	if !label.starts_with("//") && !label.starts_with('@') {
+		return Some(format!("{}:{}", package, label.trim_start_matches(':')));
+	}
+	if label.contains(':') {
+		return Some(label.to_string());
+	}
+	// Without a target, it's the one named after the last directory, or after the repository.
+	let (repo, path) = match label.find("//") {
+		Some(slashes) => (&label[..slashes], &label[slashes..]),
+		None => (label, "//"),
+	};
+	let name = match path.trim_start_matches('/').rsplit('/').next() {
+		Some(name) if !name.is_empty() => name,
+		_ => repo.trim_start_matches('@'),
+	};
+	if name.is_empty() {
+		return None;
+	}
+	Some(format!("{}{}:{}", repo, path, name))
+}
+
+/// The file `label` refers to, as seen from `doc`: the file itself if it is one,
+/// or else the BUILD file of the package the target is in.
+pub fn resolve_label(label: &str, doc: &Path, bazel: &dyn BazelResolver) -> Option<PathBuf> {
+	if !label.starts_with("//") && !label.starts_with('@') {
+		// Relative labels point into the package `doc` is the BUILD file of.
+		let file = doc.parent()?.join(label.trim_start_matches(':'));
+		return Some(file).filter(|file| file.is_file() && file != doc);
+	}
+	if let Ok(file) = bazel.resolve_bazel_path(label) {
+		return Some(file);
+	}
+	let package = label.split(':').next()?;
+	["BUILD.bazel", "BUILD"]
+		.iter()
+		.find_map(|build_file| bazel.resolve_bazel_path(&format!("{}:{}", package, build_file)).ok())
+}
+
+fn link(range: lsp::Range, file: &Path) -> Option<lsp::DocumentLink> {
+	Some(lsp::DocumentLink {
+		range,
+		target: Some(lsp::Url::from_file_path(file).ok()?),
+		tooltip: None,
+		data: None,
+	})
+}
+
+/// Links the labels of loads, and the label strings passed to rules and macros, to the files they refer to.
+pub fn document_links(document: &IndexedDocument, doc: &Path, bazel: &dyn BazelResolver) -> Vec<lsp::DocumentLink> {
+	// Loads were resolved while indexing, and we don't want to resolve them any differently.
+	let loads = document
+		.loads
+		.iter()
+		.filter_map(|load| link(load.label_range().as_lsp_range(), load.path.as_ref()?));
+	let labels = document.labels.iter().filter_map(|label| {
+		let file = resolve_label(&label.label, doc, bazel)?;
+		link(label.range().as_lsp_range(), &file)
+	});
+	loads.chain(labels).collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::ast::process_document;
+	use crate::bazel::BazelWorkspace;
+	use std::fs;
+
+	#[test]
+	fn test_links_to_files_and_packages() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(root.join("defs.bzl"), "").unwrap();
+		fs::write(root.join("main.rs"), "").unwrap();
+		fs::create_dir(root.join("lib")).unwrap();
+		fs::write(root.join("lib").join("BUILD.bazel"), "").unwrap();
+		let build = root.join("BUILD");
+		let contents = "load('//:defs.bzl', 'my_rule')\n\
+		                my_rule(name = 'main.rs', srcs = ['main.rs', 'missing.rs'], deps = ['//lib', '//lib:lib', '//missing:lib', ':other'])\n";
+		fs::write(&build, contents).unwrap();
+		let bazel = BazelWorkspace::new();
+		bazel.locate_workspace(root).unwrap();
+		let (document, _) = process_document(contents, &bazel).unwrap();
+
+		let links = document_links(&document, &build, &bazel)
+			.into_iter()
+			.map(|link| {
+				let target = link.target.unwrap().to_file_path().unwrap();
+				(link.range.start.character, target.strip_prefix(root).unwrap().to_path_buf())
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			links,
+			vec![
+				(6, PathBuf::from("defs.bzl")),
+				(35, PathBuf::from("main.rs")),
+				(69, PathBuf::from("lib/BUILD.bazel")),
+				(78, PathBuf::from("lib/BUILD.bazel")),
+			]
+		);
+	}
+}
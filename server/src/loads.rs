@@ -0,0 +1,551 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use rustpython_parser::ast;
+use rustpython_parser::lexer::make_tokenizer;
+use rustpython_parser::parser;
+use rustpython_parser::token::Tok;
+use tower_lsp::lsp_types as lsp;
+
+use crate::bazel::{BazelResolver, BazelWorkspace};
+use crate::builtins::Builtins;
+use crate::index::snapshot::Snapshot;
+
+/// A `load` statement as written. We edit them by writing them anew.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Load {
+	pub label: String,
+	/// The name each symbol is bound to, and the name it's loaded by.
+	pub symbols: Vec<(String, String)>,
+	pub range: lsp::Range,
+	multi_line: bool,
+	// We wouldn't know where to put its comments, so a load with any is left as it is.
+	commented: bool,
+	// The comments on their own lines right above the statement, which go along with it.
+	comments: Vec<String>,
+}
+
+impl Load {
+	/// The statement, with its symbols sorted by the names they are bound to like buildifier does,
+	/// and the aliases last like keyword arguments have to be.
+	fn to_text(&self) -> String {
+		let mut symbols = self.symbols.clone();
+		symbols.sort_by(|(a, loaded_a), (b, loaded_b)| (a != loaded_a, a).cmp(&(b != loaded_b, b)));
+		symbols.dedup();
+		let args = std::iter::once(format!("\"{}\"", self.label))
+			.chain(symbols.iter().map(|(name, loaded)| match name == loaded {
+				true => format!("\"{}\"", name),
+				false => format!("{} = \"{}\"", name, loaded),
+			}))
+			.collect::<Vec<_>>();
+		match self.multi_line {
+			true => format!("load(\n{})", args.iter().map(|arg| format!("    {},\n", arg)).collect::<String>()),
+			false => format!("load({})", args.join(", ")),
+		}
+	}
+
+	/// The statement like `to_text`, after its comments.
+	fn to_text_with_comments(&self) -> String {
+		self.comments.iter().cloned().chain(std::iter::once(self.to_text())).collect::<Vec<_>>().join("\n")
+	}
+
+	/// The line the load starts on, counting its comments.
+	fn first_line(&self) -> u32 {
+		self.range.start.line - self.comments.len() as u32
+	}
+}
+
+/// The loads of a file, and where new ones go.
+#[derive(Debug)]
+pub struct Loads {
+	pub loads: Vec<Load>,
+	// Where loads go if there aren't any: after the docstring of the file, if it has one.
+	first_line: u32,
+	// Every name the file mentions outside of its loads.
+	used: HashSet<String>,
+}
+
+impl Loads {
+	/// The top-level loads of `contents`, or None if it doesn't parse.
+	pub fn parse(contents: &str) -> Option<Self> {
+		let program = parser::parse_program(contents).ok()?;
+		let tokens = make_tokenizer(contents).collect::<Result<Vec<_>, _>>().ok()?;
+		let token_at = |location: &ast::Location| tokens.iter().position(|(start, _, _)| start == location);
+		let lines = contents.lines().collect::<Vec<_>>();
+		let mut first_line = 0;
+		let mut loads = vec![];
+		let mut load_tokens = vec![];
+		for (i, statement) in program.statements.iter().enumerate() {
+			let expression = match &statement.node {
+				ast::StatementType::Expression { expression } => expression,
+				_ => continue,
+			};
+			let (args, keywords) = match &expression.node {
+				ast::ExpressionType::String { .. } if i == 0 => {
+					first_line = tokens[token_at(&expression.location)?].2.row() as u32;
+					continue;
+				}
+				ast::ExpressionType::Call {
+					function,
+					args,
+					keywords,
+				} if matches!(&function.node, ast::ExpressionType::Identifier { name } if name == "load") => (args, keywords),
+				_ => continue,
+			};
+			let string = |expression: &ast::Expression| match &expression.node {
+				ast::ExpressionType::String {
+					value: ast::StringGroup::Constant { value },
+				} => Some(value.clone()),
+				_ => None,
+			};
+			let mut symbols = args.get(1..)?.iter().map(|arg| string(arg).map(|symbol| (symbol.clone(), symbol)));
+			let aliases = keywords.iter().map(|keyword| Some((keyword.name.clone()?, string(&keyword.value)?)));
+			// The call is at its parenthesis, which we find the match of.
+			let open = token_at(&expression.location)?;
+			let mut depth = 0;
+			let close = tokens[open..].iter().position(|(_, tok, _)| {
+				match tok {
+					Tok::Lpar | Tok::Lsqb | Tok::Lbrace => depth += 1,
+					Tok::Rpar | Tok::Rsqb | Tok::Rbrace => depth -= 1,
+					_ => {}
+				}
+				depth == 0
+			})? + open;
+			load_tokens.push(token_at(&statement.location)?..=close);
+			let start = lsp::Position::new(statement.location.row() as u32 - 1, statement.location.column() as u32 - 1);
+			let end = tokens[close].2;
+			let end = lsp::Position::new(end.row() as u32 - 1, end.column() as u32 - 1);
+			let text = lines[start.line as usize..=end.line as usize].join("\n");
+			// Comments at the top of the file are about the file, not about its first load.
+			let previous_end = loads.last().map(|load: &Load| load.range.end.line as usize + 1).unwrap_or(first_line as usize);
+			let comments_start = (previous_end..start.line as usize)
+				.rev()
+				.take_while(|&line| lines[line].trim_start().starts_with('#'))
+				.last()
+				.filter(|&line| line > 0)
+				.unwrap_or(start.line as usize);
+			loads.push(Load {
+				label: string(args.first()?)?,
+				symbols: symbols.by_ref().chain(aliases).collect::<Option<Vec<_>>>()?,
+				range: lsp::Range::new(start, end),
+				multi_line: start.line != end.line,
+				// Labels and symbols can't have a `#`, so any is a comment.
+				commented: text.contains('#'),
+				comments: lines[comments_start..start.line as usize].iter().map(|line| line.to_string()).collect(),
+			});
+		}
+		let used = tokens
+			.iter()
+			.enumerate()
+			.filter(|(index, _)| !load_tokens.iter().any(|load| load.contains(index)))
+			.filter_map(|(_, (_, tok, _))| match tok {
+				Tok::Name { name } => Some(name.clone()),
+				_ => None,
+			})
+			.collect();
+		Some(Loads { loads, first_line, used })
+	}
+
+	/// The loads merged by label, without the symbols that aren't used, and sorted like buildifier does.
+	/// Labels are made canonical if `resolver` can tell what packages they are in.
+	fn organized(&self, resolver: Option<&dyn BazelResolver>) -> Vec<Load> {
+		let mut organized = Vec::<Load>::new();
+		for load in self.loads.iter().filter(|load| !load.commented) {
+			let label = resolver
+				.and_then(|resolver| canonical_label(&load.label, resolver))
+				.unwrap_or_else(|| load.label.clone());
+			let symbols = load.symbols.iter().filter(|(name, _)| self.used.contains(name)).cloned();
+			match organized.iter_mut().find(|organized| organized.label == label) {
+				Some(merged) => {
+					merged.symbols.extend(symbols);
+					merged.multi_line |= load.multi_line;
+					merged.comments.extend(load.comments.iter().cloned());
+				}
+				None => organized.push(Load {
+					label,
+					symbols: symbols.collect(),
+					..load.clone()
+				}),
+			}
+		}
+		organized.retain(|load| !load.symbols.is_empty());
+		organized.sort_by(|a, b| load_order(&a.label).cmp(&load_order(&b.label)));
+		organized
+	}
+
+	/// The edits that organize the loads, if they aren't already. The loads that are left
+	/// go where the first one was.
+	fn organize_edits(&self, contents: &str, resolver: Option<&dyn BazelResolver>) -> Vec<lsp::TextEdit> {
+		let original = self.loads.iter().filter(|load| !load.commented).collect::<Vec<_>>();
+		let organized = self.organized(resolver);
+		let texts = organized.iter().map(Load::to_text_with_comments).collect::<Vec<_>>();
+		let lines = contents.lines().collect::<Vec<_>>();
+		let text_of = |load: &Load| lines[load.first_line() as usize..=load.range.end.line as usize].join("\n");
+		if original.iter().map(|load| text_of(load)).eq(texts.iter().cloned()) {
+			return vec![];
+		}
+		// Loads on the same line are replaced together, since edits can't overlap.
+		let mut ranges = Vec::<lsp::Range>::new();
+		for load in &original {
+			let range = lsp::Range::new(lsp::Position::new(load.first_line(), 0), lsp::Position::new(load.range.end.line + 1, 0));
+			match ranges.last_mut() {
+				Some(last) if range.start < last.end => last.end = last.end.max(range.end),
+				_ => ranges.push(range),
+			}
+		}
+		ranges
+			.into_iter()
+			.enumerate()
+			.map(|(i, range)| match (i, texts.is_empty()) {
+				(0, false) => lsp::TextEdit::new(range, format!("{}\n", texts.join("\n"))),
+				_ => lsp::TextEdit::new(range, String::new()),
+			})
+			.collect()
+	}
+
+	/// The edit that loads `name` from `label`, from the existing load of one of `labels` if there is one.
+	fn load_edit(&self, labels: &[&str], label: &str, name: &str) -> lsp::TextEdit {
+		if let Some(load) = self.loads.iter().find(|load| labels.contains(&load.label.as_str()) && !load.commented) {
+			let mut load = load.clone();
+			load.symbols.push((name.to_string(), name.to_string()));
+			return lsp::TextEdit::new(load.range, load.to_text());
+		}
+		let load = Load {
+			label: label.to_string(),
+			symbols: vec![(name.to_string(), name.to_string())],
+			range: lsp::Range::default(),
+			multi_line: false,
+			commented: false,
+			comments: vec![],
+		};
+		// Loads go in the order of their labels, if they were in order already.
+		let (line, text) = match (self.loads.iter().find(|load| load_order(&load.label) > load_order(label)), self.loads.last()) {
+			(Some(next), _) => (next.first_line(), format!("{}\n", load.to_text())),
+			(None, Some(last)) => (last.range.end.line + 1, format!("{}\n", load.to_text())),
+			(None, None) if self.first_line > 0 => (self.first_line, format!("\n{}\n", load.to_text())),
+			(None, None) => (0, format!("{}\n\n", load.to_text())),
+		};
+		let position = lsp::Position::new(line, 0);
+		lsp::TextEdit::new(lsp::Range::new(position, position), text)
+	}
+}
+
+/// `label` with the file in its actual package, e.g. `@repo//:defs.bzl` for `@repo//defs.bzl`,
+/// or None if it's relative or `resolver` can't find its package.
+fn canonical_label(label: &str, resolver: &dyn BazelResolver) -> Option<String> {
+	let (repo, path) = label.split_at(label.find("//")?);
+	// `@//` is the main repository even from other repositories, which the resolver can't tell from `//`.
+	if repo == "@" {
+		return None;
+	}
+	let parts = path.trim_start_matches('/').replacen(':', "/", 1);
+	let parts = parts.split('/').filter(|part| !part.is_empty()).collect::<Vec<_>>();
+	// A file belongs to the package of the closest directory with a BUILD file.
+	(0..parts.len()).rev().find_map(|dirs| {
+		let package = parts[..dirs].join("/");
+		["BUILD", "BUILD.bazel"]
+			.iter()
+			.find(|build| resolver.resolve_bazel_path(&format!("{}//{}:{}", repo, package, build)).is_ok())
+			.map(|_| format!("{}//{}:{}", repo, package, parts[dirs..].join("/")))
+	})
+}
+
+/// The order buildifier wants loads in: other repositories first, then packages, then the package of the file.
+fn load_order(label: &str) -> (bool, bool, &str, &str) {
+	let (package, file) = match label.find(':') {
+		Some(colon) => (&label[..colon], &label[colon + 1..]),
+		None => ("", label),
+	};
+	(!label.starts_with('@'), package.is_empty(), package, file)
+}
+
+/// Whether `label` is a file that is only meant to be loaded from its own repository, by convention.
+fn is_private(label: &str) -> bool {
+	label.split(['/', ':']).any(|part| part == "private" || part == "internal")
+}
+
+/// The `.bzl` files that export `name`, with the label to load each by. The files the workspace loads the most come first.
+fn exporters(snapshot: &Snapshot, name: &str, doc: &Path, workspace: Option<&BazelWorkspace>) -> Vec<(PathBuf, String)> {
+	if name.starts_with('_') {
+		return vec![];
+	}
+	// The `@repo` part of the labels of files in `file`'s repository, which is empty in the main one.
+	let repository_of = |file: &Path| {
+		let label = workspace?.label_for(file)?;
+		Some(label[..label.find("//")?].to_string())
+	};
+	let doc_repository = repository_of(doc);
+	let mut exporters = snapshot
+		.exporters_of(name)
+		.into_iter()
+		.filter(|file| file != doc)
+		.filter_map(|file| {
+			// The labels the file is loaded by, in the workspace and its external repositories.
+			let mut loaded = HashMap::<String, usize>::new();
+			for loader in snapshot.loaded_by(&file) {
+				// `//` labels in other repositories are relative to them, and `@//` is the main one.
+				let repository = repository_of(&loader)
+					.filter(|repository| Some(repository) != doc_repository.as_ref())
+					.map(|repository| if repository.is_empty() { "@".to_string() } else { repository });
+				for load in snapshot.get_doc(&loader).map(|document| document.loads.clone()).unwrap_or_default() {
+					if load.path.as_ref() != Some(&file) {
+						continue;
+					}
+					let label = match &repository {
+						Some(repository) if load.label.starts_with("//") => format!("{}{}", repository, load.label),
+						_ if load.label.starts_with("//") || load.label.starts_with('@') => load.label,
+						_ => continue,
+					};
+					*loaded.entry(label).or_default() += 1;
+				}
+			}
+			let label = loaded
+				.iter()
+				.max_by_key(|(label, count)| (**count, std::cmp::Reverse(label.len())))
+				.map(|(label, _)| label.clone())
+				.or_else(|| workspace?.label_for(&file))?;
+			let loads = loaded.values().sum::<usize>();
+			Some((std::cmp::Reverse(loads), is_private(&label), label.len(), label, file))
+		})
+		.collect::<Vec<_>>();
+	exporters.sort();
+	exporters.into_iter().map(|(_, _, _, label, file)| (file, label)).collect()
+}
+
+/// Quick fixes that load the names called in `range` that aren't defined, from the `.bzl` files we know export them.
+pub fn missing_load_actions(
+	snapshot: &Snapshot,
+	workspace: Option<&BazelWorkspace>,
+	doc: &Path,
+	contents: &str,
+	range: lsp::Range,
+	diagnostics: &[lsp::Diagnostic],
+	builtins: &Builtins,
+) -> Vec<lsp::CodeAction> {
+	let (document, loads, uri) = match (snapshot.get_doc(doc), Loads::parse(contents), lsp::Url::from_file_path(doc)) {
+		(Some(document), Some(loads), Ok(uri)) => (document, loads, uri),
+		_ => return vec![],
+	};
+	let mut undefined = document
+		.calls
+		.iter()
+		.filter(|call| {
+			let call_range = call.range().as_lsp_range();
+			call_range.start <= range.end && call_range.end >= range.start
+		})
+		.filter(|call| !document.declarations.contains_key(&call.function_name))
+		.filter(|call| !document.is_local(&call.function_name, call.function.as_deref()))
+		.filter(|call| builtins.get(&call.function_name).is_none())
+		.collect::<Vec<_>>();
+	// A name can be called more than once, anywhere in the range.
+	let mut seen = HashSet::new();
+	undefined.retain(|call| seen.insert(call.function_name.clone()));
+	let mut actions = vec![];
+	for call in undefined {
+		let name = &call.function_name;
+		let fixed = diagnostics
+			.iter()
+			.filter(|diagnostic| diagnostic.range == call.range().as_lsp_range())
+			.cloned()
+			.collect::<Vec<_>>();
+		for (i, (file, label)) in exporters(snapshot, name, doc, workspace).into_iter().enumerate() {
+			// The file might be loaded already, by another label.
+			let mut labels = document
+				.loads
+				.iter()
+				.filter(|load| load.path.as_ref() == Some(&file))
+				.map(|load| load.label.as_str())
+				.collect::<Vec<_>>();
+			labels.push(&label);
+			let edit = loads.load_edit(&labels, &label, name);
+			actions.push(lsp::CodeAction {
+				title: format!("Load `{}` from \"{}\"", name, label),
+				kind: Some(lsp::CodeActionKind::QUICKFIX),
+				diagnostics: Some(fixed.clone()).filter(|fixed| !fixed.is_empty()),
+				edit: Some(lsp::WorkspaceEdit {
+					changes: Some(std::iter::once((uri.clone(), vec![edit])).collect()),
+					document_changes: None,
+					change_annotations: None,
+				}),
+				command: None,
+				is_preferred: Some(i == 0),
+				disabled: None,
+				data: None,
+			});
+		}
+	}
+	actions
+}
+
+/// The `source.organizeImports` action of `doc`, if its loads need organizing.
+pub fn organize_loads_action(workspace: Option<&BazelWorkspace>, doc: &Path, contents: &str) -> Option<lsp::CodeAction> {
+	let resolver = workspace.map(|workspace| workspace.resolver_for(doc));
+	let edits = Loads::parse(contents)?.organize_edits(contents, resolver.as_ref().map(|resolver| resolver as &dyn BazelResolver));
+	if edits.is_empty() {
+		return None;
+	}
+	Some(lsp::CodeAction {
+		title: "Organize loads".to_string(),
+		kind: Some(lsp::CodeActionKind::SOURCE_ORGANIZE_IMPORTS),
+		diagnostics: None,
+		edit: Some(lsp::WorkspaceEdit {
+			changes: Some(std::iter::once((lsp::Url::from_file_path(doc).ok()?, edits)).collect()),
+			document_changes: None,
+			change_annotations: None,
+		}),
+		command: None,
+		is_preferred: None,
+		disabled: None,
+		data: None,
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::index::Documents;
+	use std::fs;
+	use std::os::unix::fs::symlink;
+
+	fn edit_of(action: &lsp::CodeAction) -> &lsp::TextEdit {
+		let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+		&changes.values().next().unwrap()[0]
+	}
+
+	#[test]
+	fn test_loads_missing_symbols_from_the_files_that_export_them() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = &tmp.path().join("ws");
+		let output_base = tmp.path().join("output_base");
+		fs::create_dir_all(output_base.join("execroot").join("ws").join("bazel-out")).unwrap();
+		fs::create_dir(root).unwrap();
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		symlink(output_base.join("execroot").join("ws").join("bazel-out"), root.join("bazel-out")).unwrap();
+		let external = output_base.join("external");
+		fs::create_dir_all(external.join("rules_rust").join("rust").join("private")).unwrap();
+		let rules_rust = external.join("rules_rust").join("rust");
+		fs::write(rules_rust.join("BUILD"), "").unwrap();
+		fs::write(rules_rust.join("private").join("rust.bzl"), "rust_library = rule()\nrust_test = rule()\n").unwrap();
+		fs::write(
+			rules_rust.join("defs.bzl"),
+			"load('//rust/private:rust.bzl', _rust_library = 'rust_library', _rust_test = 'rust_test')\n\
+			 rust_library = _rust_library\nrust_test = _rust_test\n",
+		)
+		.unwrap();
+		fs::create_dir(root.join("other")).unwrap();
+		fs::write(root.join("other").join("BUILD"), "load('@rules_rust//rust:defs.bzl', 'rust_test')\n").unwrap();
+		fs::create_dir(root.join("lib")).unwrap();
+		let build = root.join("lib").join("BUILD");
+		let contents = "load(\"@rules_rust//rust:defs.bzl\", \"rust_test\")\n\nrust_library(name = \"lib\")\n";
+		fs::write(&build, contents).unwrap();
+		let repeated = root.join("other").join("repeated.bzl");
+		let repeated_contents = "def targets():\n    rust_library(name = \"a\")\n    rust_test(name = \"b\")\n    rust_library(name = \"c\")\n";
+		fs::write(&repeated, repeated_contents).unwrap();
+
+		let workspace = BazelWorkspace::new();
+		workspace.locate_workspace(root).unwrap();
+		let documents = Documents::default();
+		for file in &[rules_rust.join("private").join("rust.bzl"), rules_rust.join("defs.bzl"), root.join("other").join("BUILD"), build.clone(), repeated.clone()] {
+			documents.index_single(file, &workspace).unwrap();
+		}
+		let snapshot = documents.snapshot();
+		let builtins = Builtins::bundled();
+		let at = |line, character| lsp::Range::new(lsp::Position::new(line, character), lsp::Position::new(line, character));
+
+		let actions = missing_load_actions(&snapshot, Some(&workspace), &build, contents, at(2, 3), &[], &builtins);
+		let titles = actions.iter().map(|action| action.title.as_str()).collect::<Vec<_>>();
+		assert_eq!(
+			titles,
+			vec![
+				"Load `rust_library` from \"@rules_rust//rust:defs.bzl\"",
+				"Load `rust_library` from \"@rules_rust//rust/private:rust.bzl\"",
+			]
+		);
+		assert_eq!(
+			edit_of(&actions[0]),
+			&lsp::TextEdit::new(
+				lsp::Range::new(lsp::Position::new(0, 0), lsp::Position::new(0, 47)),
+				"load(\"@rules_rust//rust:defs.bzl\", \"rust_library\", \"rust_test\")".to_string()
+			)
+		);
+		assert_eq!(
+			edit_of(&actions[1]),
+			&lsp::TextEdit::new(at(1, 0), "load(\"@rules_rust//rust/private:rust.bzl\", \"rust_library\")\n".to_string())
+		);
+		assert_eq!(missing_load_actions(&snapshot, Some(&workspace), &build, contents, at(0, 40), &[], &builtins), vec![]);
+
+		let all = lsp::Range::new(lsp::Position::new(0, 0), lsp::Position::new(4, 0));
+		let actions = missing_load_actions(&snapshot, Some(&workspace), &repeated, repeated_contents, all, &[], &builtins);
+		let titles = actions.iter().map(|action| action.title.as_str()).collect::<Vec<_>>();
+		assert_eq!(
+			titles,
+			vec![
+				"Load `rust_library` from \"@rules_rust//rust:defs.bzl\"",
+				"Load `rust_library` from \"@rules_rust//rust/private:rust.bzl\"",
+				"Load `rust_test` from \"@rules_rust//rust:defs.bzl\"",
+				"Load `rust_test` from \"@rules_rust//rust/private:rust.bzl\"",
+			]
+		);
+
+		// Other repositories go before the workspace, which isn't the order of the labels as strings.
+		let loads = Loads::parse("load(\"//lib:defs.bzl\", \"a\")\n").unwrap();
+		assert_eq!(
+			loads.load_edit(&["@repo//:defs.bzl"], "@repo//:defs.bzl", "b"),
+			lsp::TextEdit::new(at(0, 0), "load(\"@repo//:defs.bzl\", \"b\")\n".to_string())
+		);
+	}
+
+	#[test]
+	fn test_organizes_loads() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(root.join("BUILD"), "").unwrap();
+		fs::create_dir(root.join("lib")).unwrap();
+		fs::write(root.join("lib").join("BUILD"), "").unwrap();
+		let workspace = BazelWorkspace::new();
+		workspace.locate_workspace(root).unwrap();
+		let build = root.join("lib").join("BUILD");
+		let contents = "load(\":local.bzl\", \"local\")\n\
+		                load(\"//lib/defs.bzl\", \"b\", \"unused\")\n\
+		                # Keep this one.\n\
+		                load(\"//:root.bzl\", \"root\")\n\
+		                load(\"//lib:defs.bzl\", \"a\")\n\
+		                load(\"//:unused.bzl\", \"unused\")\n\
+		                \n\
+		                a(b(local(root)))\n";
+		let action = organize_loads_action(Some(&workspace), &build, contents).unwrap();
+		let mut edits = action.edit.unwrap().changes.unwrap().remove(&lsp::Url::from_file_path(&build).unwrap()).unwrap();
+		edits.sort_by_key(|edit| edit.range.start);
+		let lines = |start, end| lsp::Range::new(lsp::Position::new(start, 0), lsp::Position::new(end, 0));
+		assert_eq!(
+			edits,
+			vec![
+				lsp::TextEdit::new(
+					lines(0, 1),
+					"# Keep this one.\nload(\"//:root.bzl\", \"root\")\nload(\"//lib:defs.bzl\", \"a\", \"b\")\nload(\":local.bzl\", \"local\")\n"
+						.to_string()
+				),
+				lsp::TextEdit::new(lines(1, 2), String::new()),
+				lsp::TextEdit::new(lines(2, 4), String::new()),
+				lsp::TextEdit::new(lines(4, 5), String::new()),
+				lsp::TextEdit::new(lines(5, 6), String::new()),
+			]
+		);
+		let organized = "load(\"//:root.bzl\", \"root\")\nload(\":local.bzl\", \"local\")\n\nroot(local)\n";
+		assert_eq!(organize_loads_action(Some(&workspace), &build, organized), None);
+
+		// The comment at the top is about the file, and the loads on one line are replaced at once.
+		let contents = "# The header.\nload(\":a.bzl\", \"a\"); load(\"//:b.bzl\", \"b\")\n\na(b)\n";
+		let action = organize_loads_action(Some(&workspace), &build, contents).unwrap();
+		let edits = action.edit.unwrap().changes.unwrap().remove(&lsp::Url::from_file_path(&build).unwrap()).unwrap();
+		assert_eq!(
+			edits,
+			vec![lsp::TextEdit::new(lines(1, 2), "load(\"//:b.bzl\", \"b\")\nload(\":a.bzl\", \"a\")\n".to_string())]
+		);
+
+		// The user may still be typing the load.
+		assert_eq!(organize_loads_action(Some(&workspace), &build, "load()
+"), None);
+	}
+}
@@ -1,68 +1,457 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 use tower_lsp::jsonrpc::{Error, Result};
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
 
 mod ast;
+mod build_language;
+mod builtins;
+use builtins::Builtins;
+mod buildifier;
+use buildifier::Buildifier;
+mod call_context;
+mod call_hierarchy;
+mod code_lens;
+mod completion;
+mod format;
+mod inference;
+mod inlay_hints;
+mod links;
+mod loads;
 mod index;
+use index::cache::IndexCache;
+use index::documents::Update;
 use index::Documents;
 
 mod bazel;
-use bazel::BazelWorkspace;
+use bazel::{is_starlark_file, BazelWorkspace, BazelWorkspaces, ExternalRepos};
 
-#[macro_use] extern crate maplit;
+mod indexer;
+use indexer::BackgroundIndexer;
+
+mod semantic_tokens;
+mod source;
+
+#[cfg(test)]
+#[macro_use]
+extern crate maplit;
 
 #[derive(Debug)]
 struct Backend {
     client: Client,
-    documents: Documents,
-    bazel: BazelWorkspace,
+    documents: Arc<Documents>,
+    bazel: Arc<BazelWorkspaces>,
+    indexer: BackgroundIndexer,
+    builtins: Builtins,
+    // Lints open documents, if we found it.
+    buildifier: RwLock<Option<Buildifier>>,
+    // What buildifier last found in each open document, which we offer to fix.
+    lint_findings: RwLock<HashMap<PathBuf, Vec<buildifier::Finding>>>,
 }
 
 impl Backend {
     fn new(client: Client) -> Self {
+        let documents = Arc::new(Documents::with_cache(IndexCache::in_user_cache_dir()));
+        let bazel = Arc::new(BazelWorkspaces::default());
+        let indexer = BackgroundIndexer::new(documents.clone(), bazel.clone(), client.clone());
         Backend {
             client,
-            documents: Documents::default(),
-            bazel: BazelWorkspace::new(),
+            documents,
+            bazel,
+            indexer,
+            builtins: Builtins::bundled(),
+            buildifier: RwLock::new(Buildifier::from_path()),
+            lint_findings: RwLock::default(),
         }
     }
 
     fn capabilities() -> ServerCapabilities {
-        let mut capabilities = ServerCapabilities::default();
-        capabilities.text_document_sync =
-            Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::Full));
-        capabilities.definition_provider = Some(true);
-        capabilities.workspace = Some(WorkspaceCapability {
-            workspace_folders: Some(WorkspaceFolderCapability {
-                supported: Some(true),
-                change_notifications: None,
+        ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Options(
+                TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::FULL),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                    ..TextDocumentSyncOptions::default()
+                },
+            )),
+            call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+            code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                code_action_kinds: Some(vec![
+                    CodeActionKind::QUICKFIX,
+                    CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+                ]),
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+                resolve_provider: Some(false),
+            })),
+            code_lens_provider: Some(CodeLensOptions {
+                resolve_provider: Some(false),
             }),
-        });
-        capabilities
+            definition_provider: Some(OneOf::Left(true)),
+            document_formatting_provider: Some(OneOf::Left(true)),
+            document_highlight_provider: Some(OneOf::Left(true)),
+            document_link_provider: Some(DocumentLinkOptions {
+                resolve_provider: Some(false),
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+            }),
+            document_range_formatting_provider: Some(OneOf::Left(true)),
+            execute_command_provider: Some(ExecuteCommandOptions {
+                commands: code_lens::COMMANDS.iter().map(|command| command.to_string()).collect(),
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+            }),
+            folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            inlay_hint_provider: Some(OneOf::Left(true)),
+            selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+            semantic_tokens_provider: Some(
+                SemanticTokensOptions {
+                    legend: semantic_tokens::legend(),
+                    range: Some(true),
+                    full: Some(SemanticTokensFullOptions::Bool(true)),
+                    ..SemanticTokensOptions::default()
+                }
+                .into(),
+            ),
+            completion_provider: Some(CompletionOptions {
+                resolve_provider: Some(false),
+                trigger_characters: None,
+                all_commit_characters: None,
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+                completion_item: None,
+            }),
+            signature_help_provider: Some(SignatureHelpOptions {
+                trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                retrigger_characters: None,
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+            }),
+            workspace: Some(WorkspaceServerCapabilities {
+                workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                    supported: Some(true),
+                    change_notifications: Some(OneOf::Left(true)),
+                }),
+                file_operations: None,
+            }),
+            ..ServerCapabilities::default()
+        }
     }
 
-    async fn update_doc(&self, doc: &PathBuf) {
+    async fn update_doc(&self, doc: &Path) {
         self.client
-            .log_message(MessageType::Log, format!("opened file {:?}", doc))
+            .log_message(MessageType::LOG, format!("opened file {:?}", doc))
             .await;
 
-        self.documents.refresh_doc(doc, &self.bazel);
+        let update = match self.reindex_doc(doc) {
+            Ok(update) => update,
+            Err(msg) => {
+                self.client.log_message(MessageType::ERROR, msg).await;
+                return;
+            }
+        };
+        if let Some(msg) = &update.cache_error {
+            self.client.log_message(MessageType::WARNING, msg).await;
+        }
+        let mut to_publish = self.open_among(update.diagnostics_changed);
+        to_publish.push(doc.to_path_buf());
+        to_publish.sort();
+        to_publish.dedup();
+        self.publish_diagnostics(to_publish).await;
         self.client
             .log_message(
-                MessageType::Log,
-                format!("index is now {:#?}", self.documents),
+                MessageType::LOG,
+                // The whole index is too big to log, now that we index the workspace in the background.
+                format!("index of {:?} is now {:#?}", doc, self.documents.snapshot().get_doc(doc)),
             )
             .await;
     }
 
-    async fn update_bazel(&self, file: &PathBuf) {
-        let res = self.bazel.maybe_change_source_root(&file);
-        if let Err(msg) = res {
-            self.client.log_message(MessageType::Error, msg).await;
-        } else {
-            self.client.log_message(MessageType::Info, format!("Bazel is now {:#?}", &self.bazel)).await;
+    /// The documents among `files` that are open in the editor.
+    ///
+    /// We only publish diagnostics for those, and clear them when they are closed.
+    fn open_among(&self, files: Vec<PathBuf>) -> Vec<PathBuf> {
+        files
+            .into_iter()
+            .filter(|file| self.documents.is_open(file))
+            .collect()
+    }
+
+    /// The contents of `doc` in the editor, or on disk if it isn't open.
+    fn text_of(&self, doc: &Path) -> Option<String> {
+        self.documents
+            .open_text(doc)
+            .or_else(|| std::fs::read_to_string(doc).ok())
+    }
+
+    /// The builtins `doc` can use, including the native rules of its workspace if we know them.
+    fn builtins_for(&self, doc: &Path) -> Builtins {
+        let native_rules = self
+            .bazel
+            .workspace_for(doc)
+            .and_then(|workspace| workspace.native_rules());
+        self.builtins.with_native_rules(native_rules)
+    }
+
+    async fn remove_doc(&self, doc: &Path) {
+        match self.documents.remove_doc(doc) {
+            Ok(changed) => self.publish_diagnostics(self.open_among(changed)).await,
+            Err(msg) => self.client.log_message(MessageType::ERROR, msg).await,
+        }
+    }
+
+    fn reindex_doc(&self, doc: &Path) -> std::result::Result<Update, String> {
+        let workspace = self
+            .bazel
+            .workspace_for(doc)
+            .ok_or_else(|| format!("No Bazel workspace contains {:?}", doc))?;
+        self.documents.refresh_doc(doc, &workspace)
+    }
+
+    /// Restores the index of every workspace under `folder` from the on-disk cache.
+    async fn restore_index(&self, folder: &Path) {
+        let workspaces = self
+            .bazel
+            .roots()
+            .into_iter()
+            .filter(|root| root.starts_with(folder))
+            .filter_map(|root| self.bazel.workspace_for(&root))
+            .collect::<Vec<_>>();
+        for workspace in workspaces {
+            self.restore_workspace_index(&workspace).await;
+        }
+    }
+
+    async fn restore_workspace_index(&self, workspace: &BazelWorkspace) {
+        let stale = match self.documents.restore_from_cache(workspace) {
+            Ok(stale) => stale,
+            Err(msg) => {
+                self.client.log_message(MessageType::ERROR, msg).await;
+                return;
+            }
+        };
+        self.client
+            .log_message(MessageType::INFO, format!("Re-indexing {} stale cached documents", stale.len()))
+            .await;
+        for doc in stale {
+            match self.documents.refresh_doc(&doc, workspace) {
+                Ok(Update { cache_error: Some(msg), .. }) | Err(msg) => {
+                    self.client.log_message(MessageType::WARNING, msg).await
+                }
+                Ok(_) => {}
+            }
+        }
+    }
+
+    /// Asks the client to tell us about Starlark files that change outside of the editor.
+    async fn watch_starlark_files(&self) {
+        let watchers = ["**/*.bzl", "**/BUILD", "**/BUILD.bazel", "**/WORKSPACE", "**/WORKSPACE.bazel", "**/MODULE.bazel"]
+            .iter()
+            .map(|glob| FileSystemWatcher {
+                glob_pattern: GlobPattern::String(glob.to_string()),
+                kind: None,
+            })
+            .collect();
+        let registration = Registration {
+            id: "watch-starlark-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions { watchers }).ok(),
+        };
+        if let Err(err) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(MessageType::WARNING, format!("Couldn't watch Starlark files: {:?}", err))
+                .await;
+        }
+    }
+
+    async fn publish_diagnostics(&self, docs: Vec<PathBuf>) {
+        let snapshot = self.documents.snapshot();
+        for doc in docs {
+            if let Ok(uri) = Url::from_file_path(&doc) {
+                let mut diagnostics = snapshot.diagnostics(&doc);
+                if let Some(document) = snapshot.get_doc(&doc) {
+                    diagnostics.extend(builtins::undefined_names(&doc, &document, &self.builtins_for(&doc)));
+                    diagnostics.extend(inference::member_diagnostics(&snapshot, &doc));
+                }
+                // Buildifier only lints what the editor shows, since it runs for every change.
+                if self.documents.is_open(&doc) {
+                    let findings = self.lint(&doc).await;
+                    diagnostics.extend(findings.into_iter().map(|finding| finding.diagnostic));
+                }
+                self.client.publish_diagnostics(uri, diagnostics, None).await;
+            }
+        }
+    }
+
+    /// Runs buildifier on `doc`, if we have it, and remembers what it found.
+    async fn lint(&self, doc: &Path) -> Vec<buildifier::Finding> {
+        let buildifier = self.buildifier.read().ok().and_then(|buildifier| buildifier.clone());
+        let (buildifier, text) = match (buildifier, self.text_of(doc)) {
+            (Some(buildifier), Some(text)) if is_starlark_file(doc) => (buildifier, text),
+            _ => return vec![],
+        };
+        let path = doc.to_path_buf();
+        let linted = tokio::task::spawn_blocking(move || buildifier.lint(&path, &text))
+            .await
+            .map_err(|err| format!("Running buildifier failed: {:?}", err))
+            .and_then(|res| res);
+        let findings = match linted {
+            Ok(findings) => findings,
+            Err(msg) => {
+                self.client.log_message(MessageType::WARNING, msg).await;
+                vec![]
+            }
+        };
+        if let Ok(mut lint_findings) = self.lint_findings.write() {
+            lint_findings.insert(doc.to_path_buf(), findings.clone());
+        }
+        findings
+    }
+
+    async fn add_folder(&self, folder: &Path) {
+        let bazel = self.bazel.clone();
+        let folder = folder.to_path_buf();
+        // Walking a big folder takes a while, so we do it off the runtime.
+        let added = tokio::task::spawn_blocking(move || bazel.add_folder(&folder))
+            .await
+            .map_err(|err| format!("Adding folder failed: {:?}", err))
+            .and_then(|res| res);
+        match added {
+            Ok(roots) => {
+                for root in roots {
+                    self.client
+                        .log_message(MessageType::INFO, format!("Added Bazel workspace {:?}", root))
+                        .await;
+                }
+            }
+            Err(msg) => self.client.log_message(MessageType::ERROR, msg).await,
+        }
+    }
+
+    async fn remove_folder(&self, folder: &Path) {
+        self.indexer.remove_folder(folder);
+        match self.bazel.remove_folder(folder) {
+            Ok(removed) => {
+                self.client
+                    .log_message(MessageType::INFO, format!("Removed Bazel workspaces {:?}", removed))
+                    .await;
+            }
+            Err(msg) => self.client.log_message(MessageType::ERROR, msg).await,
+        }
+    }
+
+    /// Asks Bazel about the workspace of `file` in the background, the first time one of its files is opened:
+    /// where its external repositories are, and which rules it provides natively.
+    ///
+    /// Until then, we make do with what we find on disk, so that we never run Bazel in workspaces nobody works on.
+    fn query_bazel(&self, file: &Path) {
+        let workspace = match self.bazel.workspace_for(file) {
+            Some(workspace) => workspace,
+            None => return,
+        };
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let queried = tokio::task::spawn_blocking(move || {
+                let external_repos = workspace.query_bazel()?;
+                let native_rules = match external_repos {
+                    Some(ExternalRepos::FromBazel) => Some(workspace.load_native_rules()),
+                    _ => None,
+                };
+                Ok::<_, String>((external_repos, native_rules))
+            })
+            .await
+            .map_err(|err| format!("Querying Bazel failed: {:?}", err))
+            .and_then(|res| res);
+            match queried {
+                Ok((Some(external_repos), native_rules)) => {
+                    Backend::report_external_repos(&client, &external_repos).await;
+                    if let Some(Err(msg)) = native_rules {
+                        client.log_message(MessageType::WARNING, msg).await;
+                    }
+                }
+                Ok((None, _)) => {}
+                Err(msg) => client.log_message(MessageType::ERROR, msg).await,
+            }
+        });
+    }
+
+    /// Runs Bazel in `workspace` in the background, passing on its output to the client log as it comes.
+    fn run_bazel(&self, workspace: BazelWorkspace, command: Vec<String>) {
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let description = format!("bazel {}", command.join(" "));
+            client
+                .log_message(MessageType::INFO, format!("Running {}", description))
+                .await;
+            let mut child = match workspace.spawn_bazel(command) {
+                Ok(child) => child,
+                Err(msg) => {
+                    client.show_message(MessageType::ERROR, msg).await;
+                    return;
+                }
+            };
+            // Reading the pipes blocks, so each gets a thread that sends on its lines.
+            let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+            let stdout = child.stdout.take().map(|out| Box::new(out) as Box<dyn std::io::Read + Send>);
+            let stderr = child.stderr.take().map(|err| Box::new(err) as Box<dyn std::io::Read + Send>);
+            for output in stdout.into_iter().chain(stderr) {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    use std::io::BufRead;
+                    for line in std::io::BufReader::new(output).lines().map_while(std::result::Result::ok) {
+                        if sender.send(line).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+            while let Some(line) = receiver.recv().await {
+                client.log_message(MessageType::LOG, line).await;
+            }
+            match tokio::task::spawn_blocking(move || child.wait()).await {
+                Ok(Ok(status)) if status.success() => {
+                    client
+                        .log_message(MessageType::INFO, format!("Finished {}", description))
+                        .await
+                }
+                _ => {
+                    client
+                        .show_message(MessageType::ERROR, format!("Failed to run {}, see the log", description))
+                        .await
+                }
+            }
+        });
+    }
+
+    async fn report_external_repos(client: &Client, external_repos: &ExternalRepos) {
+        match external_repos {
+            ExternalRepos::FromBazel => {}
+            ExternalRepos::FromOutputBase(output_base) => {
+                client
+                    .log_message(
+                        MessageType::INFO,
+                        format!("Bazel is not available, using the external repositories of {:?}", output_base),
+                    )
+                    .await;
+            }
+            ExternalRepos::Unavailable(reason) => {
+                client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("Bazel is not available, and no output base was found: {}", reason),
+                    )
+                    .await;
+            }
+        }
+        let unavailable = external_repos.unavailable_features();
+        if !unavailable.is_empty() {
+            client
+                .show_message(
+                    MessageType::WARNING,
+                    format!("Running without Bazel. Unavailable features: {}", unavailable.join(", ")),
+                )
+                .await;
         }
     }
 }
@@ -71,13 +460,27 @@ impl Backend {
 impl LanguageServer for Backend {
     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
         self.client
-            .log_message(MessageType::Info, "initialized!")
+            .log_message(MessageType::INFO, "initialized!")
             .await;
-        params
-            .root_uri
-            .ok_or_else(|| Error::internal_error())
-            .and_then(|url| url.to_file_path().map_err(|_| Error::internal_error()))
-            .and_then(|path| self.bazel.update_workspace(&path).map_err(|_| Error::internal_error()))?;
+        let folders = match (params.workspace_folders, params.root_uri) {
+            (Some(folders), _) if !folders.is_empty() => {
+                folders.into_iter().map(|folder| folder.uri).collect::<Vec<_>>()
+            }
+            (_, Some(root_uri)) => vec![root_uri],
+            _ => return Err(Error::internal_error()),
+        };
+        let configured_buildifier = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("buildifier"))
+            .and_then(|buildifier| buildifier.as_str());
+        if let (Some(executable), Ok(mut buildifier)) = (configured_buildifier, self.buildifier.write()) {
+            *buildifier = Some(Buildifier::new(executable));
+        }
+        for folder in folders {
+            let path = folder.to_file_path().map_err(|_| Error::internal_error())?;
+            self.add_folder(&path).await;
+        }
         Ok(InitializeResult {
             capabilities: Backend::capabilities(),
             server_info: None,
@@ -86,15 +489,53 @@ impl LanguageServer for Backend {
 
     async fn initialized(&self, _: InitializedParams) {
         self.client
-            .log_message(MessageType::Info, "server initialized!")
+            .log_message(MessageType::INFO, "server initialized!")
             .await;
+        self.watch_starlark_files().await;
+        self.indexer.start();
+        for root in self.bazel.roots() {
+            if let Some(workspace) = self.bazel.workspace_for(&root) {
+                self.restore_workspace_index(&workspace).await;
+            }
+            self.indexer.enqueue_folder(&root);
+        }
     }
 
     async fn shutdown(&self) -> Result<()> {
-        self.client.log_message(MessageType::Info, "goodbye!").await;
+        self.client.log_message(MessageType::INFO, "goodbye!").await;
         Ok(())
     }
 
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let to_paths = |folders: Vec<WorkspaceFolder>| {
+            folders
+                .into_iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .collect::<Vec<PathBuf>>()
+        };
+        for folder in to_paths(params.event.removed) {
+            self.remove_folder(&folder).await;
+        }
+        for folder in to_paths(params.event.added) {
+            self.add_folder(&folder).await;
+            self.restore_index(&folder).await;
+            self.indexer.enqueue_folder(&folder);
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        for change in params.changes {
+            let path = match change.uri.to_file_path() {
+                Ok(path) if is_starlark_file(&path) => path,
+                _ => continue,
+            };
+            match change.typ {
+                FileChangeType::DELETED => self.remove_doc(&path).await,
+                _ => self.update_doc(&path).await,
+            }
+        }
+    }
+
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let path = params
             .text_document
@@ -102,6 +543,9 @@ impl LanguageServer for Backend {
             .to_file_path()
             .map_err(|_| Error::internal_error())
             .expect("bad path");
+        if let Some(change) = params.content_changes.into_iter().last() {
+            self.documents.open_doc(&path, change.text);
+        }
         self.update_doc(&path).await;
     }
 
@@ -112,8 +556,36 @@ impl LanguageServer for Backend {
             .to_file_path()
             .map_err(|_| Error::internal_error())
             .expect("bad path");
-        self.update_bazel(&path).await;
+        self.documents.open_doc(&path, params.text_document.text);
+        self.query_bazel(&path);
         self.update_doc(&path).await;
+        // The open document is already indexed, but what it loads might not be.
+        if let Some(doc) = self.documents.snapshot().get_doc(&path) {
+            self.indexer.prioritize(doc.loaded_files());
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        if let Ok(path) = params.text_document.uri.to_file_path() {
+            self.update_doc(&path).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        if let Ok(path) = params.text_document.uri.to_file_path() {
+            self.documents.close_doc(&path);
+            if let Ok(mut lint_findings) = self.lint_findings.write() {
+                lint_findings.remove(&path);
+            }
+            self.client
+                .publish_diagnostics(params.text_document.uri, vec![], None)
+                .await;
+            // Unsaved changes are gone, so the index goes back to what's on disk.
+            match self.reindex_doc(&path) {
+                Ok(update) => self.publish_diagnostics(self.open_among(update.diagnostics_changed)).await,
+                Err(msg) => self.client.log_message(MessageType::LOG, msg).await,
+            }
+        }
     }
 
     async fn goto_definition(
@@ -128,20 +600,390 @@ impl LanguageServer for Backend {
             .to_file_path()
             .map_err(|_| Error::internal_error())?;
         let position = params.text_document_position_params.position;
-        let maybe_location = self.documents.locate_declaration_of_call_at(&path, position);
+        let snapshot = self.documents.snapshot();
+        let maybe_location = snapshot.locate_definition_at(&path, position);
         self.client
             .log_message(
-                MessageType::Info,
+                MessageType::INFO,
                 format!("Goto Location {:#?}", &maybe_location),
             )
             .await;
-        if let Some(loc) = &maybe_location {
-            self.update_bazel(&loc.uri.to_file_path().expect("")).await;
-        }
         Ok(maybe_location.map(|loc| {
             GotoDefinitionResponse::Scalar(loc)
         }))
     }
+
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        let path = params
+            .text_document_position_params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let position = params.text_document_position_params.position;
+        let call = match self
+            .text_of(&path)
+            .and_then(|text| call_context::call_at(&text, position))
+        {
+            Some(call) => call,
+            None => return Ok(None),
+        };
+        let definition = match self
+            .documents
+            .snapshot()
+            .resolve_declaration(&path, &call.function_name)
+        {
+            Some((_, decl)) => decl.definition,
+            None => self.builtins_for(&path).get(&call.function_name).cloned(),
+        };
+        Ok(definition.and_then(|definition| {
+            let signature = definition.signature()?;
+            let active_parameter = signature.parameter_for(call.argument, call.keyword.as_deref());
+            Some(signature.as_lsp_signature_help(&call.function_name, definition.doc(), active_parameter))
+        }))
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let path = params
+            .text_document_position
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let position = params.text_document_position.position;
+        let text = match self.text_of(&path) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let snapshot = self.documents.snapshot();
+        let builtins = self.builtins_for(&path);
+        // Where a keyword can go, so can a positional argument, so we offer both.
+        let mut items = match call_context::call_at(&text, position).filter(|call| call.at_keyword) {
+            Some(call) => completion::attribute_completions(&snapshot, &builtins, &path, &call),
+            None => vec![],
+        };
+        // Only what's before the cursor has been typed yet.
+        let typed = call_context::name_at(&text, position)
+            .map(|(name, range)| {
+                name.chars()
+                    .take((position.character - range.start.character) as usize)
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+        let document = snapshot.get_doc(&path);
+        let inferred = typed.rfind('.').and_then(|dot| {
+            let function = call_context::enclosing_function(&text, position)?;
+            inference::member_completions(document.as_deref()?, &function, &typed[..dot])
+        });
+        items.extend(inferred.unwrap_or_else(|| completion::name_completions(document.as_deref(), &builtins, &typed)));
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        let path = params
+            .text_document_position_params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let position = params.text_document_position_params.position;
+        Ok(self
+            .documents
+            .snapshot()
+            .get_doc(&path)
+            .map(|document| document.highlights(position))
+            .filter(|highlights| !highlights.is_empty()))
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let (document, workspace) = match (
+            self.documents.snapshot().get_doc(&path),
+            self.bazel.workspace_for(&path),
+        ) {
+            (Some(document), Some(workspace)) => (document, workspace),
+            _ => return Ok(None),
+        };
+        Ok(Some(links::document_links(&document, &path, &workspace.resolver_for(&path))))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let path = params
+            .text_document_position_params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let position = params.text_document_position_params.position;
+        let snapshot = self.documents.snapshot();
+        let declared = snapshot
+            .get_doc(&path)
+            .and_then(|doc| doc.call_at(position))
+            .and_then(|call| {
+                let (_, decl) = snapshot.resolve_declaration(&path, &call.function_name)?;
+                let (range, real_name) = (call.range().as_lsp_range(), decl.real_name);
+                Some(decl.definition.map(|definition| (definition.as_markdown(&real_name), range)))
+            })
+            .or_else(|| inference::member_hover(&snapshot, &path, position).map(Some));
+        // Members like `ctx.actions.run` aren't indexed, so we look builtins up by the text under the cursor.
+        let described = declared.unwrap_or_else(|| {
+            let (name, range) = call_context::name_at(&self.text_of(&path)?, position)?;
+            let definition = self.builtins_for(&path).get(&name)?.clone();
+            Some((definition.as_markdown(&name), range))
+        });
+        Ok(described.map(|(value, range)| Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(range),
+        }))
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        if !is_starlark_file(&path) {
+            return Ok(None);
+        }
+        Ok(self
+            .text_of(&path)
+            .and_then(|text| format::format_document(&text, format::Dialect::of(&path))))
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        if !is_starlark_file(&path) {
+            return Ok(None);
+        }
+        Ok(self.text_of(&path).and_then(|text| {
+            format::format_range(&text, format::Dialect::of(&path), params.range)
+        }))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        Ok(self.text_of(&path).and_then(|text| ast::folding_ranges(&text)))
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        Ok(self
+            .text_of(&path)
+            .and_then(|text| ast::selection_ranges(&text, &params.positions)))
+    }
+
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> Result<Option<Vec<CallHierarchyItem>>> {
+        let position = params.text_document_position_params;
+        let path = position
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let item = call_hierarchy::prepare(&self.documents.snapshot(), &path, position.position);
+        Ok(item.map(|item| vec![item]))
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        Ok(Some(call_hierarchy::incoming_calls(
+            &self.documents.snapshot(),
+            &params.item,
+        )))
+    }
+
+    async fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        let path = params
+            .item
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        Ok(Some(call_hierarchy::outgoing_calls(
+            &self.documents.snapshot(),
+            &params.item,
+            &self.builtins_for(&path),
+        )))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let tokens = semantic_tokens::semantic_tokens(
+            &self.documents.snapshot(),
+            &path,
+            &self.builtins_for(&path),
+            None,
+        );
+        Ok(tokens.map(SemanticTokensResult::Tokens))
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let tokens = semantic_tokens::semantic_tokens(
+            &self.documents.snapshot(),
+            &path,
+            &self.builtins_for(&path),
+            Some(params.range),
+        );
+        Ok(tokens.map(SemanticTokensRangeResult::Tokens))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let workspace = self.bazel.workspace_for(&path);
+        Ok(Some(inlay_hints::inlay_hints(
+            &self.documents.snapshot(),
+            workspace.as_ref(),
+            &path,
+            params.range,
+        )))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let (text, workspace) = match (self.text_of(&path), self.bazel.workspace_for(&path)) {
+            (Some(text), Some(workspace)) => (text, workspace),
+            _ => return Ok(None),
+        };
+        Ok(Some(code_lens::code_lenses(
+            &self.documents.snapshot(),
+            &workspace,
+            &path,
+            &text,
+        )))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let path = params
+            .text_document
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::internal_error())?;
+        let text = match self.text_of(&path) {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        let CodeActionContext { diagnostics, only, .. } = params.context;
+        let workspace = self.bazel.workspace_for(&path);
+        let mut actions = loads::missing_load_actions(
+            &self.documents.snapshot(),
+            workspace.as_ref(),
+            &path,
+            &text,
+            params.range,
+            &diagnostics,
+            &self.builtins_for(&path),
+        );
+        actions.extend(loads::organize_loads_action(workspace.as_ref(), &path, &text));
+        let buildifier = self.buildifier.read().ok().and_then(|buildifier| buildifier.clone());
+        let findings = self
+            .lint_findings
+            .read()
+            .ok()
+            .and_then(|lint_findings| lint_findings.get(&path).cloned())
+            .unwrap_or_default();
+        if let (Some(buildifier), false) = (buildifier, findings.is_empty()) {
+            let fixes = tokio::task::spawn_blocking(move || {
+                buildifier::code_actions(&buildifier, &path, &text, &findings, &diagnostics)
+            })
+            .await
+            .map_err(|_| Error::internal_error())?;
+            actions.extend(fixes);
+        }
+        // Clients ask for kinds of actions, and also get the more specific kinds, e.g. `source.organizeImports` for `source`.
+        let requested = |action: &CodeAction| match (&only, &action.kind) {
+            (Some(only), Some(kind)) => only.iter().any(|only| {
+                kind.as_str() == only.as_str() || kind.as_str().starts_with(&format!("{}.", only.as_str()))
+            }),
+            _ => true,
+        };
+        Ok(Some(
+            actions
+                .into_iter()
+                .filter(requested)
+                .map(CodeActionOrCommand::CodeAction)
+                .collect(),
+        ))
+    }
+
+    /// Runs the Bazel commands of the code lenses.
+    async fn execute_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> Result<Option<serde_json::Value>> {
+        let mut arguments = params.arguments.into_iter();
+        let label: String = parse_argument(arguments.next().unwrap_or_default())?;
+        let uri: Url = parse_argument(arguments.next().unwrap_or_default())?;
+        let command = code_lens::bazel_command(&params.command, &label).ok_or_else(Error::method_not_found)?;
+        let path = uri.to_file_path().map_err(|_| Error::internal_error())?;
+        let workspace = self
+            .bazel
+            .workspace_for(&path)
+            .ok_or_else(|| Error::invalid_params(format!("{:?} is not in a Bazel workspace", path)))?;
+        self.run_bazel(workspace, command);
+        Ok(None)
+    }
+}
+
+fn parse_argument<T: serde::de::DeserializeOwned>(argument: serde_json::Value) -> Result<T> {
+    serde_json::from_value(argument).map_err(|err| Error::invalid_params(err.to_string()))
 }
 
 #[tokio::main]
@@ -149,11 +991,8 @@ async fn main() -> tokio::io::Result<()> {
     let read = tokio::io::stdin();
     let write = tokio::io::stdout();
 
-    let (service, messages) = LspService::new(|client| Backend::new(client));
-    Server::new(read, write)
-        .interleave(messages)
-        .serve(service)
-        .await;
+    let (service, socket) = LspService::new(Backend::new);
+    Server::new(read, write, socket).serve(service).await;
 
     Ok(())
 }
@@ -0,0 +1,246 @@
+use std::path::Path;
+
+use tower_lsp::lsp_types as lsp;
+
+use crate::builtins::Builtins;
+use crate::index::definition::Definition;
+use crate::index::function_decl::CallableSymbolSource;
+use crate::index::indexed_document::IndexedDocument;
+use crate::index::member_access::Receiver;
+use crate::index::range::Range;
+use crate::index::snapshot::Snapshot;
+
+/// What a name resolves to, which TextMate grammars can't tell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+	Function,
+	Rule,
+	Provider,
+	Parameter,
+	Variable,
+	Label,
+}
+
+impl Kind {
+	/// Symbols we know nothing about, like `x = 1`, are variables.
+	fn of(definition: Option<&Definition>) -> Self {
+		match definition {
+			Some(Definition::Function { .. }) => Kind::Function,
+			Some(Definition::Rule { .. }) => Kind::Rule,
+			Some(Definition::Provider { .. }) => Kind::Provider,
+			_ => Kind::Variable,
+		}
+	}
+}
+
+/// The token types, in the order of `Kind`. Rules, providers and labels have no standard type,
+/// so clients that don't know them should fall back to class, struct and string.
+const TOKEN_TYPES: &[lsp::SemanticTokenType] = &[
+	lsp::SemanticTokenType::FUNCTION,
+	lsp::SemanticTokenType::new("rule"),
+	lsp::SemanticTokenType::new("provider"),
+	lsp::SemanticTokenType::PARAMETER,
+	lsp::SemanticTokenType::VARIABLE,
+	lsp::SemanticTokenType::new("label"),
+];
+
+const DECLARATION: u32 = 1;
+const DEFAULT_LIBRARY: u32 = 1 << 1;
+const LOADED: u32 = 1 << 2;
+
+/// The token modifiers, in the order of their bits.
+const TOKEN_MODIFIERS: &[lsp::SemanticTokenModifier] = &[
+	lsp::SemanticTokenModifier::DECLARATION,
+	lsp::SemanticTokenModifier::DEFAULT_LIBRARY,
+	lsp::SemanticTokenModifier::new("loaded"),
+];
+
+pub fn legend() -> lsp::SemanticTokensLegend {
+	lsp::SemanticTokensLegend {
+		token_types: TOKEN_TYPES.to_vec(),
+		token_modifiers: TOKEN_MODIFIERS.to_vec(),
+	}
+}
+
+struct Token {
+	range: lsp::Range,
+	kind: Kind,
+	modifiers: u32,
+}
+
+/// The kind of the top-level `name`, as declared or loaded by `path`, or built in.
+fn classify(snapshot: &Snapshot, path: &Path, document: &IndexedDocument, builtins: &Builtins, name: &str) -> Option<(Kind, u32)> {
+	match document.declarations.get(name) {
+		Some(decl) => match decl.source {
+			CallableSymbolSource::DeclaredInFile(_) => Some((Kind::of(decl.definition.as_ref()), 0)),
+			CallableSymbolSource::Loaded(_) => {
+				let definition = snapshot.resolve_declaration(path, name).and_then(|(_, decl)| decl.definition);
+				Some((Kind::of(definition.as_ref()), LOADED))
+			}
+		},
+		None => builtins.get(name).map(|definition| (Kind::of(Some(definition)), DEFAULT_LIBRARY)),
+	}
+}
+
+fn tokens_in(snapshot: &Snapshot, path: &Path, document: &IndexedDocument, builtins: &Builtins) -> Vec<Token> {
+	let mut tokens = vec![];
+	let mut push = |range: &Range, (kind, modifiers): (Kind, u32)| {
+		tokens.push(Token {
+			range: range.as_lsp_range(),
+			kind,
+			modifiers,
+		})
+	};
+	for decl in document.declarations.values() {
+		if let Some(range) = decl.declared_range() {
+			push(range, (Kind::of(decl.definition.as_ref()), DECLARATION));
+		}
+	}
+	for local in &document.locals {
+		let kind = if local.is_parameter { Kind::Parameter } else { Kind::Variable };
+		push(local.range(), (kind, DECLARATION));
+	}
+	for call in &document.calls {
+		let function = call.function.as_deref();
+		let locals = document
+			.locals
+			.iter()
+			.filter(|local| Some(local.function.as_str()) == function && local.name == call.function_name);
+		let classified = match locals.map(|local| local.is_parameter).max() {
+			Some(true) => Some((Kind::Parameter, 0)),
+			Some(false) => Some((Kind::Variable, 0)),
+			None => classify(snapshot, path, document, builtins, &call.function_name),
+		};
+		if let Some(classified) = classified {
+			push(call.range(), classified);
+		}
+	}
+	for load in &document.loads {
+		push(load.label_range(), (Kind::Label, 0));
+		for (symbol, range) in load.symbol_ranges() {
+			if let Some(classified) = classify(snapshot, path, document, builtins, symbol) {
+				push(range, classified);
+			}
+		}
+	}
+	// Members of builtin modules, e.g. `native.cc_library`.
+	for access in &document.members {
+		let receiver = match &access.receiver {
+			Receiver::Name(receiver) => receiver,
+			Receiver::Provider(_) => continue,
+		};
+		let module = receiver.split('.').next().unwrap_or_default();
+		if document.declarations.contains_key(module) || document.is_local(module, access.function.as_deref()) {
+			continue;
+		}
+		if let Some(definition) = builtins.get(&format!("{}.{}", receiver, access.member)) {
+			push(access.range(), (Kind::of(Some(definition)), DEFAULT_LIBRARY));
+		}
+	}
+	for label in &document.labels {
+		push(label.range(), (Kind::Label, 0));
+	}
+	tokens
+}
+
+/// The semantic tokens of `path`, only those that start in `range` if there is one.
+pub fn semantic_tokens(
+	snapshot: &Snapshot,
+	path: &Path,
+	builtins: &Builtins,
+	range: Option<lsp::Range>,
+) -> Option<lsp::SemanticTokens> {
+	let document = snapshot.get_doc(path)?;
+	let mut tokens = tokens_in(snapshot, path, &document, builtins)
+		.into_iter()
+		.filter(|token| range.map(|range| range.start <= token.range.start && token.range.start < range.end).unwrap_or(true))
+		.collect::<Vec<_>>();
+	tokens.sort_by_key(|token| token.range.start);
+	tokens.dedup_by_key(|token| token.range.start);
+	// Each token is relative to the one before it.
+	let mut previous = lsp::Position::default();
+	let data = tokens
+		.into_iter()
+		.map(|token| {
+			let start = token.range.start;
+			let delta_start = if start.line == previous.line { start.character - previous.character } else { start.character };
+			let encoded = lsp::SemanticToken {
+				delta_line: start.line - previous.line,
+				delta_start,
+				length: token.range.end.character - start.character,
+				token_type: token.kind as u32,
+				token_modifiers_bitset: token.modifiers,
+			};
+			previous = start;
+			encoded
+		})
+		.collect();
+	Some(lsp::SemanticTokens { result_id: None, data })
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::bazel::BazelWorkspace;
+	use crate::index::Documents;
+	use std::fs;
+
+	#[test]
+	fn test_classifies_names_by_what_they_resolve_to() {
+		let tmp = tempfile::tempdir().unwrap();
+		let root = tmp.path();
+		fs::write(root.join("WORKSPACE"), "").unwrap();
+		fs::write(root.join("defs.bzl"), "MyInfo = provider(fields = ['a'])\nmy_rule = rule(implementation = _impl)\n").unwrap();
+		fs::write(
+			root.join("macros.bzl"),
+			"load('//:defs.bzl', 'my_rule', info = 'MyInfo')\ndef library(name, srcs):\n  out = info(a = srcs)\n  my_rule(name = name, srcs = glob(['*.cc']), deps = ['//lib'])\n  native.existing_rule(out)\n",
+		)
+		.unwrap();
+		let bazel = BazelWorkspace::new();
+		bazel.locate_workspace(root).unwrap();
+		let documents = Documents::default();
+		for file in &["defs.bzl", "macros.bzl"] {
+			documents.index_single(&root.join(file), &bazel).unwrap();
+		}
+		let macros = root.join("macros.bzl");
+		let tokens = semantic_tokens(&documents.snapshot(), &macros, &Builtins::bundled(), None).unwrap();
+
+		let mut position = lsp::Position::default();
+		let decoded = tokens
+			.data
+			.iter()
+			.map(|token| {
+				position.character = if token.delta_line == 0 { position.character + token.delta_start } else { token.delta_start };
+				position.line += token.delta_line;
+				(position.line, position.character, token.length, TOKEN_TYPES[token.token_type as usize].as_str(), token.token_modifiers_bitset)
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			decoded,
+			vec![
+				(0, 6, 11, "label", 0),
+				(0, 21, 7, "rule", LOADED),
+				(0, 31, 4, "provider", LOADED),
+				(0, 39, 6, "provider", LOADED),
+				(1, 4, 7, "function", DECLARATION),
+				(1, 12, 4, "parameter", DECLARATION),
+				(1, 18, 4, "parameter", DECLARATION),
+				(2, 2, 3, "variable", DECLARATION),
+				(2, 8, 4, "provider", LOADED),
+				(2, 17, 4, "parameter", 0),
+				(3, 2, 7, "rule", LOADED),
+				(3, 17, 4, "parameter", 0),
+				(3, 30, 4, "function", DEFAULT_LIBRARY),
+				(3, 55, 5, "label", 0),
+				(4, 2, 6, "variable", DEFAULT_LIBRARY),
+				(4, 9, 13, "function", DEFAULT_LIBRARY),
+				(4, 23, 3, "variable", 0),
+			]
+		);
+
+		let second_line = lsp::Range::new(lsp::Position::new(1, 0), lsp::Position::new(2, 0));
+		let tokens = semantic_tokens(&documents.snapshot(), &macros, &Builtins::bundled(), Some(second_line)).unwrap();
+		assert_eq!(tokens.data.len(), 3);
+		assert_eq!((tokens.data[0].delta_line, tokens.data[0].delta_start), (1, 4));
+	}
+}
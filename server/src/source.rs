@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use rustpython_parser::ast;
+use rustpython_parser::lexer::make_tokenizer;
+use rustpython_parser::token::Tok;
+
+// A row and column, both starting at 1, as the lexer counts them.
+pub type Pos = (usize, usize);
+
+#[derive(Debug)]
+pub struct Token {
+	pub start: Pos,
+	pub tok: Tok,
+	pub end: Pos,
+}
+
+/// The parser drops comments, so we find them between the tokens.
+#[derive(Debug)]
+pub struct Comment {
+	pub pos: Pos,
+	pub text: String,
+	// Whether it's the only thing on its line, as opposed to ending a line of code.
+	pub own_line: bool,
+}
+
+/// What the AST leaves out of the source: where each token ends, parentheses and comments.
+pub struct Source<'a> {
+	pub lines: Vec<&'a str>,
+	pub tokens: Vec<Token>,
+	by_start: HashMap<Pos, usize>,
+	// From the index of each opening bracket to the index of the one that closes it.
+	pub closing: HashMap<usize, usize>,
+	pub comments: Vec<Comment>,
+}
+
+impl<'a> Source<'a> {
+	pub fn new(text: &'a str) -> Option<Self> {
+		let lines = text.split('\n').map(|line| line.trim_end_matches('\r')).collect::<Vec<_>>();
+		let tokens = make_tokenizer(text)
+			.map(|token| token.map(|(start, tok, end)| Token {
+				start: (start.row(), start.column()),
+				tok,
+				end: (end.row(), end.column()),
+			}))
+			.collect::<Result<Vec<_>, _>>()
+			.ok()?;
+		let mut source = Source {
+			lines,
+			tokens,
+			by_start: HashMap::new(),
+			closing: HashMap::new(),
+			comments: vec![],
+		};
+		let mut open = vec![];
+		for (index, token) in source.tokens.iter().enumerate() {
+			match token.tok {
+				Tok::Newline | Tok::Indent | Tok::Dedent => continue,
+				Tok::Lpar | Tok::Lsqb | Tok::Lbrace => open.push(index),
+				Tok::Rpar | Tok::Rsqb | Tok::Rbrace => {
+					source.closing.insert(open.pop()?, index);
+				}
+				_ => {}
+			}
+			source.by_start.entry(token.start).or_insert(index);
+		}
+		let mut gap_start = (1, 1);
+		let mut gaps = vec![];
+		for token in &source.tokens {
+			if token.start > gap_start {
+				gaps.push((gap_start, token.start));
+			}
+			gap_start = gap_start.max(token.end);
+		}
+		gaps.push((gap_start, (source.lines.len() + 1, 1)));
+		for (from, to) in gaps {
+			source.find_comments(from, to);
+		}
+		Some(source)
+	}
+
+	/// Only whitespace and comments can be between two tokens, so any `#` there starts a comment.
+	fn find_comments(&mut self, from: Pos, to: Pos) {
+		for row in from.0..=to.0.min(self.lines.len()) {
+			let line = self.lines[row - 1].chars().collect::<Vec<_>>();
+			let start = if row == from.0 { from.1 - 1 } else { 0 };
+			let end = if row == to.0 { (to.1 - 1).min(line.len()) } else { line.len() };
+			if let Some(hash) = (start..end).find(|&column| line[column] == '#') {
+				self.comments.push(Comment {
+					pos: (row, hash + 1),
+					text: line[hash..end].iter().collect::<String>().trim_end().to_string(),
+					own_line: line[..hash].iter().all(|c| c.is_whitespace()),
+				});
+			}
+		}
+	}
+
+	pub fn slice(&self, from: Pos, to: Pos) -> String {
+		let mut text = String::new();
+		for row in from.0..=to.0 {
+			let line = self.lines.get(row - 1).copied().unwrap_or("");
+			let start = if row == from.0 { from.1 - 1 } else { 0 };
+			let end = if row == to.0 { to.1 - 1 } else { usize::MAX };
+			text.extend(line.chars().skip(start).take(end.saturating_sub(start)));
+			if row < to.0 {
+				text.push('\n');
+			}
+		}
+		text
+	}
+
+	pub fn is_blank(&self, row: usize) -> bool {
+		row >= 1 && self.lines.get(row - 1).map(|line| line.trim().is_empty()).unwrap_or(false)
+	}
+
+	pub fn token_at(&self, location: &ast::Location) -> Option<usize> {
+		self.by_start.get(&(location.row(), location.column())).copied()
+	}
+
+	pub fn is(&self, index: usize, tok: &Tok) -> bool {
+		self.tokens.get(index).map(|token| &token.tok == tok).unwrap_or(false)
+	}
+
+	// A parenthesis right after something that can be called opens the arguments of a call, or the parameters of a `def`.
+	fn is_call_paren(&self, index: usize) -> bool {
+		index > 0
+			&& matches!(
+				self.tokens[index - 1].tok,
+				Tok::Name { .. } | Tok::Rpar | Tok::Rsqb | Tok::Rbrace | Tok::String { .. }
+			)
+	}
+
+	fn first(&self, expr: &ast::Expression) -> Option<usize> {
+		match &expr.node {
+			ast::ExpressionType::Binop { a, .. }
+			| ast::ExpressionType::Subscript { a, .. }
+			| ast::ExpressionType::Attribute { value: a, .. }
+			| ast::ExpressionType::Call { function: a, .. }
+			| ast::ExpressionType::IfExpression { body: a, .. } => Some(self.outer(a)?.0),
+			ast::ExpressionType::BoolOp { values: operands, .. }
+			| ast::ExpressionType::Compare { vals: operands, .. }
+			| ast::ExpressionType::Tuple { elements: operands } if !operands.is_empty() => Some(self.outer(&operands[0])?.0),
+			_ => self.token_at(&expr.location),
+		}
+	}
+
+	fn last(&self, expr: &ast::Expression) -> Option<usize> {
+		match &expr.node {
+			ast::ExpressionType::Binop { b: operand, .. }
+			| ast::ExpressionType::Unop { a: operand, .. }
+			| ast::ExpressionType::Lambda { body: operand, .. }
+			| ast::ExpressionType::IfExpression { orelse: operand, .. }
+			| ast::ExpressionType::Starred { value: operand } => Some(self.outer(operand)?.1),
+			ast::ExpressionType::BoolOp { values: operands, .. } | ast::ExpressionType::Compare { vals: operands, .. } => {
+				Some(self.outer(operands.last()?)?.1)
+			}
+			ast::ExpressionType::Tuple { elements } if !elements.is_empty() => {
+				let last = self.outer(elements.last()?)?.1;
+				Some(if self.is(last + 1, &Tok::Comma) { last + 1 } else { last })
+			}
+			ast::ExpressionType::Attribute { .. } => Some(self.token_at(&expr.location)? + 1),
+			ast::ExpressionType::Call { .. }
+			| ast::ExpressionType::Subscript { .. }
+			| ast::ExpressionType::List { .. }
+			| ast::ExpressionType::Tuple { .. }
+			| ast::ExpressionType::Dict { .. }
+			| ast::ExpressionType::Comprehension { .. } => self.closing.get(&self.token_at(&expr.location)?).copied(),
+			_ => self.token_at(&expr.location),
+		}
+	}
+
+	/// The first and last tokens of `expr`, including the parentheses around it, and how many of those there are.
+	pub fn outer(&self, expr: &ast::Expression) -> Option<(usize, usize, usize)> {
+		let (mut first, mut last) = (self.first(expr)?, self.last(expr)?);
+		let mut parens = 0;
+		while first > 0
+			&& self.is(first - 1, &Tok::Lpar)
+			&& !self.is_call_paren(first - 1)
+			&& self.closing.get(&(first - 1)) == Some(&(last + 1))
+		{
+			first -= 1;
+			last += 1;
+			parens += 1;
+		}
+		Some((first, last, parens))
+	}
+
+	/// The index of the token that ends the logical line `index` is on.
+	pub fn end_of_line(&self, index: usize) -> usize {
+		(index..self.tokens.len())
+			.find(|&index| self.tokens[index].tok == Tok::Newline)
+			.unwrap_or(self.tokens.len() - 1)
+	}
+
+	/// The last row of the code between the tokens `first` and `until`.
+	pub fn last_row(&self, first: usize, until: Pos) -> usize {
+		self.tokens[first..]
+			.iter()
+			.take_while(|token| token.start < until)
+			.filter(|token| !matches!(token.tok, Tok::Newline | Tok::Indent | Tok::Dedent))
+			.map(|token| token.end.0)
+			.max()
+			.unwrap_or(self.tokens[first].start.0)
+	}
+
+	/// The first token of `statement`, which the parser doesn't always locate it at.
+	pub fn statement_start(&self, statement: &ast::Statement) -> Option<usize> {
+		match &statement.node {
+			ast::StatementType::Expression { expression } => Some(self.outer(expression)?.0),
+			ast::StatementType::Assign { targets, .. } => Some(self.outer(targets.first()?)?.0),
+			ast::StatementType::AugAssign { target, .. } => Some(self.outer(target)?.0),
+			_ => self.token_at(&statement.location),
+		}
+	}
+
+	/// Where the token at `index` starts in the source. The lexer starts strings right after their first quote.
+	pub fn start_of(&self, index: usize) -> Pos {
+		let Token { start, tok, .. } = &self.tokens[index];
+		if !matches!(tok, Tok::String { .. }) {
+			return *start;
+		}
+		let line = self.lines[start.0 - 1].chars().collect::<Vec<_>>();
+		let mut column = start.1 - 1;
+		while column > 1 && line[column - 2].is_ascii_alphabetic() {
+			column -= 1;
+		}
+		(start.0, column)
+	}
+}